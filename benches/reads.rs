@@ -0,0 +1,73 @@
+use std::{env, fs};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use simpleDB::engine::{engine::Engine, options::options::Options, ss_table::compression::Compression};
+
+const KEYS: u64 = 50_000;
+const LOOKUPS: u64 = 1_000;
+
+/// Database of `KEYS` keys with 100 byte values in one sstable, written once for every benchmark
+fn fill(dir: &str, compression: Compression) {
+    let _ = fs::remove_dir_all(dir);
+    let options = Options::builder().mem_table_size(64 * 1024 * 1024).compression(compression).build().unwrap();
+    let engine = Engine::open(dir.to_owned(), options).unwrap();
+    for i in 0..KEYS {
+        engine.set(key(i), vec![i as u8; 100]).unwrap();
+    }
+    engine.close().unwrap();
+}
+
+fn key(i: u64) -> Vec<u8> {
+    format!("key-{:08}", i).into_bytes()
+}
+
+/// Same keys in the same order for every reader, spread over the whole table
+fn lookups() -> Vec<Vec<u8>> {
+    let mut state = 42u64;
+    (0..LOOKUPS)
+        .map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            key((state >> 33) % KEYS)
+        })
+        .collect()
+}
+
+/// Point lookups read through pread in a buffer and through a mapping of the file,
+/// without the block cache so every lookup reads its block, then with it
+fn point_lookups(c: &mut Criterion) {
+    let lookups = lookups();
+    let mut group = c.benchmark_group("point_lookups");
+    group.throughput(Throughput::Elements(LOOKUPS));
+
+    for compression in [Compression::None, Compression::Lz4] {
+        let dir = env::temp_dir().join(format!("simpledb-bench-reads-{:?}", compression));
+        let dir = dir.to_str().unwrap().to_owned();
+        fill(&dir, compression);
+
+        for (block_cache_size, cache) in [(0, "no-cache"), (64 * 1024 * 1024, "cache")] {
+            for (allow_mmap_reads, reader) in [(false, "pread"), (true, "mmap")] {
+                let options = Options::builder()
+                    .create_if_missing(false)
+                    .block_cache_size(block_cache_size)
+                    .allow_mmap_reads(allow_mmap_reads)
+                    .build()
+                    .unwrap();
+                let engine = Engine::open(dir.clone(), options).unwrap();
+                let id = BenchmarkId::new(format!("{:?}/{}", compression, cache), reader);
+                group.bench_with_input(id, &lookups, |b, lookups| {
+                    b.iter(|| {
+                        for key in lookups {
+                            black_box(engine.get(key.clone()).unwrap().unwrap());
+                        }
+                    })
+                });
+                engine.close().unwrap();
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    group.finish();
+}
+
+criterion_group!(benches, point_lookups);
+criterion_main!(benches);
@@ -1,13 +1,11 @@
-use core::time;
-use std::{cell::RefCell, str::from_utf8};
-
-use simpleDB::engine::{engine::Engine, mem_table::red_black_tree::red_black_tree::{RedBlackTree, Status}};
+use simpleDB::engine::{db::db::Db, mem_table::red_black_tree::red_black_tree::{RedBlackTree, Status}};
 
 fn main() {
-    let engine = RefCell::new(Engine::new(
+    let _db = Db::open(
         "/home/adarsh/my_files/personal/lsm-database-engine/sstable".to_owned(), 
-        200
-    ));
+        200,
+        2
+    );
     // eprintln!("Enigner result {:?}",engine);
     // println!("{}","hello".as_bytes().to_vec().len() + "world".as_bytes().to_vec().len());
     
@@ -63,7 +61,8 @@ fn main() {
 
     // let mut engine = Engine::new(
     //     "/home/adarsh/my_files/personal/lsm-database-engine/sstable".to_owned(), 
-    //     200
+    //     200,
+    //     2
     // ).unwrap();
     
     // for i in 1..60 {
@@ -84,7 +83,7 @@ fn main() {
     println!("d");
     let mut iter = rb.root.into_iter();
     print!("s");
-    for i in 1..60 {
+    for _ in 1..60 {
         println!("insi");
         let x = iter.next().unwrap();
         let key = x.key().unwrap();
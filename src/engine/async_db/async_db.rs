@@ -0,0 +1,149 @@
+use std::{
+    ops::{Bound, RangeBounds},
+    sync::Arc,
+};
+
+use super::thread_pool::ThreadPool;
+use crate::engine::{db::db::Db, write_batch::write_batch::WriteBatch};
+use crate::Result;
+
+/// Async façade over `Db`, the blocking calls run in a dedicated thread pool
+///
+/// The futures don't depend on any runtime, they are woken from the pool threads
+///
+/// Cancellation: an operation is handed to the pool the first time its future is polled,
+/// from then on it runs to the end even if the future is dropped,
+/// a dropped `set` may or may not be applied, like a request lost on the network
+/// A future dropped before its first poll does nothing
+#[derive(Debug, Clone)]
+pub struct AsyncDb {
+    db: Db,
+    pool: Arc<ThreadPool>,
+}
+
+impl AsyncDb {
+    pub fn new(db: Db, threads: usize) -> Result<Self> {
+        Ok(Self {
+            db,
+            pool: Arc::new(ThreadPool::new(threads)?),
+        })
+    }
+
+    /// Blocking handle to the same database
+    pub fn db(&self) -> &Db {
+        &self.db
+    }
+
+    pub async fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let db = self.db.clone();
+        self.pool.spawn(move || db.get(key)).await
+    }
+
+    pub async fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        let db = self.db.clone();
+        self.pool.spawn(move || db.set(key, value)).await
+    }
+
+    pub async fn delete(&self, key: Vec<u8>) -> Result<()> {
+        let db = self.db.clone();
+        self.pool.spawn(move || db.delete(key)).await
+    }
+
+    pub async fn write(&self, batch: WriteBatch) -> Result<()> {
+        let db = self.db.clone();
+        self.pool.spawn(move || db.write(batch)).await
+    }
+
+    pub async fn scan<R: RangeBounds<Vec<u8>>>(&self, range: R) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let range: (Bound<Vec<u8>>, Bound<Vec<u8>>) = (range.start_bound().cloned(), range.end_bound().cloned());
+        let db = self.db.clone();
+        self.pool.spawn(move || db.scan(range)).await
+    }
+
+    pub async fn flush(&self) -> Result<()> {
+        let db = self.db.clone();
+        self.pool.spawn(move || db.flush()).await
+    }
+
+    pub async fn compact(&self) -> Result<()> {
+        let db = self.db.clone();
+        self.pool.spawn(move || db.compact()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        future::Future,
+        pin::pin,
+        task::{Context, Poll, Wake, Waker},
+        thread::{self, Thread},
+    };
+
+    /// Minimal executor, parks the thread till the future is woken from a pool thread
+    struct ThreadWaker {
+        thread: Thread,
+    }
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.thread.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let waker = Waker::from(Arc::new(ThreadWaker { thread: thread::current() }));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    fn test_db(name: &str, threads: usize) -> AsyncDb {
+        let dir = std::env::temp_dir().join(format!("simpledb-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let db = Db::open(dir.to_str().unwrap().to_owned(), 1024, 2).unwrap();
+        AsyncDb::new(db, threads).unwrap()
+    }
+
+    #[test]
+    fn async_operations() {
+        let db = test_db("async-operations", 4);
+        block_on(async {
+            for i in 0..50u8 {
+                db.set(vec![i], vec![i]).await.unwrap();
+            }
+            db.delete(vec![0]).await.unwrap();
+            db.flush().await.unwrap();
+
+            assert_eq!(db.get(vec![1]).await.unwrap(), Some(vec![1]));
+            assert_eq!(db.get(vec![0]).await.unwrap(), None);
+            assert_eq!(db.scan(vec![0]..vec![5]).await.unwrap().len(), 4);
+        });
+    }
+
+    #[test]
+    fn dropped_future_still_runs() {
+        // one thread, the jobs run in the order they were queued
+        let db = test_db("async-dropped-future-still-runs", 1);
+
+        // never polled, never queued
+        drop(db.set(vec![1], vec![1]));
+
+        let waker = Waker::from(Arc::new(ThreadWaker { thread: thread::current() }));
+        let mut cx = Context::from_waker(&waker);
+        {
+            let mut set = pin!(db.set(vec![2], vec![2]));
+            // queued by the first poll, then cancelled
+            let _ = set.as_mut().poll(&mut cx);
+        }
+
+        assert_eq!(block_on(db.get(vec![1])).unwrap(), None);
+        assert_eq!(block_on(db.get(vec![2])).unwrap(), Some(vec![2]));
+    }
+}
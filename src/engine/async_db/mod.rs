@@ -0,0 +1,2 @@
+pub mod async_db;
+pub mod thread_pool;
@@ -0,0 +1,174 @@
+use std::{
+    future::Future,
+    io,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{
+        mpsc::{self, Sender},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    thread::{self, JoinHandle},
+};
+
+use crate::{error::error::Busy, Error, Result};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Fixed number of threads running blocking jobs, see `AsyncDb`
+#[derive(Debug)]
+pub struct ThreadPool {
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+    pub fn new(size: usize) -> Result<Self> {
+        if size == 0 {
+            return Err(Error::InvalidArgument("thread pool needs at least one thread".to_owned()));
+        }
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let mut workers = vec![];
+        for i in 0..size {
+            let receiver = receiver.clone();
+            let worker = thread::Builder::new()
+                .name(format!("simpledb-io-{}", i))
+                .spawn(move || loop {
+                    // the lock is released before the job runs
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        // the pool is dropped
+                        Err(_) => return,
+                    }
+                })?;
+            workers.push(worker);
+        }
+
+        Ok(Self {
+            sender: Some(sender),
+            workers,
+        })
+    }
+
+    /// Queues the job and returns a future resolving to its result
+    ///
+    /// The job runs whether the future is polled or not, dropping the future only drops the result
+    pub fn spawn<T, F>(&self, job: F) -> TaskHandle<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T> + Send + 'static,
+    {
+        let state = Arc::new(Mutex::new(TaskState::default()));
+        let task_state = state.clone();
+        let job: Job = Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(job))
+                .unwrap_or_else(|_| Err(io::Error::other("task panicked").into()));
+            TaskState::complete(&task_state, result);
+        });
+
+        if self.sender.as_ref().unwrap().send(job).is_err() {
+            TaskState::complete(&state, Err(Busy::Stopped("thread pool has stopped".to_owned()).into()));
+        }
+        TaskHandle { state }
+    }
+}
+
+impl Drop for ThreadPool {
+    /// Runs the queued jobs before returning
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+struct TaskState<T> {
+    result: Option<Result<T>>,
+    waker: Option<Waker>,
+}
+
+impl<T> Default for TaskState<T> {
+    fn default() -> Self {
+        Self {
+            result: None,
+            waker: None,
+        }
+    }
+}
+
+impl<T> TaskState<T> {
+    /// Stores the result, the task is woken after the lock is released so it can poll right away
+    fn complete(state: &Mutex<Self>, result: Result<T>) {
+        let waker = {
+            let mut state = state.lock().unwrap();
+            state.result = Some(result);
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Result of a job running in the thread pool
+pub struct TaskHandle<T> {
+    state: Arc<Mutex<TaskState<T>>>,
+}
+
+impl<T> Future for TaskHandle<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                // the task may have moved to another executor thread since the last poll
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::Wake;
+
+    /// Tells whether the state of the task was unlocked when it was woken
+    struct LockingWaker {
+        state: Arc<Mutex<TaskState<u8>>>,
+        unlocked: Mutex<Sender<bool>>,
+    }
+
+    impl Wake for LockingWaker {
+        fn wake(self: Arc<Self>) {
+            let unlocked = self.state.try_lock().is_ok();
+            self.unlocked.lock().unwrap().send(unlocked).unwrap();
+        }
+    }
+
+    #[test]
+    fn wakes_outside_the_lock() {
+        let pool = ThreadPool::new(1).unwrap();
+        let (start, started) = mpsc::channel::<()>();
+        let mut handle = pool.spawn(move || {
+            started.recv().unwrap();
+            Ok(1u8)
+        });
+        let (sender, unlocked) = mpsc::channel();
+        let waker = Waker::from(Arc::new(LockingWaker { state: handle.state.clone(), unlocked: Mutex::new(sender) }));
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut handle).poll(&mut cx).is_pending());
+
+        start.send(()).unwrap();
+        // a woken task polling right away doesn't wait for the worker
+        assert!(unlocked.recv().unwrap());
+        assert!(matches!(Pin::new(&mut handle).poll(&mut cx), Poll::Ready(Ok(1))));
+    }
+}
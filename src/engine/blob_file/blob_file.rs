@@ -0,0 +1,233 @@
+use crate::{
+    engine::checksum::checksum::checksum,
+    error::error::Corruption,
+    Result,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Length(u64) and CRC32C(u32) of the record in front of it, little endian like the log
+const RECORD_HEADER_SIZE: usize = 8 + 4;
+
+/// Where a value moved out of its sstable lives, see `SSTable::separate_values()`
+#[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq,Eq)]
+pub struct BlobIndex {
+    // timestamp of the blob file
+    pub file: u128,
+    pub offset: u64,
+    // of the whole record, header included, so the live records of a file add up to its size
+    pub size: u64,
+}
+
+/// The key is stored along with the value, so a record read through a wrong index is caught
+#[derive(Serialize,Deserialize)]
+struct BlobRecord {
+    key: Vec<u8>,
+    value: Vec<u8>,
+}
+
+/// Appends the large values of one flush or compaction to a new blob file
+///
+/// Written in a temporary file first and renamed once it is synced, like the sstables,
+/// the file must be finished before the table pointing in it is written
+pub struct BlobWriter {
+    file_name: PathBuf,
+    temp_file_name: PathBuf,
+    file: BufWriter<File>,
+    timestamp: u128,
+    offset: u64,
+}
+
+impl BlobWriter {
+    pub fn create(dir: &Path, timestamp: u128) -> Result<Self> {
+        let file_name = BlobFile::file_name(dir, timestamp);
+        let temp_file_name = file_name.with_extension("blob.tmp");
+        let file = OpenOptions::new().write(true).create(true).truncate(true).open(&temp_file_name)?;
+        Ok(Self {
+            file_name,
+            temp_file_name,
+            file: BufWriter::new(file),
+            timestamp,
+            offset: 0,
+        })
+    }
+
+    pub fn append(&mut self, key: &[u8], value: &[u8]) -> Result<BlobIndex> {
+        let record = bincode::serialize(&BlobRecord { key: key.to_vec(), value: value.to_vec() })?;
+        self.file.write_all(&(record.len() as u64).to_le_bytes())?;
+        self.file.write_all(&checksum(&record).to_le_bytes())?;
+        self.file.write_all(&record)?;
+
+        let index = BlobIndex {
+            file: self.timestamp,
+            offset: self.offset,
+            size: (RECORD_HEADER_SIZE + record.len()) as u64,
+        };
+        self.offset += index.size;
+        Ok(index)
+    }
+
+    /// Syncs the file and renames it in place, returns its name
+    pub fn finish(mut self) -> Result<PathBuf> {
+        self.file.flush()?;
+        self.file.get_ref().sync_all()?;
+        fs::rename(&self.temp_file_name, &self.file_name)?;
+        Ok(self.file_name)
+    }
+}
+
+/// Blob file in the list of live blob files of a column family, readers hold it while they read it
+///
+/// Garbage collection only marks the files whose values it moved obsolete,
+/// the file is removed once the last reader is done with it, same as `SSTableFile`
+#[derive(Debug)]
+pub struct BlobFile {
+    file_name: PathBuf,
+    timestamp: u128,
+    // read with pread, kept open as long as the file is live
+    file: File,
+    size: u64,
+    obsolete: AtomicBool,
+}
+
+impl BlobFile {
+    pub fn open(file_name: PathBuf) -> Result<Self> {
+        let file = File::open(&file_name)?;
+        let size = file.metadata()?.len();
+        let timestamp = Self::file_timestamp(&file_name)
+            .ok_or_else(|| Corruption::new(&file_name, 0, "not a blob file name"))?;
+        Ok(Self {
+            file_name,
+            timestamp,
+            file,
+            size,
+            obsolete: AtomicBool::new(false),
+        })
+    }
+
+    /// Name of the blob file, same timestamp as the table written along with it
+    pub fn file_name(dir: &Path, timestamp: u128) -> PathBuf {
+        dir.join(timestamp.to_string() + ".blob")
+    }
+
+    /// Timestamp of the blob file, None if it is not a blob file
+    pub fn file_timestamp(file_name: &Path) -> Option<u128> {
+        if file_name.extension()? != "blob" {
+            return None;
+        }
+        file_name.file_stem()?.to_str()?.parse().ok()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.file_name
+    }
+
+    pub fn timestamp(&self) -> u128 {
+        self.timestamp
+    }
+
+    /// Bytes of every record, live or not
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Value of the record at `index`, which must have been written for `key`
+    ///
+    /// Fails with `Corruption` if the record doesn't decode or belongs to another key,
+    /// or with `verify_checksums` if its checksum doesn't match
+    pub fn read(&self, key: &[u8], index: &BlobIndex, verify_checksums: bool) -> Result<Vec<u8>> {
+        let corruption = |reason: &str| Corruption::new(&self.file_name, index.offset, reason);
+        let Some(record_size) = (index.size as usize).checked_sub(RECORD_HEADER_SIZE) else {
+            return Err(corruption("blob index smaller than a record header").into());
+        };
+        let mut data = vec![0; index.size as usize];
+        self.file.read_exact_at(&mut data, index.offset).map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => corruption("blob record past the end of the file").into(),
+            _ => crate::Error::from(err),
+        })?;
+
+        let length = u64::from_le_bytes(data[..8].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(data[8..RECORD_HEADER_SIZE].try_into().unwrap());
+        let record = &data[RECORD_HEADER_SIZE..];
+        if length != record_size {
+            return Err(corruption("blob record length doesn't match its index").into());
+        }
+        if verify_checksums && checksum(record) != crc {
+            return Err(corruption("blob record checksum mismatch").into());
+        }
+        let record: BlobRecord = bincode::deserialize(record).map_err(|err| corruption(&err.to_string()))?;
+        if record.key != key {
+            return Err(corruption("blob record belongs to another key").into());
+        }
+        Ok(record.value)
+    }
+
+    pub fn mark_obsolete(&self) {
+        self.obsolete.store(true, Ordering::Release);
+    }
+}
+
+impl Drop for BlobFile {
+    fn drop(&mut self) {
+        if self.obsolete.load(Ordering::Acquire) {
+            let _ = fs::remove_file(&self.file_name);
+        }
+    }
+}
+
+/// Blob files and what the garbage collection did with them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlobStats {
+    // live blob files of every column family
+    pub file_count: usize,
+    pub file_size: u64,
+    // values moved by compaction out of the files with too much garbage, since the engine was opened
+    pub relocated_values: u64,
+    // size of the blob files removed since the engine was opened
+    pub reclaimed_bytes: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    #[test]
+    fn append_and_read() {
+        let dir = std::env::temp_dir().join(format!("simpledb-blob-file-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut writer = BlobWriter::create(&dir, 9).unwrap();
+        let indexes: Vec<BlobIndex> =
+            (0..10u8).map(|i| writer.append(&[i], &vec![i; 1000 + i as usize]).unwrap()).collect();
+        let file_name = writer.finish().unwrap();
+        assert_eq!(BlobFile::file_timestamp(&file_name), Some(9));
+
+        let blob_file = BlobFile::open(file_name.clone()).unwrap();
+        assert_eq!(blob_file.size(), indexes.iter().map(|index| index.size).sum::<u64>());
+        assert_eq!(blob_file.read(&[3], &indexes[3], true).unwrap(), vec![3; 1003]);
+        let err = blob_file.read(&[4], &indexes[3], true).unwrap_err();
+        assert!(matches!(err, Error::Corruption(corruption) if corruption.offset == indexes[3].offset));
+
+        // a flipped bit in the value
+        let mut content = fs::read(&file_name).unwrap();
+        content[indexes[5].offset as usize + 100] ^= 1;
+        fs::write(&file_name, &content).unwrap();
+        let blob_file = BlobFile::open(file_name.clone()).unwrap();
+        assert!(matches!(blob_file.read(&[5], &indexes[5], true), Err(Error::Corruption(_))));
+        assert_eq!(blob_file.read(&[6], &indexes[6], true).unwrap(), vec![6; 1006]);
+
+        blob_file.mark_obsolete();
+        drop(blob_file);
+        assert!(!file_name.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
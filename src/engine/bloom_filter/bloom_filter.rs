@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+
+/// Set of keys which may give false positives but never false negatives
+///
+/// Every key sets `hash_count` bits, picked by double hashing one 64 bit hash of the key,
+/// about 1% false positives with 10 bits per key
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    hash_count: u32,
+}
+
+impl BloomFilter {
+    pub fn new(keys: &[&[u8]], bits_per_key: usize) -> Self {
+        // ln 2 * bits per key is the best number of hashes
+        let hash_count = ((bits_per_key as f64 * 0.69) as u32).clamp(1, 30);
+        // a few keys would get a very high false positive rate with a tiny filter
+        let bit_count = (keys.len() * bits_per_key).max(64);
+        let mut filter = Self {
+            bits: vec![0; bit_count.div_ceil(8)],
+            hash_count,
+        };
+        for key in keys {
+            for bit in filter.bit_positions(key) {
+                filter.bits[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        filter
+    }
+
+    /// False only if the key was never added
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        if self.bits.is_empty() {
+            return true;
+        }
+        self.bit_positions(key).all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    fn bit_positions(&self, key: &[u8]) -> impl Iterator<Item = usize> {
+        let hash = hash(key);
+        let bit_count = self.bits.len() as u64 * 8;
+        let (h1, h2) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+        (0..self.hash_count as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bit_count) as usize)
+    }
+}
+
+/// FNV-1a, stored filters depend on it so it must never change
+fn hash(key: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for byte in key {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// Lookups of the sstables which asked a filter, summed since the engine was opened
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FilterStats {
+    pub checked: u64,
+    // the filter said the key or prefix is not in the table, so it wasn't read
+    pub useful: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_false_negatives_few_false_positives() {
+        let keys: Vec<Vec<u8>> = (0..10_000u32).map(|i| format!("key-{}", i).into_bytes()).collect();
+        let key_refs: Vec<&[u8]> = keys.iter().map(|key| key.as_slice()).collect();
+        let filter = BloomFilter::new(&key_refs, 10);
+        assert!(keys.iter().all(|key| filter.may_contain(key)));
+
+        let false_positives =
+            (10_000..20_000u32).filter(|i| filter.may_contain(format!("key-{}", i).as_bytes())).count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+
+        let empty = BloomFilter::new(&[], 10);
+        assert!(!empty.may_contain(b"key-1"));
+    }
+}
@@ -0,0 +1,4 @@
+/// CRC32C of the data, stored next to every sstable block, sstable footer and log record
+pub fn checksum(data: &[u8]) -> u32 {
+    crc32c::crc32c(data)
+}
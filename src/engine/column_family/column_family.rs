@@ -0,0 +1,109 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    engine::{
+        compaction::compaction_filter::CompactionFilter,
+        merge_operator::merge_operator::MergeOperator,
+        ss_table::{compression::Compression, ss_table::TableOptions},
+    },
+    Error, Result,
+};
+
+pub const DEFAULT_COLUMN_FAMILY: &str = "default";
+
+/// Handle to one keyspace of the database, see `Engine::create_column_family()`
+///
+/// Every column family has its own memtable and sstables, they share the log,
+/// so a batch writing to several of them is still atomic
+/// The plain engine methods use the default column family
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnFamily {
+    id: u32,
+    name: String,
+}
+
+impl ColumnFamily {
+    pub fn new(id: u32, name: &str) -> Self {
+        Self {
+            id,
+            name: name.to_owned(),
+        }
+    }
+
+    pub fn default_family() -> Self {
+        Self::new(0, DEFAULT_COLUMN_FAMILY)
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Directory of the sstables, the default column family keeps them in the database directory
+    pub fn dir(root: &Path, id: u32) -> PathBuf {
+        match id {
+            0 => root.to_path_buf(),
+            id => root.join(format!("cf-{}", id)),
+        }
+    }
+
+    /// Id of a column family directory, None if it is not one
+    pub fn dir_id(dir: &Path) -> Option<u32> {
+        dir.file_name()?.to_str()?.strip_prefix("cf-")?.parse().ok()
+    }
+}
+
+/// Settings of one column family, the ones left to None follow the settings of the engine
+///
+/// The memtable and sstable settings are stored in the manifest next to the name and come back on open,
+/// the merge operator and the compaction filters can't be,
+/// set them again with `Engine::set_column_family_options()` after opening the database
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ColumnFamilyOptions {
+    // bytes the memtable of the column family holds before the memtables are frozen
+    pub mem_table_size: Option<usize>,
+    // codec of the tables of every level
+    pub compression: Option<Compression>,
+    pub block_size: Option<usize>,
+    pub bloom_bits_per_key: Option<usize>,
+    #[serde(skip)]
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    // run in order by every compaction of the column family
+    #[serde(skip)]
+    pub compaction_filters: Vec<Arc<dyn CompactionFilter>>,
+}
+
+impl ColumnFamilyOptions {
+    /// Fails with `Error::InvalidArgument` naming the first setting out of range
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |reason: &str| Err(Error::InvalidArgument(reason.to_owned()));
+        if self.mem_table_size == Some(0) {
+            return invalid("mem_table_size must be at least 1");
+        }
+        if self.block_size == Some(0) {
+            return invalid("block_size must be at least 1");
+        }
+        if self.bloom_bits_per_key.is_some_and(|bits| bits > 64) {
+            return invalid("bloom_bits_per_key must be at most 64");
+        }
+        Ok(())
+    }
+
+    /// Layout of the engine with the settings of the column family on top
+    pub fn table_options(&self, table_options: TableOptions) -> TableOptions {
+        TableOptions {
+            compression: self.compression.unwrap_or(table_options.compression),
+            block_size: self.block_size.unwrap_or(table_options.block_size),
+            bloom_bits_per_key: self.bloom_bits_per_key.unwrap_or(table_options.bloom_bits_per_key),
+            ..table_options
+        }
+    }
+}
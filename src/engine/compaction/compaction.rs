@@ -0,0 +1,321 @@
+use crate::engine::{
+    comparator::comparator::{BytewiseComparator, Comparator},
+    merge_operator::merge_operator::{fold_versions, MergeOperator},
+    ss_table::ss_table::{RangeTombstone, SSTableEntry},
+};
+use super::compaction_filter::{CompactionFilter, CompactionFilterStats, FilterDecision};
+use std::{cmp::Reverse, iter::Peekable, sync::Arc};
+
+/*
+* Compaction merges sstables and keeps only the versions someone can still read.
+* Versions of a key come newest first, the newest one is read by everyone who starts now,
+* an older version is read by a snapshot pinned in between it and the next newer version,
+* or by a time-travel read inside the retention window, see `Engine::get_as_of()`.
+* A kept merge operand is replaced by the value folded at its timestamp,
+* an expired value by a TombStone, so the key keeps hiding its older versions.
+* A range tombstone acts as a TombStone of every key it covers while the versions are picked,
+* and is kept as long as one of the versions it hides is.
+*/
+
+/// Settings of one compaction, see `retain_visible_versions()`
+pub struct Compaction<'a> {
+    // timestamps of the live snapshots, sorted
+    pub snapshots: &'a [u128],
+    // oldest timestamp time-travel reads may ask for, `u128::MAX` keeps no history
+    pub history_start: u128,
+    // no older version of any key lives outside of the compacted run,
+    // then the TombStones at the bottom of a key hide nothing and are dropped as well
+    pub bottommost: bool,
+    // without it the versions of a key with merge operands are all kept,
+    // with it or with filters the values in blob files must be read first
+    pub merge_operator: Option<&'a dyn MergeOperator>,
+    // run in order on the values no snapshot reads
+    pub filters: &'a [Arc<dyn CompactionFilter>],
+    // one per filter, filled by `retain_visible_versions()`
+    pub filter_stats: Vec<CompactionFilterStats>,
+    // of every compacted table
+    pub range_tombstones: &'a [RangeTombstone],
+    // order of the keys in the run
+    pub comparator: &'a dyn Comparator,
+}
+
+impl<'a> Compaction<'a> {
+    pub fn new(snapshots: &'a [u128], bottommost: bool) -> Self {
+        Self {
+            snapshots,
+            history_start: u128::MAX,
+            bottommost,
+            merge_operator: None,
+            filters: &[],
+            filter_stats: vec![],
+            range_tombstones: &[],
+            comparator: &BytewiseComparator,
+        }
+    }
+
+    /// Drops the versions no reader can see anymore from a merged run, see `MergeIterator`
+    pub fn retain_visible_versions<I: Iterator<Item = SSTableEntry>>(&mut self, entries: I) -> Vec<SSTableEntry> {
+        self.filter_stats = self.filters.iter().map(|filter| CompactionFilterStats::new(filter.name())).collect();
+        let mut entries = entries.peekable();
+        let mut retained = vec![];
+
+        while let Some(newest) = entries.next() {
+            let mut versions = versions_of_key(newest, &mut entries);
+            let start = retained.len();
+
+            let has_operands = versions.iter().any(|version| version.merge);
+            if has_operands && self.merge_operator.is_none() {
+                retained.extend(versions);
+                continue;
+            }
+
+            // TombStones standing for the range tombstones, timestamps are unique so they are told apart by it
+            let range_deleted_at: Vec<u128> = self
+                .range_tombstones
+                .iter()
+                .filter(|tombstone| tombstone.contains(self.comparator, &versions[0].key))
+                .map(|tombstone| tombstone.timestamp)
+                .collect();
+            if !range_deleted_at.is_empty() {
+                let key = versions[0].key.clone();
+                for &timestamp in &range_deleted_at {
+                    versions.push(SSTableEntry::new(key.clone(), vec![], timestamp, true));
+                }
+                versions.sort_by_key(|version| Reverse(version.timestamp));
+            }
+
+            let mut newer_timestamp = u128::MAX;
+            for (i, version) in versions.iter().enumerate() {
+                let timestamp = version.timestamp;
+                // the version is valid till the newer one was written
+                let in_history = newer_timestamp > self.history_start;
+                let read_by_snapshot = read_by_snapshot(self.snapshots, timestamp, newer_timestamp);
+                if i == 0 || in_history || read_by_snapshot {
+                    let mut version = match self.merge_operator {
+                        Some(merge_operator) if version.merge => fold_versions(merge_operator, &versions[i..]),
+                        _ => version.clone(),
+                    };
+                    if version.is_expired() {
+                        version = SSTableEntry::new(version.key, vec![], version.timestamp, true);
+                    } else if !version.deleted && !read_by_snapshot {
+                        version = self.filter(version);
+                    }
+                    retained.push(version);
+                }
+                newer_timestamp = timestamp;
+            }
+
+            if self.bottommost {
+                while retained.len() > start && retained.last().unwrap().deleted {
+                    retained.pop();
+                }
+            }
+            if !range_deleted_at.is_empty() {
+                let kept: Vec<SSTableEntry> = retained
+                    .drain(start..)
+                    .filter(|version| !(version.deleted && range_deleted_at.contains(&version.timestamp)))
+                    .collect();
+                retained.extend(kept);
+            }
+        }
+        retained
+    }
+
+    /// Range tombstones still hiding one of the retained versions,
+    /// every one of them when older versions may live elsewhere
+    pub fn live_range_tombstones(&self, retained: &[SSTableEntry]) -> Vec<RangeTombstone> {
+        self.range_tombstones
+            .iter()
+            .filter(|tombstone| {
+                let start =
+                    retained.partition_point(|entry| self.comparator.compare(&entry.key, &tombstone.start).is_lt());
+                !self.bottommost
+                    || retained[start..]
+                        .iter()
+                        .take_while(|entry| tombstone.contains(self.comparator, &entry.key))
+                        .any(|entry| entry.timestamp < tombstone.timestamp)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Runs the filters on a value, a removed value becomes a TombStone
+    fn filter(&mut self, mut version: SSTableEntry) -> SSTableEntry {
+        for (filter, stats) in self.filters.iter().zip(self.filter_stats.iter_mut()) {
+            stats.examined += 1;
+            match filter.filter(&version.key, &version.value) {
+                FilterDecision::Keep => {}
+                FilterDecision::Remove => {
+                    stats.removed += 1;
+                    return SSTableEntry::new(version.key, vec![], version.timestamp, true);
+                }
+                FilterDecision::ChangeValue(value) => {
+                    stats.changed += 1;
+                    version.value = value;
+                    // no longer the value in the blob file
+                    version.blob = None;
+                }
+            }
+        }
+        version
+    }
+}
+
+/// The newest version followed by every older version of the same key
+fn versions_of_key<I: Iterator<Item = SSTableEntry>>(
+    newest: SSTableEntry,
+    entries: &mut Peekable<I>
+) -> Vec<SSTableEntry> {
+    let mut versions = vec![];
+    while let Some(older) = entries.next_if(|older| older.key == newest.key) {
+        versions.push(older);
+    }
+    versions.insert(0, newest);
+    versions
+}
+
+/// Is there a snapshot in `[timestamp, newer_timestamp)`
+fn read_by_snapshot(snapshots: &[u128], timestamp: u128, newer_timestamp: u128) -> bool {
+    let index = snapshots.partition_point(|snapshot| *snapshot < timestamp);
+    index < snapshots.len() && snapshots[index] < newer_timestamp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: u8, timestamp: u128, deleted: bool) -> SSTableEntry {
+        SSTableEntry::new(vec![key], vec![], timestamp, deleted)
+    }
+
+    fn retained(entries: Vec<SSTableEntry>, snapshots: &[u128], bottommost: bool) -> Vec<(u8, u128)> {
+        Compaction::new(snapshots, bottommost)
+            .retain_visible_versions(entries.into_iter())
+            .iter()
+            .map(|entry| (entry.key[0], entry.timestamp))
+            .collect()
+    }
+
+    #[test]
+    fn only_newest_without_snapshots() {
+        let entries = vec![entry(1, 9, false), entry(1, 5, false), entry(2, 7, true), entry(2, 3, false), entry(3, 4, true)];
+        assert_eq!(retained(entries.clone(), &[], true), vec![(1, 9)]);
+        // TombStones must stay if older versions may live elsewhere
+        assert_eq!(retained(entries, &[], false), vec![(1, 9), (2, 7), (3, 4)]);
+    }
+
+    #[test]
+    fn versions_read_by_snapshots() {
+        let entries = vec![entry(1, 9, false), entry(1, 5, false), entry(1, 2, false), entry(2, 7, true), entry(2, 3, false)];
+        // 6 reads 1@5 and 2@3, 8 reads 1@5 and 2@7
+        assert_eq!(retained(entries.clone(), &[6, 8], true), vec![(1, 9), (1, 5), (2, 7), (2, 3)]);
+        // 1 is older than everything, 2 reads 1@2
+        assert_eq!(retained(entries.clone(), &[1, 2], true), vec![(1, 9), (1, 2)]);
+        // 9 reads only the newest versions
+        assert_eq!(retained(entries, &[9], true), vec![(1, 9)]);
+    }
+
+    #[test]
+    fn versions_inside_history() {
+        let entries = vec![entry(1, 9, false), entry(1, 5, false), entry(1, 2, false), entry(2, 7, true), entry(2, 3, false)];
+        // reads from 6 on see 1@5 and both versions of 2
+        let mut compaction = Compaction::new(&[], true);
+        compaction.history_start = 6;
+        let kept: Vec<(u8, u128)> = compaction
+            .retain_visible_versions(entries.clone().into_iter())
+            .iter()
+            .map(|entry| (entry.key[0], entry.timestamp))
+            .collect();
+        assert_eq!(kept, vec![(1, 9), (1, 5), (2, 7), (2, 3)]);
+
+        compaction.history_start = 0;
+        assert_eq!(compaction.retain_visible_versions(entries.into_iter()).len(), 5);
+    }
+
+    #[test]
+    fn operands_folded() {
+        use crate::engine::merge_operator::merge_operator::U64AddOperator;
+        let add = |value: u64, timestamp| SSTableEntry::merge_operand(vec![1], value.to_le_bytes().to_vec(), timestamp);
+        let entries = vec![add(1, 9), add(2, 5), SSTableEntry::new(vec![1], 10u64.to_le_bytes().to_vec(), 2, false)];
+
+        let mut compaction = Compaction::new(&[6], true);
+        compaction.merge_operator = Some(&U64AddOperator);
+        let folded = compaction.retain_visible_versions(entries.clone().into_iter());
+        let folded: Vec<(u64, u128, bool)> = folded
+            .into_iter()
+            .map(|entry| (u64::from_le_bytes(entry.value.try_into().unwrap()), entry.timestamp, entry.merge))
+            .collect();
+        assert_eq!(folded, vec![(13, 9, false), (12, 5, false)]);
+
+        assert_eq!(Compaction::new(&[], true).retain_visible_versions(entries.into_iter()).len(), 3);
+    }
+
+    #[test]
+    fn expired_values_dropped() {
+        let entries = vec![
+            entry(1, 9, false).with_expiry(Some(1)),
+            entry(1, 5, false),
+            entry(2, 7, false).with_expiry(Some(u128::MAX)),
+        ];
+        assert_eq!(retained(entries.clone(), &[], true), vec![(2, 7)]);
+        // the TombStone keeps hiding 1@5 from the older tables
+        let kept = Compaction::new(&[], false).retain_visible_versions(entries.into_iter());
+        assert!(kept[0].deleted && kept[0].value.is_empty());
+        assert_eq!(kept.len(), 2);
+    }
+
+    #[test]
+    fn filters_skip_snapshot_reads() {
+        #[derive(Debug)]
+        struct DropOdd;
+        impl CompactionFilter for DropOdd {
+            fn name(&self) -> &str {
+                "drop_odd"
+            }
+            fn filter(&self, key: &[u8], _value: &[u8]) -> FilterDecision {
+                match key[0] % 2 {
+                    1 => FilterDecision::Remove,
+                    _ => FilterDecision::ChangeValue(vec![0]),
+                }
+            }
+        }
+
+        let entries = vec![entry(1, 9, false), entry(1, 5, false), entry(2, 7, false), entry(3, 4, true)];
+        let filters: Vec<Arc<dyn CompactionFilter>> = vec![Arc::new(DropOdd)];
+        let mut compaction = Compaction::new(&[6], true);
+        compaction.filters = &filters;
+        let kept = compaction.retain_visible_versions(entries.into_iter());
+
+        // 1@5 is read by the snapshot, so is left alone and the TombStone hides it from the others
+        let kept: Vec<(u8, u128, bool, Vec<u8>)> =
+            kept.into_iter().map(|entry| (entry.key[0], entry.timestamp, entry.deleted, entry.value)).collect();
+        assert_eq!(kept, vec![(1, 9, true, vec![]), (1, 5, false, vec![]), (2, 7, false, vec![0])]);
+        assert_eq!(
+            compaction.filter_stats,
+            vec![CompactionFilterStats { name: "drop_odd".to_owned(), examined: 2, removed: 1, changed: 1 }]
+        );
+    }
+
+    #[test]
+    fn range_tombstones_drop_covered_versions() {
+        let entries =
+            vec![entry(1, 9, false), entry(1, 5, false), entry(2, 3, false), entry(3, 2, false), entry(4, 1, false)];
+        let range_tombstones = vec![RangeTombstone::new(vec![1], vec![4], 6), RangeTombstone::new(vec![4], vec![5], 4)];
+
+        let mut compaction = Compaction::new(&[], true);
+        compaction.range_tombstones = &range_tombstones;
+        let kept = compaction.retain_visible_versions(entries.clone().into_iter());
+        assert_eq!(kept.iter().map(|entry| (entry.key[0], entry.timestamp)).collect::<Vec<_>>(), vec![(1, 9)]);
+        assert!(compaction.live_range_tombstones(&kept).is_empty());
+
+        // 5 reads 1@5, 2@3 and 3@2 but not 4
+        let mut compaction = Compaction::new(&[5], true);
+        compaction.range_tombstones = &range_tombstones;
+        let kept = compaction.retain_visible_versions(entries.into_iter());
+        assert_eq!(
+            kept.iter().map(|entry| (entry.key[0], entry.timestamp)).collect::<Vec<_>>(),
+            vec![(1, 9), (1, 5), (2, 3), (3, 2)]
+        );
+        assert_eq!(compaction.live_range_tombstones(&kept), vec![range_tombstones[0].clone()]);
+    }
+}
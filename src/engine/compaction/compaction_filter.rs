@@ -0,0 +1,51 @@
+use std::fmt;
+
+/// What a `CompactionFilter` does with a value
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterDecision {
+    Keep,
+    /// The key reads as deleted from then on
+    Remove,
+    ChangeValue(Vec<u8>),
+}
+
+/// Application rule run on the values during compaction, see `Engine::add_compaction_filter()`
+///
+/// Only sees the values no snapshot reads, never TombStones or merge operands,
+/// a merged key is seen once its operands are folded
+pub trait CompactionFilter: Send + Sync {
+    /// Key of the filter in `Engine::compaction_filter_stats()`
+    fn name(&self) -> &str;
+
+    fn filter(&self, key: &[u8], value: &[u8]) -> FilterDecision;
+}
+
+impl fmt::Debug for dyn CompactionFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CompactionFilter({})", self.name())
+    }
+}
+
+/// What a filter did, for one compaction or summed over every compaction
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompactionFilterStats {
+    pub name: String,
+    pub examined: u64,
+    pub removed: u64,
+    pub changed: u64,
+}
+
+impl CompactionFilterStats {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, other: &CompactionFilterStats) {
+        self.examined += other.examined;
+        self.removed += other.removed;
+        self.changed += other.changed;
+    }
+}
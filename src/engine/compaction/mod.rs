@@ -0,0 +1,2 @@
+pub mod compaction;
+pub mod compaction_filter;
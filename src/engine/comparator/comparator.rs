@@ -0,0 +1,129 @@
+use std::{cmp::Ordering, fmt, ops::Bound, sync::Arc};
+
+/// Order of the keys of a database, given to `Engine::with_comparator()` or `OptionsBuilder::comparator()`
+///
+/// The name is stored in the manifest, the database can't be opened with a comparator of another name
+/// Only equal keys may compare as `Equal`
+pub trait Comparator: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering;
+
+    /// A key in `[start, limit)`, as short as possible, index entries store it instead of `start`
+    fn find_shortest_separator(&self, start: &[u8], _limit: &[u8]) -> Vec<u8> {
+        start.to_vec()
+    }
+
+    /// Range holding every key starting with `prefix`, the whole keyspace unless the order tells
+    fn prefix_range(&self, _prefix: &[u8]) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+        (Bound::Unbounded, Bound::Unbounded)
+    }
+}
+
+/// Smallest key byte by byte above every key starting with `prefix`, None if there is none
+fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let last = prefix.iter().rposition(|byte| *byte < u8::MAX)?;
+    let mut successor = prefix[..=last].to_vec();
+    successor[last] += 1;
+    Some(successor)
+}
+
+impl fmt::Debug for dyn Comparator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Comparator({})", self.name())
+    }
+}
+
+/// Default one, the keys sorted byte by byte like `[u8]`
+#[derive(Debug, Default)]
+pub struct BytewiseComparator;
+
+impl Comparator for BytewiseComparator {
+    fn name(&self) -> &str {
+        "simpledb.bytewise"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        a.cmp(b)
+    }
+
+    /// Bumps the first byte `start` differs from `limit`, if it stays below `limit`
+    fn find_shortest_separator(&self, start: &[u8], limit: &[u8]) -> Vec<u8> {
+        let common = start.iter().zip(limit).take_while(|(a, b)| a == b).count();
+        // one is a prefix of the other
+        if common == start.len().min(limit.len()) {
+            return start.to_vec();
+        }
+        let byte = start[common];
+        if byte < u8::MAX && byte + 1 < limit[common] {
+            let mut separator = start[..=common].to_vec();
+            separator[common] += 1;
+            return separator;
+        }
+        start.to_vec()
+    }
+
+    fn prefix_range(&self, prefix: &[u8]) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+        let end = prefix_successor(prefix).map_or(Bound::Unbounded, Bound::Excluded);
+        (Bound::Included(prefix.to_vec()), end)
+    }
+}
+
+/// Largest key first
+#[derive(Debug, Default)]
+pub struct ReverseBytewiseComparator;
+
+impl Comparator for ReverseBytewiseComparator {
+    fn name(&self) -> &str {
+        "simpledb.reverse_bytewise"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        b.cmp(a)
+    }
+
+    fn prefix_range(&self, prefix: &[u8]) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+        let start = prefix_successor(prefix).map_or(Bound::Unbounded, Bound::Excluded);
+        (start, Bound::Included(prefix.to_vec()))
+    }
+}
+
+pub fn default_comparator() -> Arc<dyn Comparator> {
+    Arc::new(BytewiseComparator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortest_separator() {
+        let bytewise = BytewiseComparator;
+        assert_eq!(bytewise.find_shortest_separator(b"abcdef", b"abzz"), b"abd");
+        // c + 1 is the limit
+        assert_eq!(bytewise.find_shortest_separator(b"abcdef", b"abd"), b"abcdef");
+        assert_eq!(bytewise.find_shortest_separator(b"ab", b"abc"), b"ab");
+        assert_eq!(bytewise.find_shortest_separator(&[1, 255, 3], &[2, 0]), vec![1, 255, 3]);
+
+        let reverse = ReverseBytewiseComparator;
+        assert_eq!(reverse.compare(b"b", b"a"), Ordering::Less);
+        assert_eq!(reverse.find_shortest_separator(b"b", b"a"), b"b");
+    }
+
+    #[test]
+    fn prefix_ranges() {
+        assert_eq!(
+            BytewiseComparator.prefix_range(b"ab"),
+            (Bound::Included(b"ab".to_vec()), Bound::Excluded(b"ac".to_vec()))
+        );
+        assert_eq!(
+            BytewiseComparator.prefix_range(&[1, 255]),
+            (Bound::Included(vec![1, 255]), Bound::Excluded(vec![2]))
+        );
+        assert_eq!(BytewiseComparator.prefix_range(&[255]).1, Bound::Unbounded);
+        assert_eq!(
+            ReverseBytewiseComparator.prefix_range(b"ab"),
+            (Bound::Excluded(b"ac".to_vec()), Bound::Included(b"ab".to_vec()))
+        );
+    }
+}
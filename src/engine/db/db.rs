@@ -0,0 +1,150 @@
+use std::{ops::Deref, sync::Arc};
+
+use crate::engine::engine::Engine;
+use crate::{error::error::Busy, Result};
+
+/// Cloneable handle to one engine, clones can be moved to other threads
+///
+/// Every engine method is available through the handle,
+/// the engine is closed when the last handle is dropped
+#[derive(Debug, Clone)]
+pub struct Db {
+    engine: Arc<Engine>,
+}
+
+impl Db {
+    /// Opens the database in `storage_path`, see `Engine::new()`
+    pub fn open(
+        storage_path: String,
+        mem_table_size: usize,
+        max_immutable_mem_tables: usize
+    ) -> Result<Self> {
+        Ok(Self {
+            engine: Arc::new(Engine::new(storage_path, mem_table_size, max_immutable_mem_tables)?),
+        })
+    }
+
+    /// Closes the engine, see `Engine::close()`, fails while other handles are alive
+    pub fn close(self) -> Result<()> {
+        Arc::try_unwrap(self.engine)
+            .map_err(|_| Busy::InUse("database is still used by other handles".to_owned()))?
+            .close()
+    }
+}
+
+/// Shares an engine opened some other way, e.g. with `Engine::open()` and its options
+impl From<Engine> for Db {
+    fn from(engine: Engine) -> Self {
+        Self {
+            engine: Arc::new(engine),
+        }
+    }
+}
+
+impl Deref for Db {
+    type Target = Engine;
+
+    fn deref(&self) -> &Self::Target {
+        &self.engine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+    };
+
+    fn test_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("simpledb-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn concurrent_writers_and_readers() {
+        let dir = test_dir("db-concurrent-writers-and-readers");
+        let db = Db::open(dir.clone(), 4096, 2).unwrap();
+        db.set(b"fixed".to_vec(), b"value".to_vec()).unwrap();
+        let done = Arc::new(AtomicBool::new(false));
+
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let db = db.clone();
+                let done = done.clone();
+                thread::spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        assert_eq!(db.get(b"fixed".to_vec()).unwrap(), Some(b"value".to_vec()));
+                        db.scan(..).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        let writers: Vec<_> = (0..8u8)
+            .map(|writer| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    for i in 0..100u8 {
+                        db.set(vec![writer, i], vec![i]).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        // compaction while the readers go on
+        db.flush().unwrap();
+        db.compact().unwrap();
+        done.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert_eq!(db.scan(..).unwrap().len(), 801);
+        assert!(db.clone().close().is_err());
+        db.close().unwrap();
+
+        let db = Db::open(dir, 4096, 2).unwrap();
+        assert_eq!(db.get(vec![7, 99]).unwrap(), Some(vec![99]));
+    }
+
+    #[test]
+    fn snapshot_sees_whole_batches_of_other_threads() {
+        let db = Db::open(test_dir("db-snapshot-sees-whole-batches"), 1024, 2).unwrap();
+        let writer = {
+            let db = db.clone();
+            thread::spawn(move || {
+                for i in 0..200u32 {
+                    let mut batch = crate::engine::write_batch::write_batch::WriteBatch::new();
+                    batch.set(b"a".to_vec(), i.to_le_bytes().to_vec()).set(b"b".to_vec(), i.to_le_bytes().to_vec());
+                    db.write(batch).unwrap();
+                }
+            })
+        };
+        for _ in 0..200 {
+            let snapshot = db.snapshot();
+            assert_eq!(db.get_at(&snapshot, b"a".to_vec()).unwrap(), db.get_at(&snapshot, b"b".to_vec()).unwrap());
+        }
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn single_leader_elected() {
+        let db = Db::open(test_dir("db-single-leader-elected"), 1024, 2).unwrap();
+        let threads: Vec<_> = (0..8u8)
+            .map(|candidate| {
+                let db = db.clone();
+                thread::spawn(move || db.set_if_absent(b"leader".to_vec(), vec![candidate]).unwrap())
+            })
+            .collect();
+
+        let elected: Vec<bool> = threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+        assert_eq!(elected.iter().filter(|elected| **elected).count(), 1);
+        let leader = elected.iter().position(|elected| *elected).unwrap() as u8;
+        assert_eq!(db.get(b"leader".to_vec()).unwrap(), Some(vec![leader]));
+    }
+}
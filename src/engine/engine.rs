@@ -1,82 +1,2642 @@
-use std::{fs, path::PathBuf, time::{SystemTime, UNIX_EPOCH}};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fs::{self, File},
+    mem,
+    ops::RangeBounds,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Condvar, Mutex, RwLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use super::mem_table::mem_table::MemTable;
-use anyhow::{Ok, Result};
+use super::{
+    blob_file::blob_file::{BlobFile, BlobIndex, BlobStats},
+    bloom_filter::bloom_filter::FilterStats,
+    column_family::column_family::{ColumnFamily, ColumnFamilyOptions, DEFAULT_COLUMN_FAMILY},
+    comparator::comparator::{default_comparator, Comparator},
+    compaction::{
+        compaction::Compaction,
+        compaction_filter::{CompactionFilter, CompactionFilterStats},
+    },
+    manifest::manifest::Manifest,
+    mem_table::mem_table::MemTable,
+    merge_iterator::merge_iterator::{MergeIterator, VisibleIterator},
+    merge_operator::merge_operator::{fold_versions, MergeOperator},
+    options::options::{CompactionStyle, Options, WalSyncMode},
+    snapshot::snapshot::{Snapshot, SnapshotList},
+    ss_table::{
+        compression::{Compression, CompressionStats},
+        ss_table::{RangeTombstone, SSTable, SSTableEntry, SSTableFile, TableOptions, TableReader},
+        table_cache::TableCache,
+    },
+    lock_manager::lock_manager::LockManager,
+    lru_cache::lru_cache::CacheStats,
+    transaction::{
+        pessimistic_transaction::{IsolationLevel, PessimisticTransaction},
+        transaction::{Transaction, TransactionConflict},
+    },
+    wal::wal::{Wal, WalRecord},
+    write_batch::write_batch::{BatchEntry, WriteBatch},
+};
+use crate::{
+    error::error::{Busy, Corruption},
+    Error, Result,
+};
 
+/// Memtable of every column family by id, they share one log so they are frozen and flushed together
+type MemTables = BTreeMap<u32, MemTable>;
+
+/// Blob files of one column family by timestamp, see `BlobIndex::file`
+type BlobFiles = BTreeMap<u128, Arc<BlobFile>>;
+
+/// Immutable memtables, sstables and blob files of one column family, as a reader copies them out
+type TableLists = (VecDeque<Arc<MemTables>>, Vec<Arc<SSTableFile>>, BlobFiles);
+
+/// Memtables which are full, sent to the background thread
+struct FlushJob {
+    mem_tables: Arc<MemTables>,
+    // used for the sstable file names
+    timestamp: u128,
+    // log of the memtables, removed once the sstables are written
+    wal_file: PathBuf,
+}
+
+/// Sstables of one column family
+#[derive(Debug, Clone)]
+struct ColumnFamilyTables {
+    name: String,
+    // sstable files in the disk, oldest first, same as the manifest
+    ss_tables: Vec<Arc<SSTableFile>>,
+    // the large values the sstables point at, see `Options::min_blob_size`
+    blob_files: BlobFiles,
+}
+
+/// Everything `get` reads apart from the current memtables
+#[derive(Debug, Default)]
+struct Tables {
+    // full memtables waiting to be written in the disk, newest first
+    immutable_mem_tables: VecDeque<Arc<MemTables>>,
+    // live column families by id
+    column_families: BTreeMap<u32, ColumnFamilyTables>,
+    next_column_family_id: u32,
+    // timestamp of the last log whose memtables are in the sstables
+    last_flushed_wal: u128,
+    // set if a background thread failed to write a memtable or to compact, no more memtables are accepted
+    background_error: Option<Error>,
+}
+
+/// State shared with the background flush and compaction threads
 #[derive(Debug)]
-pub struct Engine {
-    // to store the SSTable files
+struct Shared {
+    // to store the SSTable and WAL files
     ss_table_dir: PathBuf,
-    mem_table: MemTable, 
-    // keep memtable size with some buffer space
-    mem_table_size: usize  
+    writer: Mutex<Writer>,
+    tables: Mutex<Tables>,
+    // notified every time the flush thread is done with a memtable and when a background thread fails
+    flushed: Condvar,
+    // the ones changed while the engine runs are updated here, the background thread reads them as well
+    options: RwLock<Options>,
+    // summed over every table written since the engine was opened
+    compression_stats: Mutex<CompressionStats>,
+    // given to every sstable file, see `Options::max_open_files` and `Options::block_cache_size`
+    table_cache: Arc<TableCache>,
+    filter_stats: Mutex<FilterStats>,
+    // what the garbage collection of the blob files did, the live files are counted from the tables
+    blob_stats: Mutex<BlobStats>,
+    // by column family id, taken after `tables` when both are needed
+    column_family_options: RwLock<HashMap<u32, ColumnFamilyOptions>>,
+    // one compaction at a time, they would pick the same tables
+    compaction: Mutex<()>,
+    snapshots: Arc<SnapshotList>,
+    // summed over every compaction since the engine was opened
+    compaction_filter_stats: Mutex<Vec<CompactionFilterStats>>,
+    comparator: Arc<dyn Comparator>,
+}
+
+impl Shared {
+    /// Layout of the tables of the column family written at `level`,
+    /// memtables are flushed at level 0 and compacted to level 1
+    fn table_options(&self, column_family: u32, level: usize) -> TableOptions {
+        let table_options = self.options.read().unwrap().table_options(level);
+        match self.column_family_options.read().unwrap().get(&column_family) {
+            Some(options) => options.table_options(table_options),
+            None => table_options,
+        }
+    }
+
+    fn ss_table_file(&self, file_name: PathBuf) -> Arc<SSTableFile> {
+        Arc::new(SSTableFile::new(file_name, self.table_cache.clone()))
+    }
+
+    fn add_compression_stats(&self, stats: &CompressionStats) {
+        self.compression_stats.lock().unwrap().add(stats);
+    }
+
+    /// Counts what the filter of a table answered, None if it had nothing to say
+    fn add_filter_check(&self, may_contain: Option<bool>) {
+        if let Some(may_contain) = may_contain {
+            let mut stats = self.filter_stats.lock().unwrap();
+            stats.checked += 1;
+            stats.useful += u64::from(!may_contain);
+        }
+    }
+
+    /// Merges the sstables of the column families which piled up too many of them, see `CompactionStyle::Automatic`
+    fn compact_due(&self) -> Result<()> {
+        let CompactionStyle::Automatic { max_tables } = self.options.read().unwrap().compaction_style else {
+            return Ok(());
+        };
+        let ids: Vec<u32> = {
+            let tables = self.tables.lock().unwrap();
+            let column_families = tables.column_families.iter();
+            column_families.filter(|(_, cf)| cf.ss_tables.len() >= max_tables).map(|(id, _)| *id).collect()
+        };
+        for id in ids {
+            self.compact_column_family(id)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the value of an entry pointing in a blob file, the entry keeps its `BlobIndex`
+    ///
+    /// `blob_files` must be taken along with the tables the entry was read from
+    fn read_blob(&self, column_family: u32, blob_files: &BlobFiles, mut entry: SSTableEntry) -> Result<SSTableEntry> {
+        if let Some(index) = entry.blob {
+            entry.value = self.blob_value(column_family, blob_files, &entry.key, &index)?;
+        }
+        Ok(entry)
+    }
+
+    fn blob_value(&self, column_family: u32, blob_files: &BlobFiles, key: &[u8], index: &BlobIndex) -> Result<Vec<u8>> {
+        let Some(blob_file) = blob_files.get(&index.file) else {
+            let file_name = BlobFile::file_name(&ColumnFamily::dir(&self.ss_table_dir, column_family), index.file);
+            return Err(Corruption::new(&file_name, index.offset, "blob file missing from the manifest").into());
+        };
+        let verify_checksums = self.options.read().unwrap().verify_checksums;
+        blob_file.read(key, index, verify_checksums)
+    }
+
+    /// Reads the whole table for compaction, straight from the file,
+    /// the blocks don't fill the block cache so a compaction doesn't evict the ones the readers need
+    fn read_table(&self, file: &SSTableFile) -> Result<SSTable> {
+        SSTable::read(file.path(), self.options.read().unwrap().verify_checksums)
+    }
+
+    fn compact_column_family(&self, id: u32) -> Result<()> {
+        let _compaction = self.compaction.lock().unwrap();
+        // the tables flushed meanwhile only point in the blob files written along with them
+        let (inputs, blob_files) = match self.tables.lock().unwrap().column_families.get(&id) {
+            Some(column_family) => (column_family.ss_tables.clone(), column_family.blob_files.clone()),
+            // dropped meanwhile
+            None => return Ok(()),
+        };
+        if inputs.is_empty() {
+            return Ok(());
+        }
+
+        let options = self.column_family_options.read().unwrap().get(&id).cloned().unwrap_or_default();
+        // both look at the values
+        let read_values = options.merge_operator.is_some() || !options.compaction_filters.is_empty();
+        let mut runs = vec![];
+        let mut range_tombstones = vec![];
+        for file in &inputs {
+            let ss_table = self.read_table(file)?;
+            range_tombstones.extend_from_slice(ss_table.range_tombstones());
+            let mut entries = ss_table.into_entries();
+            if read_values {
+                entries =
+                    entries.into_iter().map(|entry| self.read_blob(id, &blob_files, entry)).collect::<Result<_>>()?;
+            }
+            runs.push(entries);
+        }
+        // the inputs are the oldest tables, nothing older lives elsewhere
+        let history_start = match self.options.read().unwrap().retention_window {
+            Some(retention_window) => Engine::now().saturating_sub(retention_window.as_micros()),
+            None => u128::MAX,
+        };
+        let snapshots = self.snapshots.timestamps();
+        let mut compaction = Compaction::new(&snapshots, true);
+        compaction.history_start = history_start;
+        compaction.merge_operator = options.merge_operator.as_deref();
+        compaction.filters = &options.compaction_filters;
+        compaction.range_tombstones = &range_tombstones;
+        compaction.comparator = self.comparator.as_ref();
+        let mut entries = compaction.retain_visible_versions(MergeIterator::new(runs, self.comparator.as_ref()));
+        let range_tombstones = compaction.live_range_tombstones(&entries);
+        let (dropped_blob_files, relocated_values) = self.collect_blob_garbage(id, &blob_files, &mut entries)?;
+
+        let timestamp = self.writer.lock().unwrap().next_timestamp();
+        let dir = ColumnFamily::dir(&self.ss_table_dir, id);
+        let output = SSTable::file_name(&dir, timestamp);
+        let table_options = self.table_options(id, 1);
+        let mut ss_table = SSTable::new(entries, range_tombstones);
+        let blob_output = ss_table.separate_values(&dir, timestamp, table_options.min_blob_size)?;
+        let stats = ss_table.write(&output, self.comparator.as_ref(), &table_options)?;
+        self.add_compression_stats(&stats);
+        let remove_outputs = || {
+            let _ = fs::remove_file(&output);
+            if let Some(blob_output) = &blob_output {
+                let _ = fs::remove_file(blob_output);
+            }
+        };
+
+        // the flush thread may have appended tables meanwhile, the inputs are still the prefix
+        let mut tables = self.tables.lock().unwrap();
+        let mut column_families = tables.column_families.clone();
+        let Some(column_family) = column_families.get_mut(&id) else {
+            // dropped while compacting
+            remove_outputs();
+            return Ok(());
+        };
+        column_family.ss_tables.splice(0..inputs.len(), [self.ss_table_file(output.clone())]);
+        for timestamp in &dropped_blob_files {
+            column_family.blob_files.remove(timestamp);
+        }
+        if let Some(blob_output) = &blob_output {
+            let blob_file = match BlobFile::open(blob_output.clone()) {
+                Ok(blob_file) => blob_file,
+                Err(err) => {
+                    remove_outputs();
+                    return Err(err);
+                }
+            };
+            column_family.blob_files.insert(blob_file.timestamp(), Arc::new(blob_file));
+        }
+        let result = Engine::store_manifest(
+            &self.ss_table_dir,
+            &column_families,
+            &self.column_family_options.read().unwrap(),
+            tables.next_column_family_id,
+            tables.last_flushed_wal,
+            self.comparator.name()
+        );
+        if let Err(err) = result {
+            // the old tables are still the live ones
+            remove_outputs();
+            return Err(err);
+        }
+        tables.column_families = column_families;
+
+        // removed once the readers still holding them are done
+        for file in inputs {
+            file.mark_obsolete();
+        }
+        drop(tables);
+        {
+            let mut blob_stats = self.blob_stats.lock().unwrap();
+            blob_stats.relocated_values += relocated_values;
+            for timestamp in dropped_blob_files {
+                blob_files[&timestamp].mark_obsolete();
+                blob_stats.reclaimed_bytes += blob_files[&timestamp].size();
+            }
+        }
+
+        let mut filter_stats = self.compaction_filter_stats.lock().unwrap();
+        for stats in compaction.filter_stats {
+            match filter_stats.iter_mut().find(|total| total.name == stats.name) {
+                Some(total) => total.add(&stats),
+                None => filter_stats.push(stats),
+            }
+        }
+        Ok(())
+    }
+
+    /// Garbage collection of the blob files the compacted tables pointed in, `entries` are the versions retained
+    ///
+    /// A blob file no retained version points in anymore is dropped as a whole,
+    /// the live values of one whose garbage share reached `Options::blob_garbage_ratio` are read back
+    /// in the entries, to be written in the blob file of the output, then it is dropped as well
+    /// Returns the timestamps of the dropped blob files and the number of values moved
+    fn collect_blob_garbage(
+        &self,
+        column_family: u32,
+        blob_files: &BlobFiles,
+        entries: &mut [SSTableEntry]
+    ) -> Result<(Vec<u128>, u64)> {
+        let mut live_bytes: HashMap<u128, u64> = HashMap::new();
+        for index in entries.iter().filter_map(|entry| entry.blob) {
+            *live_bytes.entry(index.file).or_default() += index.size;
+        }
+        let garbage_ratio = self.options.read().unwrap().blob_garbage_ratio;
+        let mut dropped = vec![];
+        let mut relocated = vec![];
+        for (timestamp, blob_file) in blob_files {
+            let live_bytes = live_bytes.get(timestamp).copied().unwrap_or(0);
+            let garbage = blob_file.size().saturating_sub(live_bytes);
+            let relocate = live_bytes > 0 && (garbage as f64) >= garbage_ratio * blob_file.size() as f64;
+            if relocate {
+                relocated.push(*timestamp);
+            }
+            if live_bytes == 0 || relocate {
+                dropped.push(*timestamp);
+            }
+        }
+
+        let mut relocated_values = 0;
+        for entry in entries.iter_mut() {
+            let Some(index) = entry.blob.filter(|index| relocated.contains(&index.file)) else {
+                continue;
+            };
+            entry.value = self.blob_value(column_family, blob_files, &entry.key, &index)?;
+            entry.blob = None;
+            relocated_values += 1;
+        }
+        Ok((dropped, relocated_values))
+    }
+}
+
+/// Memtables the writes go to, readers share them with the writer
+#[derive(Debug, Default)]
+struct Current {
+    // one for every live column family
+    mem_tables: MemTables,
+    // timestamp of the last entry in the memtable, snapshots are taken at it
+    last_sequence: u128,
+}
+
+impl Current {
+    /// Space taken by the memtables of every column family
+    fn size(&self) -> usize {
+        self.mem_tables.values().map(|mem_table| mem_table.size).sum()
+    }
+}
+
+/// Held by the one thread writing to the log and the memtable
+#[derive(Debug)]
+struct Writer {
+    // log of the current memtables
+    wal: Wal,
+    // last timestamp handed to an entry
+    last_timestamp: u128,
+}
+
+impl Writer {
+    /// Current time in micro seconds, bumped if the clock didn't move since the last call
+    fn next_timestamp(&mut self) -> u128 {
+        self.last_timestamp = Engine::now().max(self.last_timestamp + 1);
+        self.last_timestamp
+    }
+}
+
+/// Batches waiting for the writer, the first thread to get the writer commits all of them
+#[derive(Debug, Default)]
+struct WriteQueue {
+    pending: Vec<(u64, WriteBatch)>,
+    // results of the batches committed by another thread
+    results: HashMap<u64, Result<()>>,
+    next_id: u64,
+    // groups committed since the engine was opened
+    groups: u64,
+}
+
+/// Timestamps double as sequence numbers, every entry gets its own
+/// and they are strictly increasing, so the newest entry for a key always has the largest one
+///
+/// Every method takes `&self`, readers only wait for the memtable while a writer inserts in it,
+/// see `Db` to share one engine across threads
+#[derive(Debug)]
+pub struct Engine {
+    current: RwLock<Current>,
+    write_queue: Mutex<WriteQueue>,
+    shared: Arc<Shared>,
+    flush_sender: Option<Sender<FlushJob>>,
+    flush_thread: Option<JoinHandle<()>>,
+    // fed by the flush thread, see `CompactionStyle::Automatic`
+    compaction_thread: Option<JoinHandle<()>>,
+    locks: Arc<LockManager>,
 }
 
 impl Engine {
+    /// Opens the database in `storage_path` with the default options apart from the memtables, see `open()`
     pub fn new(
         storage_path: String,
-        mem_table_size: usize
+        mem_table_size: usize,
+        max_immutable_mem_tables: usize
+    ) -> Result<Self> {
+        Self::with_comparator(storage_path, mem_table_size, max_immutable_mem_tables, default_comparator())
+    }
+
+    /// Same as `new()` with the keys sorted by `comparator`
+    pub fn with_comparator(
+        storage_path: String,
+        mem_table_size: usize,
+        max_immutable_mem_tables: usize,
+        comparator: Arc<dyn Comparator>
     ) -> Result<Self> {
+        let options = Options::builder()
+            .mem_table_size(mem_table_size)
+            .max_immutable_mem_tables(max_immutable_mem_tables)
+            .comparator(comparator)
+            .build()?;
+        Self::open(storage_path, options)
+    }
+
+    /// Opens the database in `storage_path`, creating it if missing unless the options say otherwise
+    ///
+    /// Logs left behind by a crash are replayed and written as sstables before anything else,
+    /// sstables missing from the manifest are leftovers of a crash and removed
+    /// The options are written to the `OPTIONS` file of the database as a record, see `Options`
+    pub fn open(storage_path: String, options: Options) -> Result<Self> {
+        options.validate()?;
+        options.default_column_family.validate()?;
+        let comparator = options.comparator.clone();
+
         let path = PathBuf::from(storage_path);
-        let _ = fs::create_dir(path.clone());
-        Ok(Self {
+        // any file counts, a database without its manifest is refused below
+        let exists = fs::read_dir(&path).is_ok_and(|mut entries| entries.next().is_some());
+        if exists && options.error_if_exists {
+            return Err(Error::InvalidArgument(format!("database {} already exists", path.display())));
+        }
+        if !exists && !options.create_if_missing {
+            return Err(Error::NotFound(format!("database {}", path.display())));
+        }
+        fs::create_dir_all(&path)?;
+
+        let (column_family_entries, mut last_flushed_wal, next_column_family_id) = match Manifest::load(&path)? {
+            Some(manifest) => {
+                if manifest.comparator != comparator.name() {
+                    return Err(Error::InvalidArgument(format!(
+                        "database was created with comparator {}, opened with {}",
+                        manifest.comparator,
+                        comparator.name()
+                    )));
+                }
+                (manifest.column_families, manifest.last_flushed_wal, manifest.next_column_family_id)
+            }
+            // a new database, unless the tables and logs of one written before the manifest existed are there
+            None => {
+                let old_tables = Self::find_files(&path, SSTable::file_timestamp)?;
+                let old_logs = Self::find_files(&path, Wal::file_timestamp)?;
+                if !old_tables.is_empty() || !old_logs.is_empty() {
+                    return Err(Error::InvalidArgument(format!(
+                        "database {} was written before the manifest existed, its format is no longer supported",
+                        path.display()
+                    )));
+                }
+                let mut manifest = Manifest::new(0, comparator.name(), 1);
+                let cf_options = &options.default_column_family;
+                manifest.add_column_family(0, DEFAULT_COLUMN_FAMILY, cf_options, &[], &[]);
+                (manifest.column_families, 0, 1)
+            }
+        };
+
+        let mut last_timestamp = last_flushed_wal;
+        let mut compression_stats = CompressionStats::default();
+        let mut column_families = BTreeMap::new();
+        for entry in column_family_entries {
+            let dir = ColumnFamily::dir(&path, entry.id);
+            fs::create_dir_all(&dir)?;
+            let ss_tables = entry.ss_table_files(&dir);
+            for (timestamp, file_name) in Self::find_files(&dir, SSTable::file_timestamp)? {
+                if ss_tables.contains(&file_name) {
+                    last_timestamp = last_timestamp.max(timestamp);
+                } else {
+                    fs::remove_file(&file_name)?;
+                }
+            }
+            let blob_files = entry.blob_file_names(&dir);
+            // written along with a table which never made it in the manifest
+            for (_, file_name) in Self::find_files(&dir, BlobFile::file_timestamp)? {
+                if !blob_files.contains(&file_name) {
+                    fs::remove_file(&file_name)?;
+                }
+            }
+            // the default column family takes the settings it is opened with, like the engine
+            let cf_options = if entry.id == 0 { options.default_column_family.clone() } else { entry.options };
+            column_families.insert(entry.id, (entry.name, cf_options, ss_tables, blob_files));
+        }
+        // directories of the column families dropped right before a crash
+        for dir_entry in fs::read_dir(&path)? {
+            let dir = dir_entry?.path();
+            if ColumnFamily::dir_id(&dir).is_some_and(|id| !column_families.contains_key(&id)) {
+                fs::remove_dir_all(&dir)?;
+            }
+        }
+
+        let wal_files = Self::find_files(&path, Wal::file_timestamp)?;
+        for (wal_timestamp, wal_file) in &wal_files {
+            last_timestamp = last_timestamp.max(*wal_timestamp);
+            if *wal_timestamp <= last_flushed_wal {
+                continue;
+            }
+            let mut mem_tables: MemTables = column_families
+                .keys()
+                .map(|id| (*id, MemTable::with_comparator(comparator.clone())))
+                .collect();
+            for record in Wal::replay(wal_file)? {
+                last_timestamp = last_timestamp.max(record.timestamp + record.batch.len() as u128);
+                // keep the history of the log, compaction drops what nobody reads
+                Self::apply(&mut mem_tables, record, Some(u128::MAX))?;
+            }
+            for (id, mem_table) in mem_tables {
+                if mem_table.size > 0 {
+                    let (_, cf_options, ss_tables, blob_files) = column_families.get_mut(&id).unwrap();
+                    let table_options = cf_options.table_options(options.table_options(0));
+                    let (file_name, blob_file, stats) =
+                        mem_table.flush(&ColumnFamily::dir(&path, id), *wal_timestamp, &table_options)?;
+                    ss_tables.push(file_name);
+                    blob_files.extend(blob_file);
+                    compression_stats.add(&stats);
+                }
+            }
+            last_flushed_wal = *wal_timestamp;
+        }
+        let mut manifest = Manifest::new(last_flushed_wal, comparator.name(), next_column_family_id);
+        for (id, (name, cf_options, ss_tables, blob_files)) in &column_families {
+            manifest.add_column_family(*id, name, cf_options, ss_tables, blob_files);
+        }
+        manifest.store(&path)?;
+        options.store(&path)?;
+        for (_, wal_file) in wal_files {
+            fs::remove_file(wal_file)?;
+        }
+
+        let mut writer = Writer {
+            wal: Wal::create(&path, last_timestamp + 1)?,
+            last_timestamp,
+        };
+        // the log took this one
+        writer.next_timestamp();
+        let last_sequence = writer.last_timestamp;
+
+        let shared = Arc::new(Shared {
             ss_table_dir: path,
-            mem_table: MemTable::new(),
-            mem_table_size
+            writer: Mutex::new(writer),
+            tables: Mutex::new(Tables::default()),
+            flushed: Condvar::new(),
+            table_cache: Arc::new(TableCache::new(options.max_open_files, options.block_cache_size)),
+            options: RwLock::new(options),
+            compression_stats: Mutex::new(CompressionStats::default()),
+            filter_stats: Mutex::new(FilterStats::default()),
+            blob_stats: Mutex::new(BlobStats::default()),
+            column_family_options: RwLock::new(
+                column_families.iter().map(|(id, (_, cf_options, _, _))| (*id, cf_options.clone())).collect()
+            ),
+            compaction: Mutex::new(()),
+            snapshots: Arc::new(SnapshotList::default()),
+            compaction_filter_stats: Mutex::new(vec![]),
+            comparator: comparator.clone(),
+        });
+        {
+            let mut tables = shared.tables.lock().unwrap();
+            for (id, (name, _, ss_tables, blob_file_names)) in &column_families {
+                let ss_tables =
+                    ss_tables.iter().map(|file_name| shared.ss_table_file(file_name.clone())).collect();
+                let mut blob_files = BlobFiles::new();
+                for file_name in blob_file_names {
+                    let blob_file = BlobFile::open(file_name.clone())?;
+                    blob_files.insert(blob_file.timestamp(), Arc::new(blob_file));
+                }
+                tables
+                    .column_families
+                    .insert(*id, ColumnFamilyTables { name: name.clone(), ss_tables, blob_files });
+            }
+            tables.next_column_family_id = next_column_family_id;
+            tables.last_flushed_wal = last_flushed_wal;
+        }
+        shared.add_compression_stats(&compression_stats);
+
+        let (compaction_sender, compaction_receiver) = mpsc::channel();
+        let compaction_thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("simpledb-compaction".to_owned())
+                .spawn(move || Self::compaction_worker(shared, compaction_receiver))?
+        };
+        let (flush_sender, receiver) = mpsc::channel();
+        let flush_thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("simpledb-flush".to_owned())
+                .spawn(move || Self::flush_worker(shared, receiver, compaction_sender))?
+        };
+
+        Ok(Self {
+            current: RwLock::new(Current {
+                mem_tables: column_families
+                    .keys()
+                    .map(|id| (*id, MemTable::with_comparator(comparator.clone())))
+                    .collect(),
+                last_sequence,
+            }),
+            write_queue: Mutex::new(WriteQueue::default()),
+            shared,
+            flush_sender: Some(flush_sender),
+            flush_thread: Some(flush_thread),
+            compaction_thread: Some(compaction_thread),
+            locks: Arc::new(LockManager::default()),
         })
     }
 
-    /// If Memtable size is full, Save the memtable in the disk 
-    /// Insert in the new memtable
-    pub fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+    /// Applies a single set, see `write()`
+    pub fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.set(key, value);
+        self.write(batch)
+    }
+
+    /// Applies a single set, the key reads as missing once `ttl` passed
+    /// and compaction drops it for good
+    pub fn set_with_ttl(&self, key: Vec<u8>, value: Vec<u8>, ttl: Duration) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.set_with_ttl(key, value, ttl);
+        self.write(batch)
+    }
 
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_micros();
+    /// Applies a single delete, see `write()`
+    pub fn delete(&self, key: Vec<u8>) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete(key);
+        self.write(batch)
+    }
+
+    /// Deletes every key in `[start, end)` with a single range tombstone, see `write()`
+    ///
+    /// The covered keys are hidden from the readers right away and dropped by compaction
+    pub fn delete_range(&self, start: Vec<u8>, end: Vec<u8>) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete_range(start, end);
+        self.write(batch)
+    }
+
+    /// Applies every entry of the batch or none of them
+    ///
+    /// If the batch doesn't fit in the memtable, the memtable is moved to the immutable memtables first,
+    /// the background thread saves it in the disk, so a batch is never split across memtables
+    /// The batch is written as one WAL record and its entries get consecutive timestamps
+    ///
+    /// Concurrent writers queue their batches, whoever gets the writer first
+    /// commits the whole queue with one append to the log
+    pub fn write(&self, batch: WriteBatch) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.check_batch(&batch)?;
+
+        let id = {
+            let mut queue = self.write_queue.lock().unwrap();
+            queue.next_id += 1;
+            let id = queue.next_id;
+            queue.pending.push((id, batch));
+            id
+        };
+
+        let mut writer = self.shared.writer.lock().unwrap();
+        let group = {
+            let mut queue = self.write_queue.lock().unwrap();
+            // committed by the previous holder of the writer
+            if let Some(result) = queue.results.remove(&id) {
+                return result;
+            }
+            queue.groups += 1;
+            mem::take(&mut queue.pending)
+        };
+
+        let (ids, batches): (Vec<u64>, Vec<WriteBatch>) = group.into_iter().unzip();
+        let result = self.write_group(&mut writer, batches);
+
+        let mut queue = self.write_queue.lock().unwrap();
+        for other in ids.into_iter().filter(|other| *other != id) {
+            queue.results.insert(other, result.clone());
+        }
+        result
+    }
+
+    /// Adds an operand the merge operator folds into the value of the key when it is read,
+    /// see `set_merge_operator()`
+    pub fn merge(&self, key: Vec<u8>, operand: Vec<u8>) -> Result<()> {
+        if self.merge_operator(0).is_none() {
+            return Err(Error::InvalidArgument("merge needs a merge operator".to_owned()));
+        }
+        let mut batch = WriteBatch::new();
+        batch.merge(key, operand);
+        self.write(batch)
+    }
+
+    /// Operator folding the operands of `merge()` in the default column family, it isn't stored in the disk,
+    /// set the same one again after opening a database with operands in it
+    pub fn set_merge_operator(&self, merge_operator: Arc<dyn MergeOperator>) {
+        let mut options = self.shared.column_family_options.write().unwrap();
+        options.entry(0).or_default().merge_operator = Some(merge_operator);
+    }
+
+    /// Sets the key to `new` if its newest value is `expected`, None meaning the key doesn't exist
+    ///
+    /// The check and the write are atomic, no other write can come in between
+    /// Returns whether the value was written
+    pub fn compare_and_set(&self, key: Vec<u8>, expected: Option<&[u8]>, new: Vec<u8>) -> Result<bool> {
+        let mut batch = WriteBatch::new();
+        batch.set(key.clone(), new);
+        self.write_if(&key, expected, batch)
+    }
+
+    /// Sets the key only if it doesn't exist, see `compare_and_set()`
+    pub fn set_if_absent(&self, key: Vec<u8>, value: Vec<u8>) -> Result<bool> {
+        self.compare_and_set(key, None, value)
+    }
+
+    /// Deletes the key only if its newest value is `expected`, see `compare_and_set()`
+    pub fn delete_if_equals(&self, key: Vec<u8>, expected: &[u8]) -> Result<bool> {
+        let mut batch = WriteBatch::new();
+        batch.delete(key.clone());
+        self.write_if(&key, Some(expected), batch)
+    }
+
+    /// Runs the filter on the values every compaction of the default column family keeps,
+    /// after the ones already added
+    /// Like the merge operator it isn't stored in the disk
+    pub fn add_compaction_filter(&self, filter: Arc<dyn CompactionFilter>) {
+        let mut options = self.shared.column_family_options.write().unwrap();
+        options.entry(0).or_default().compaction_filters.push(filter);
+    }
+
+    /// What every compaction filter did since the engine was opened, in the order they were added
+    pub fn compaction_filter_stats(&self) -> Vec<CompactionFilterStats> {
+        self.shared.compaction_filter_stats.lock().unwrap().clone()
+    }
+
+    /// Creates an empty column family, the name must not be taken
+    ///
+    /// The options which can be stored are written to the manifest along with it and come back on open
+    pub fn create_column_family(&self, name: &str, options: ColumnFamilyOptions) -> Result<ColumnFamily> {
+        options.validate()?;
+        // no memtable is frozen meanwhile
+        let _writer = self.shared.writer.lock().unwrap();
+        let mut tables = self.shared.tables.lock().unwrap();
+        if tables.column_families.values().any(|column_family| column_family.name == name) {
+            return Err(Error::InvalidArgument(format!("column family {} already exists", name)));
+        }
 
-        let new_entry_size = self.mem_table.get_max_entry_size(&key, &value);
-        if self.mem_table_size >= self.mem_table.size + new_entry_size {
-            self.mem_table.set(key, value, timestamp)?;
-        } else {
-            // store the memtable in the disk
-            self.mem_table.flush(&self.ss_table_dir, timestamp)?;
-            self.mem_table = MemTable::new();
-            self.mem_table.set(key, value, timestamp)?;
+        let id = tables.next_column_family_id;
+        fs::create_dir_all(ColumnFamily::dir(&self.shared.ss_table_dir, id))?;
+        let mut column_families = tables.column_families.clone();
+        column_families.insert(
+            id,
+            ColumnFamilyTables { name: name.to_owned(), ss_tables: vec![], blob_files: BlobFiles::new() }
+        );
+        let mut column_family_options = self.shared.column_family_options.write().unwrap();
+        let mut new_options = column_family_options.clone();
+        new_options.insert(id, options);
+        Self::store_manifest(
+            &self.shared.ss_table_dir,
+            &column_families,
+            &new_options,
+            id + 1,
+            tables.last_flushed_wal,
+            self.shared.comparator.name()
+        )?;
+        tables.column_families = column_families;
+        tables.next_column_family_id = id + 1;
+        *column_family_options = new_options;
+        drop(column_family_options);
+
+        self.current.write().unwrap().mem_tables.insert(id, MemTable::with_comparator(self.shared.comparator.clone()));
+        Ok(ColumnFamily::new(id, name))
+    }
+
+    /// Drops the column family and every key in it, the default one can't be dropped
+    ///
+    /// Its files are removed once the readers still holding them are done
+    pub fn drop_column_family(&self, column_family: &ColumnFamily) -> Result<()> {
+        if column_family.id() == 0 {
+            return Err(Error::InvalidArgument("the default column family can't be dropped".to_owned()));
+        }
+        let _writer = self.shared.writer.lock().unwrap();
+        let mut tables = self.shared.tables.lock().unwrap();
+        let mut column_families = tables.column_families.clone();
+        let dropped = column_families
+            .remove(&column_family.id())
+            .ok_or_else(|| Error::NotFound(format!("column family {}", column_family.name())))?;
+        let next_column_family_id = tables.next_column_family_id;
+        Self::store_manifest(
+            &self.shared.ss_table_dir,
+            &column_families,
+            &self.shared.column_family_options.read().unwrap(),
+            next_column_family_id,
+            tables.last_flushed_wal,
+            self.shared.comparator.name()
+        )?;
+        tables.column_families = column_families;
+        // its entries in the log are skipped from now on
+        self.current.write().unwrap().mem_tables.remove(&column_family.id());
+        drop(tables);
+
+        self.shared.column_family_options.write().unwrap().remove(&column_family.id());
+        for file in dropped.ss_tables {
+            file.mark_obsolete();
+        }
+        for blob_file in dropped.blob_files.values() {
+            blob_file.mark_obsolete();
         }
+        // left behind while the files are read, then removed on the next open
+        let _ = fs::remove_dir(ColumnFamily::dir(&self.shared.ss_table_dir, column_family.id()));
+        Ok(())
+    }
 
+    /// Handle to the column family with the name, the default one included
+    pub fn column_family(&self, name: &str) -> Option<ColumnFamily> {
+        let tables = self.shared.tables.lock().unwrap();
+        tables
+            .column_families
+            .iter()
+            .find(|(_, column_family)| column_family.name == name)
+            .map(|(id, column_family)| ColumnFamily::new(*id, &column_family.name))
+    }
+
+    /// Every live column family, the default one first
+    pub fn column_families(&self) -> Vec<ColumnFamily> {
+        let tables = self.shared.tables.lock().unwrap();
+        tables
+            .column_families
+            .iter()
+            .map(|(id, column_family)| ColumnFamily::new(*id, &column_family.name))
+            .collect()
+    }
+
+    /// Replaces the options of the column family, the tables written from now on follow them
+    ///
+    /// The ones which can be stored are written to the manifest, the merge operator and the compaction filters
+    /// have to be set again after opening the database
+    pub fn set_column_family_options(&self, column_family: &ColumnFamily, options: ColumnFamilyOptions) -> Result<()> {
+        options.validate()?;
+        let tables = self.shared.tables.lock().unwrap();
+        if !tables.column_families.contains_key(&column_family.id()) {
+            return Err(Error::NotFound(format!("column family {}", column_family.name())));
+        }
+        let mut column_family_options = self.shared.column_family_options.write().unwrap();
+        let mut new_options = column_family_options.clone();
+        new_options.insert(column_family.id(), options);
+        Self::store_manifest(
+            &self.shared.ss_table_dir,
+            &tables.column_families,
+            &new_options,
+            tables.next_column_family_id,
+            tables.last_flushed_wal,
+            self.shared.comparator.name()
+        )?;
+        *column_family_options = new_options;
         Ok(())
     }
 
+    /// Same as `set()` in the column family
+    pub fn set_cf(&self, column_family: &ColumnFamily, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.set_cf(column_family, key, value);
+        self.write(batch)
+    }
+
+    /// Same as `delete()` in the column family
+    pub fn delete_cf(&self, column_family: &ColumnFamily, key: Vec<u8>) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete_cf(column_family, key);
+        self.write(batch)
+    }
+
+    /// Same as `get()` in the column family
+    pub fn get_cf(&self, column_family: &ColumnFamily, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.get_versioned(column_family.id(), &key, u128::MAX)
+    }
+
+    /// Same as `scan()` in the column family
+    pub fn scan_cf<R: RangeBounds<Vec<u8>>>(
+        &self,
+        column_family: &ColumnFamily,
+        range: R
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.scan_versioned(column_family.id(), range, u128::MAX)
+    }
+
+    /// `scan_prefix()` in the column family
+    pub fn scan_prefix_cf(&self, column_family: &ColumnFamily, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.scan_prefix_versioned(column_family.id(), prefix, u128::MAX)
+    }
+
+    /// Codec of the sstables by level, memtables are flushed at level 0 and compacted to level 1,
+    /// the last codec is used for the levels past the list
+    ///
+    /// Only applies to the tables written from now on, every block keeps the codec it was written with
+    pub fn set_compression_per_level(&self, compression: Vec<Compression>) {
+        self.shared.options.write().unwrap().compression_per_level = compression;
+    }
+
+    /// Size of the sstable blocks before and after compression, summed over the tables written since open
+    pub fn compression_stats(&self) -> CompressionStats {
+        *self.shared.compression_stats.lock().unwrap()
+    }
+
+    /// Hits and misses of the block cache since the engine was opened, along with the bytes it holds
+    pub fn block_cache_stats(&self) -> CacheStats {
+        self.shared.table_cache.block_cache_stats()
+    }
+
+    /// How often the bloom filters of the sstables were asked and saved reading the table
+    pub fn filter_stats(&self) -> FilterStats {
+        *self.shared.filter_stats.lock().unwrap()
+    }
+
+    /// Size of the blob files of every column family, along with what compaction moved and removed since open
+    pub fn blob_stats(&self) -> BlobStats {
+        let mut stats = *self.shared.blob_stats.lock().unwrap();
+        let tables = self.shared.tables.lock().unwrap();
+        for blob_file in tables.column_families.values().flat_map(|column_family| column_family.blob_files.values()) {
+            stats.file_count += 1;
+            stats.file_size += blob_file.size();
+        }
+        stats
+    }
+
+    /// Hits and misses of the open sstable readers, the usage is the number of files kept open
+    pub fn table_cache_stats(&self) -> CacheStats {
+        self.shared.table_cache.stats()
+    }
+
+    /// Checks the sstable blocks against their checksums on every read, on by default
+    ///
+    /// Turned off a flipped bit may go unnoticed, a block which doesn't decode still fails with `Corruption`
+    pub fn set_verify_checksums(&self, verify_checksums: bool) {
+        self.shared.options.write().unwrap().verify_checksums = verify_checksums;
+        // the open readers keep the setting they were opened with
+        self.shared.table_cache.clear();
+    }
+
+    /// Turns the multi-version mode on, older versions of the keys are kept
+    /// and compaction only drops the ones replaced before `now - retention_window`
+    /// None turns it off, older versions are then only kept for the snapshots
+    pub fn set_retention_window(&self, retention_window: Option<Duration>) {
+        self.shared.options.write().unwrap().retention_window = retention_window;
+    }
+
     /// get will return the data stored
     /// At first, checks the memtable if available -> return
-    /// If not in Memtable, start iterating over stored sstable in decreasing timestamp order 
-    /// return the first value got or else None
-    pub fn get(&self, key: Vec<u8>) -> Option<Vec<u8>> {
+    /// Then the immutable memtables which are not yet in the disk, newest first
+    /// If not in Memtables, start iterating over stored sstable in decreasing timestamp order
+    /// return the first value got or else None, a deleted key stops the search
+    pub fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.get_versioned(0, &key, u128::MAX)
+    }
+
+    /// Same as `get()` but only sees the entries written at or before the snapshot
+    pub fn get_at(&self, snapshot: &Snapshot, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.get_versioned(0, &key, snapshot.sequence())
+    }
+
+    /// Value of the key at `timestamp`, micro seconds since the unix epoch like the entry timestamps
+    ///
+    /// Needs the multi-version mode, see `set_retention_window()`,
+    /// history older than the retention window may already be compacted away
+    pub fn get_as_of(&self, key: Vec<u8>, timestamp: u128) -> Result<Option<Vec<u8>>> {
+        if self.shared.options.read().unwrap().retention_window.is_none() {
+            return Err(Error::InvalidArgument("time-travel reads need a retention window".to_owned()));
+        }
+        self.get_versioned(0, &key, timestamp)
+    }
+
+    /// Starts a transaction reading the current state of the database
+    pub fn begin_transaction(&self) -> Transaction {
+        Transaction::new(self.snapshot())
+    }
+
+    /// Applies the writes of the transaction as one batch
+    ///
+    /// Fails with `Busy::TransactionConflict` without writing anything
+    /// if a key the transaction read was written after it started
+    pub fn commit(&self, transaction: Transaction) -> Result<()> {
+        // no write can slip in between the validation and the write
+        let mut writer = self.shared.writer.lock().unwrap();
+        let start_sequence = transaction.start_sequence();
+        for key in transaction.read_keys() {
+            self.check_unchanged(key, start_sequence)?;
+        }
+        let batch = transaction.into_write_batch();
+        self.check_batch(&batch)?;
+        self.write_group(&mut writer, vec![batch])
+    }
+
+    /// Starts a lock-based transaction, a wait for a lock gives up after `lock_timeout`
+    pub fn begin_pessimistic_transaction(
+        &self,
+        isolation: IsolationLevel,
+        lock_timeout: Duration
+    ) -> PessimisticTransaction {
+        let snapshot = match isolation {
+            IsolationLevel::ReadCommitted => None,
+            IsolationLevel::Snapshot => Some(self.snapshot()),
+        };
+        PessimisticTransaction::new(self.locks.clone(), lock_timeout, isolation, snapshot)
+    }
+
+    /// Applies the writes of the transaction as one batch and releases its locks
+    ///
+    /// Under the snapshot isolation, fails with `Busy::TransactionConflict` without writing anything
+    /// if a written key was changed after the start, before the transaction locked it
+    pub fn commit_pessimistic(&self, mut transaction: PessimisticTransaction) -> Result<()> {
+        // plain writes don't take the key locks, hold the writer so none slips in after the check
+        let mut writer = self.shared.writer.lock().unwrap();
+        if let Some(start_sequence) = transaction.start_sequence() {
+            for key in transaction.written_keys() {
+                self.check_unchanged(key, start_sequence)?;
+            }
+        }
+        let batch = transaction.take_write_batch();
+        self.check_batch(&batch)?;
+        self.write_group(&mut writer, vec![batch])
+    }
+
+    /// Pins the current state of the database, reads through it ignore later writes
+    ///
+    /// The versions it reads survive overwrites, flushes and compactions till the handle is dropped
+    pub fn snapshot(&self) -> Snapshot {
+        // the writer checks the snapshots under the write lock before overwriting a version
+        let current = self.current.read().unwrap();
+        self.shared.snapshots.acquire(current.last_sequence)
+    }
+
+    /// Live key value pairs in the range, sorted by key
+    pub fn scan<R: RangeBounds<Vec<u8>>>(&self, range: R) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.scan_versioned(0, range, u128::MAX)
+    }
+
+    /// Every live key starting with `prefix` along with its value, in the order of the comparator
+    ///
+    /// The sstables whose prefix filter doesn't have the prefix are skipped without being read,
+    /// see `OptionsBuilder::prefix_extractor()`
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.scan_prefix_versioned(0, prefix, u128::MAX)
+    }
+
+    /// Same as `scan()` but only sees the entries written at or before the snapshot
+    pub fn scan_at<R: RangeBounds<Vec<u8>>>(
+        &self,
+        snapshot: &Snapshot,
+        range: R
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.scan_versioned(0, range, snapshot.sequence())
+    }
+
+    /// Merges the sstables of every column family into one per column family
+    ///
+    /// Keeps the newest version of every key and the older versions the live snapshots read,
+    /// deleted keys nobody can see anymore are dropped for good,
+    /// a range tombstone as well once no version it covers is left
+    /// The values in blob files stay where they are, see `collect_blob_garbage()` for when they move
+    pub fn compact(&self) -> Result<()> {
+        let ids: Vec<u32> = self.shared.tables.lock().unwrap().column_families.keys().copied().collect();
+        for id in ids {
+            self.shared.compact_column_family(id)?;
+        }
+        Ok(())
+    }
+
+    /// Same as `compact()` for one column family
+    pub fn compact_cf(&self, column_family: &ColumnFamily) -> Result<()> {
+        self.check_column_family(column_family.id())?;
+        self.shared.compact_column_family(column_family.id())
+    }
+
+    /// Forces the current memtable in the disk
+    ///
+    /// Hands it over to the background thread like a full memtable
+    /// and waits till every queued memtable is written
+    pub fn flush(&self) -> Result<()> {
+        {
+            let mut writer = self.shared.writer.lock().unwrap();
+            if self.current.read().unwrap().size() > 0 {
+                self.freeze_mem_table(&mut writer)?;
+            }
+        }
+
+        let mut tables = self.shared.tables.lock().unwrap();
+        while !tables.immutable_mem_tables.is_empty() && tables.background_error.is_none() {
+            tables = self.shared.flushed.wait(tables).unwrap();
+        }
+        if let Some(err) = &tables.background_error {
+            return Err(err.clone());
+        }
+        Ok(())
+    }
+
+    /// Flushes everything, stops the background threads
+    /// and syncs the SSTable directory so the new files survive a crash
+    ///
+    /// Waits for the running compaction, its failure is returned as well
+    pub fn close(mut self) -> Result<()> {
+        self.flush()?;
+        self.stop_background_threads();
+        if let Some(err) = &self.shared.tables.lock().unwrap().background_error {
+            return Err(err.clone());
+        }
+        self.remove_empty_wal()?;
+        File::open(&self.shared.ss_table_dir)?.sync_all()?;
+        Ok(())
+    }
+
+    /// Number of full memtables waiting for the background thread
+    pub fn immutable_mem_table_count(&self) -> usize {
+        self.shared.tables.lock().unwrap().immutable_mem_tables.len()
+    }
+
+    /// Writes the batch if the newest value of the key is `expected`, under the writer
+    fn write_if(&self, key: &[u8], expected: Option<&[u8]>, batch: WriteBatch) -> Result<bool> {
+        let mut writer = self.shared.writer.lock().unwrap();
+        let current = self.get_versioned(0, key, u128::MAX)?;
+        if current.as_deref() != expected {
+            return Ok(false);
+        }
+        self.write_group(&mut writer, vec![batch])?;
+        Ok(true)
+    }
+
+    /// Fails with `Busy::TransactionConflict` if the key was written after `start_sequence`
+    fn check_unchanged(&self, key: &[u8], start_sequence: u128) -> Result<()> {
+        if let Some(entry) = self.latest_entry(0, key, u128::MAX)? {
+            if entry.timestamp > start_sequence {
+                return Err(TransactionConflict { key: key.to_vec() }.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Newest version of the key in the column family written at or before `max_timestamp`
+    fn get_versioned(&self, column_family: u32, key: &[u8], max_timestamp: u128) -> Result<Option<Vec<u8>>> {
+        Ok(self.latest_entry(column_family, key, max_timestamp)?.and_then(|entry| entry.live_value()))
+    }
+
+    /// Newest entry of the key written at or before `max_timestamp`, TombStones included,
+    /// merge operands are folded into a value
+    fn latest_entry(&self, column_family: u32, key: &[u8], max_timestamp: u128) -> Result<Option<SSTableEntry>> {
+        let versions = self.versions_to_fold(column_family, key, max_timestamp)?;
+        match versions.first() {
+            None => Ok(None),
+            Some(newest) if !newest.merge => Ok(Some(newest.clone())),
+            Some(_) => {
+                let merge_operator = self.merge_operator(column_family);
+                let merge_operator =
+                    merge_operator.ok_or_else(|| {
+                        Error::InvalidArgument("merge operand found but no merge operator is set".to_owned())
+                    })?;
+                Ok(Some(fold_versions(merge_operator.as_ref(), &versions)))
+            }
+        }
+    }
+
+    /// Versions of the key newest first, from the newest one written at or before `max_timestamp`
+    /// down to the first value or TombStone, more than one only when merge operands are on top
+    ///
+    /// A range tombstone hiding the versions below shows up as a TombStone with its timestamp
+    fn versions_to_fold(&self, column_family: u32, key: &[u8], max_timestamp: u128) -> Result<Vec<SSTableEntry>> {
+        let mut versions = vec![];
+        let mut max_timestamp = max_timestamp;
+
         // Check Memtable
-        if let Some(entry) = self.mem_table.get(key) {
-            return Some(entry);
+        {
+            let current = self.current.read().unwrap();
+            let mem_table = current
+                .mem_tables
+                .get(&column_family)
+                .ok_or_else(|| Error::NotFound(format!("column family {}", column_family)))?;
+            let get_entry = |max_timestamp| Ok(mem_table.get_entry(key, max_timestamp));
+            if self.table_versions(&mut versions, key, &mut max_timestamp, mem_table.range_tombstones(), get_entry)? {
+                return Ok(versions);
+            }
+        }
+
+        // copy the lists out so the lock is not held while reading the disk,
+        // both are taken under the same lock so a memtable being flushed is found in one of them,
+        // and after the memtable so a memtable being frozen is found as well
+        let (immutable_mem_tables, ss_tables, blob_files) = self.table_lists(column_family)?;
+
+        for mem_table in immutable_mem_tables.iter().filter_map(|mem_tables| mem_tables.get(&column_family)) {
+            let get_entry = |max_timestamp| Ok(mem_table.get_entry(key, max_timestamp));
+            if self.table_versions(&mut versions, key, &mut max_timestamp, mem_table.range_tombstones(), get_entry)? {
+                return Ok(versions);
+            }
         }
 
-        // TODO: Write logic for checking sstables
+        // tables never overlap in time, the first version found is the newest one
+        for file in ss_tables.iter().rev() {
+            let table = self.table_reader(file)?;
+            let may_contain = table.may_contain(key);
+            self.shared.add_filter_check(may_contain);
+            // the range tombstones still count when the filter rules the key out
+            let get_entry = |max_timestamp| match may_contain {
+                Some(false) => Ok(None),
+                _ => {
+                    let entry = table.get(self.shared.comparator.as_ref(), key, max_timestamp)?;
+                    entry.map(|entry| self.shared.read_blob(column_family, &blob_files, entry)).transpose()
+                }
+            };
+            if self.table_versions(&mut versions, key, &mut max_timestamp, table.range_tombstones(), get_entry)? {
+                return Ok(versions);
+            }
+        }
 
-        return None;        
+        Ok(versions)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    #[test]
-    fn test_flow() {
-        let mut engine = Engine::new(
-            "/home/adarsh/my_files/personal/lsm-database-engine/sstable".to_owned(), 
-            1024
-        ).unwrap();
-        
-        for i in 1..60 {
-            println!("Iteration {}",i);
-            engine.set(i.to_string().as_bytes().to_vec(), (i+1).to_string().as_bytes().to_vec()).unwrap();
+    /// Adds the versions one memtable or sstable holds, see `versions_to_fold()`
+    /// Returns true once nothing older is needed, fails if the table can't be read
+    ///
+    /// The range tombstones of a table only cover its own and the older tables,
+    /// the newer ones were written after them
+    fn table_versions(
+        &self,
+        versions: &mut Vec<SSTableEntry>,
+        key: &[u8],
+        max_timestamp: &mut u128,
+        range_tombstones: &[RangeTombstone],
+        get_entry: impl Fn(u128) -> Result<Option<SSTableEntry>>
+    ) -> Result<bool> {
+        let deleted_at =
+            RangeTombstone::newest_covering(self.shared.comparator.as_ref(), range_tombstones, key, *max_timestamp);
+        loop {
+            // None is smaller than any timestamp
+            let entry = get_entry(*max_timestamp)?.filter(|entry| Some(entry.timestamp) > deleted_at);
+            match (entry, deleted_at) {
+                (Some(entry), _) => match Self::push_version(versions, entry) {
+                    Some(older) => *max_timestamp = older,
+                    None => return Ok(true),
+                },
+                (None, Some(deleted_at)) => {
+                    versions.push(SSTableEntry::new(key.to_vec(), vec![], deleted_at, true));
+                    return Ok(true);
+                }
+                (None, None) => return Ok(false),
+            }
+        }
+    }
+
+    /// Adds the version, returns the timestamp to look for the next older one
+    /// or None once there is nothing left to fold
+    fn push_version(versions: &mut Vec<SSTableEntry>, entry: SSTableEntry) -> Option<u128> {
+        let older = match entry.merge {
+            true => entry.timestamp.checked_sub(1),
+            false => None,
+        };
+        versions.push(entry);
+        older
+    }
+
+    /// Merges the range of every memtable and sstable and keeps what a reader at `max_timestamp` sees
+    fn scan_versioned<R: RangeBounds<Vec<u8>>>(
+        &self,
+        column_family: u32,
+        range: R,
+        max_timestamp: u128
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.scan_tables(column_family, range, max_timestamp, None)
+    }
+
+    /// Same as `scan_versioned()` over the keys starting with `prefix`
+    fn scan_prefix_versioned(
+        &self,
+        column_family: u32,
+        prefix: &[u8],
+        max_timestamp: u128
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let range = self.shared.comparator.prefix_range(prefix);
+        let mut entries = self.scan_tables(column_family, range, max_timestamp, Some(prefix))?;
+        entries.retain(|(key, _)| key.starts_with(prefix));
+        Ok(entries)
+    }
+
+    /// See `scan_versioned()`, with a prefix the sstables whose prefix filter doesn't have it are not read
+    fn scan_tables<R: RangeBounds<Vec<u8>>>(
+        &self,
+        column_family: u32,
+        range: R,
+        max_timestamp: u128,
+        prefix: Option<&[u8]>
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+
+        let mut runs = vec![];
+        let mut range_tombstones = vec![];
+        {
+            let current = self.current.read().unwrap();
+            let mem_table = current
+                .mem_tables
+                .get(&column_family)
+                .ok_or_else(|| Error::NotFound(format!("column family {}", column_family)))?;
+            runs.push(mem_table.range_entries(range.clone()));
+            range_tombstones.extend_from_slice(mem_table.range_tombstones());
+        }
+        let (immutable_mem_tables, ss_tables, blob_files) = self.table_lists(column_family)?;
+        for mem_table in immutable_mem_tables.iter().filter_map(|mem_tables| mem_tables.get(&column_family)) {
+            runs.push(mem_table.range_entries(range.clone()));
+            range_tombstones.extend_from_slice(mem_table.range_tombstones());
         }
+        for file in ss_tables {
+            let table = self.table_reader(&file)?;
+            // may still hide keys of the older tables even when none of its keys are read
+            range_tombstones.extend_from_slice(table.range_tombstones());
+            if let Some(prefix) = prefix {
+                let may_contain = table.may_contain_prefix(prefix);
+                self.shared.add_filter_check(may_contain);
+                if may_contain == Some(false) {
+                    continue;
+                }
+            }
+            runs.push(table.range(self.shared.comparator.as_ref(), range.clone())?);
+        }
+
+        let merge_operator = self.merge_operator(column_family);
+        let comparator = self.shared.comparator.as_ref();
+        VisibleIterator::new(MergeIterator::new(runs, comparator), max_timestamp, merge_operator)
+            .with_range_tombstones(range_tombstones, comparator)
+            .with_blob_reader(|entry| self.shared.read_blob(column_family, &blob_files, entry))
+            .collect()
+    }
+
+    fn table_lists(&self, column_family: u32) -> Result<TableLists> {
+        let tables = self.shared.tables.lock().unwrap();
+        let column_family = tables
+            .column_families
+            .get(&column_family)
+            .ok_or_else(|| Error::NotFound(format!("column family {}", column_family)))?;
+        Ok((tables.immutable_mem_tables.clone(), column_family.ss_tables.clone(), column_family.blob_files.clone()))
+    }
+
+    /// Open reader of the blocks of the table, for the point lookups and the scans which don't need the whole table
+    fn table_reader(&self, file: &SSTableFile) -> Result<Arc<TableReader>> {
+        let options = self.shared.options.read().unwrap().table_reader_options();
+        self.shared.table_cache.reader(file, &options)
     }
-}
\ No newline at end of file
+
+    /// Merge operator of the column family, if any
+    fn merge_operator(&self, column_family: u32) -> Option<Arc<dyn MergeOperator>> {
+        let options = self.shared.column_family_options.read().unwrap();
+        options.get(&column_family).and_then(|options| options.merge_operator.clone())
+    }
+
+    /// Fails with `Error::InvalidArgument` for a range deletion with `start >= end`,
+    /// or with `Error::NotFound` for a column family which doesn't exist, before anything reaches the log
+    fn check_batch(&self, batch: &WriteBatch) -> Result<()> {
+        for (column_family, entry) in batch.entries() {
+            self.check_column_family(*column_family)?;
+            if let BatchEntry::DeleteRange { start, end } = entry {
+                if !self.shared.comparator.compare(start, end).is_lt() {
+                    return Err(Error::InvalidArgument("delete_range needs start < end".to_owned()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_column_family(&self, column_family: u32) -> Result<()> {
+        if !self.current.read().unwrap().mem_tables.contains_key(&column_family) {
+            return Err(Error::NotFound(format!("column family {}", column_family)));
+        }
+        Ok(())
+    }
+
+    /// Commits the batches in order, freezing the memtable whenever the next one doesn't fit
+    fn write_group(&self, writer: &mut Writer, batches: Vec<WriteBatch>) -> Result<()> {
+        let mut records = vec![];
+        let mem_table_size = self.shared.options.read().unwrap().mem_table_size;
+        // the column families without a memtable size of their own share the one of the engine, keyed by None
+        let own_sizes: HashMap<u32, usize> = {
+            let options = self.shared.column_family_options.read().unwrap();
+            options.iter().filter_map(|(id, options)| Some((*id, options.mem_table_size?))).collect()
+        };
+        let budget = |id: u32| own_sizes.get(&id).map_or((None, mem_table_size), |size| (Some(id), *size));
+        // only the writer changes the memtable, its size can't move under us
+        let mut used: HashMap<Option<u32>, usize> = HashMap::new();
+        for (id, mem_table) in &self.current.read().unwrap().mem_tables {
+            *used.entry(budget(*id).0).or_default() += mem_table.size;
+        }
+
+        for batch in batches {
+            if batch.is_empty() {
+                continue;
+            }
+            let mut batch_sizes: HashMap<Option<u32>, (usize, usize)> = HashMap::new();
+            for (id, entry) in batch.entries() {
+                let (key, size) = budget(*id);
+                batch_sizes.entry(key).or_insert((size, 0)).1 += Self::entry_size(entry);
+            }
+            let full = batch_sizes.iter().any(|(key, (size, batch_size))| {
+                let used = used.get(key).copied().unwrap_or_default();
+                *size < used + batch_size && used > 0
+            });
+            if full {
+                self.commit_records(writer, mem::take(&mut records))?;
+                self.freeze_mem_table(writer)?;
+                used.clear();
+            }
+
+            let timestamp = writer.next_timestamp();
+            writer.last_timestamp += batch.len() as u128 - 1;
+            records.push(WalRecord { timestamp, batch });
+            for (key, (_, batch_size)) in batch_sizes {
+                *used.entry(key).or_default() += batch_size;
+            }
+        }
+
+        self.commit_records(writer, records)
+    }
+
+    /// Appends the records to the log and applies them to the memtable,
+    /// readers see every record of the group at once
+    fn commit_records(&self, writer: &mut Writer, records: Vec<WalRecord>) -> Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        writer.wal.append_all(&records)?;
+        let (wal_sync_mode, multi_version) = {
+            let options = self.shared.options.read().unwrap();
+            (options.wal_sync_mode, options.retention_window.is_some())
+        };
+        if wal_sync_mode == WalSyncMode::EveryWrite {
+            writer.wal.sync()?;
+        }
+
+        let mut current = self.current.write().unwrap();
+        let newest_reader = match multi_version {
+            true => Some(u128::MAX),
+            false => self.shared.snapshots.newest(),
+        };
+        for record in records {
+            let last_sequence = record.timestamp + record.batch.len() as u128 - 1;
+            Self::apply(&mut current.mem_tables, record, newest_reader)?;
+            current.last_sequence = last_sequence;
+        }
+        Ok(())
+    }
+
+    /// Applies the entries of the record in order, entry `i` gets `record.timestamp + i`
+    /// Older versions are kept when `newest_snapshot` may read them
+    ///
+    /// Entries of a column family missing from `mem_tables` were dropped with it and are skipped
+    fn apply(mem_tables: &mut MemTables, record: WalRecord, newest_snapshot: Option<u128>) -> Result<()> {
+        for (i, (column_family, entry)) in record.batch.into_entries().into_iter().enumerate() {
+            let timestamp = record.timestamp + i as u128;
+            let Some(mem_table) = mem_tables.get_mut(&column_family) else {
+                continue;
+            };
+            match entry {
+                BatchEntry::Set { key, value } => mem_table.set(key, value, timestamp, newest_snapshot)?,
+                BatchEntry::Delete { key } => mem_table.delete(key, timestamp, newest_snapshot)?,
+                BatchEntry::Merge { key, operand } => mem_table.merge(key, operand, timestamp)?,
+                BatchEntry::SetWithTtl { key, value, expires_at } => {
+                    mem_table.set_with_expiry(key, value, expires_at, timestamp, newest_snapshot)?
+                }
+                BatchEntry::DeleteRange { start, end } => mem_table.delete_range(start, end, timestamp)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Upper bound of the memtable space the entry takes
+    fn entry_size(entry: &BatchEntry) -> usize {
+        match entry {
+            BatchEntry::Set { key, value } => MemTable::get_max_entry_size(key, value),
+            BatchEntry::Delete { key } => MemTable::get_max_entry_size(key, &[]),
+            BatchEntry::Merge { key, operand } => MemTable::get_max_entry_size(key, operand),
+            BatchEntry::SetWithTtl { key, value, .. } => MemTable::get_max_entry_size(key, value),
+            BatchEntry::DeleteRange { start, end } => MemTable::get_max_entry_size(start, end),
+        }
+    }
+
+    /// Moves the current memtable to the front of the immutable memtables,
+    /// hands it over to the background thread and starts a new log for the next memtable
+    ///
+    /// Stalls the write while `max_immutable_mem_tables` are already waiting
+    fn freeze_mem_table(&self, writer: &mut Writer) -> Result<()> {
+        let max_immutable_mem_tables = self.shared.options.read().unwrap().max_immutable_mem_tables;
+        let mut tables = self.shared.tables.lock().unwrap();
+        while tables.immutable_mem_tables.len() >= max_immutable_mem_tables
+            && tables.background_error.is_none()
+        {
+            tables = self.shared.flushed.wait(tables).unwrap();
+        }
+        if let Some(err) = &tables.background_error {
+            return Err(err.clone());
+        }
+
+        let timestamp = writer.next_timestamp();
+        let wal_timestamp = writer.next_timestamp();
+        let wal = Wal::create(&self.shared.ss_table_dir, wal_timestamp)?;
+        let wal_file = mem::replace(&mut writer.wal, wal).path().to_path_buf();
+
+        let mem_tables = {
+            let mut current = self.current.write().unwrap();
+            let empty = current
+                .mem_tables
+                .keys()
+                .map(|id| (*id, MemTable::with_comparator(self.shared.comparator.clone())))
+                .collect();
+            Arc::new(mem::replace(&mut current.mem_tables, empty))
+        };
+        tables.immutable_mem_tables.push_front(mem_tables.clone());
+        drop(tables);
+
+        let job = FlushJob {
+            mem_tables,
+            timestamp,
+            wal_file,
+        };
+        self.flush_sender
+            .as_ref()
+            .unwrap()
+            .send(job)
+            .map_err(|_| Busy::Stopped("background flush thread has stopped".to_owned()))?;
+
+        Ok(())
+    }
+
+    /// Runs in the background flush thread, writes the memtables in the order they were frozen
+    ///
+    /// Every column family gets its own sstable, registered in the manifest
+    /// before the memtables are dropped from the immutable list, so readers always find the entries in one of them
+    /// A column family left with too many sstables is handed over to the compaction thread
+    fn flush_worker(shared: Arc<Shared>, receiver: Receiver<FlushJob>, compaction_sender: Sender<()>) {
+        let dir = &shared.ss_table_dir;
+        for job in receiver {
+            let result: Result<Vec<(u32, PathBuf, Option<PathBuf>)>> = job
+                .mem_tables
+                .iter()
+                .filter(|(_, mem_table)| mem_table.size > 0)
+                .map(|(id, mem_table)| {
+                    let column_family_dir = ColumnFamily::dir(dir, *id);
+                    let (file_name, blob_file, stats) =
+                        mem_table.flush(&column_family_dir, job.timestamp, &shared.table_options(*id, 0))?;
+                    shared.add_compression_stats(&stats);
+                    Ok((*id, file_name, blob_file))
+                })
+                .collect();
+
+            let mut tables = shared.tables.lock().unwrap();
+            let result = result.and_then(|file_names| {
+                let mut column_families = tables.column_families.clone();
+                for (id, file_name, blob_file) in file_names {
+                    let Some(column_family) = column_families.get_mut(&id) else {
+                        // dropped after the memtable was frozen
+                        fs::remove_file(file_name)?;
+                        if let Some(blob_file) = blob_file {
+                            fs::remove_file(blob_file)?;
+                        }
+                        continue;
+                    };
+                    column_family.ss_tables.push(shared.ss_table_file(file_name));
+                    if let Some(blob_file) = blob_file {
+                        let blob_file = BlobFile::open(blob_file)?;
+                        column_family.blob_files.insert(blob_file.timestamp(), Arc::new(blob_file));
+                    }
+                }
+                let last_flushed_wal = Wal::file_timestamp(&job.wal_file).unwrap_or(tables.last_flushed_wal);
+                Self::store_manifest(
+                    dir,
+                    &column_families,
+                    &shared.column_family_options.read().unwrap(),
+                    tables.next_column_family_id,
+                    last_flushed_wal,
+                    shared.comparator.name()
+                )?;
+                tables.column_families = column_families;
+                tables.last_flushed_wal = last_flushed_wal;
+                if let CompactionStyle::Automatic { max_tables } = shared.options.read().unwrap().compaction_style {
+                    if tables.column_families.values().any(|cf| cf.ss_tables.len() >= max_tables) {
+                        // the compaction thread stopped after a failure, the error is already reported
+                        let _ = compaction_sender.send(());
+                    }
+                }
+                Ok(())
+            });
+            let failed = match result {
+                Ok(()) => {
+                    // oldest memtable is at the back
+                    tables.immutable_mem_tables.pop_back();
+                    // a log left behind is skipped on the next open, the manifest says it is flushed
+                    let _ = fs::remove_file(&job.wal_file);
+                    false
+                }
+                Err(err) => {
+                    // keep the memtable in the list, its entries are still readable
+                    tables.background_error = Some(err);
+                    true
+                }
+            };
+            shared.flushed.notify_all();
+
+            if failed {
+                return;
+            }
+        }
+    }
+
+    /// Runs in the background compaction thread, merges the column families the flush thread reports,
+    /// see `CompactionStyle::Automatic`
+    ///
+    /// A failure is reported like a failed flush and stops the thread
+    fn compaction_worker(shared: Arc<Shared>, receiver: Receiver<()>) {
+        while receiver.recv().is_ok() {
+            // flushes done meanwhile are covered by this round
+            while receiver.try_recv().is_ok() {}
+            if let Err(err) = shared.compact_due() {
+                shared.tables.lock().unwrap().background_error = Some(err);
+                shared.flushed.notify_all();
+                return;
+            }
+        }
+    }
+
+    /// Lists the column families with their tables and the options which can be stored
+    fn store_manifest(
+        dir: &Path,
+        column_families: &BTreeMap<u32, ColumnFamilyTables>,
+        column_family_options: &HashMap<u32, ColumnFamilyOptions>,
+        next_column_family_id: u32,
+        last_flushed_wal: u128,
+        comparator: &str
+    ) -> Result<()> {
+        let mut manifest = Manifest::new(last_flushed_wal, comparator, next_column_family_id);
+        for (id, column_family) in column_families {
+            let file_names: Vec<PathBuf> =
+                column_family.ss_tables.iter().map(|file| file.path().to_path_buf()).collect();
+            let blob_files: Vec<PathBuf> =
+                column_family.blob_files.values().map(|blob_file| blob_file.path().to_path_buf()).collect();
+            let options = column_family_options.get(id).cloned().unwrap_or_default();
+            manifest.add_column_family(*id, &column_family.name, &options, &file_names, &blob_files);
+        }
+        manifest.store(dir)
+    }
+
+    /// Closing the channel stops the flush thread once it is done with the queued memtables,
+    /// the compaction thread follows once the flush thread is gone
+    fn stop_background_threads(&mut self) {
+        drop(self.flush_sender.take());
+        if let Some(flush_thread) = self.flush_thread.take() {
+            let _ = flush_thread.join();
+        }
+        if let Some(compaction_thread) = self.compaction_thread.take() {
+            let _ = compaction_thread.join();
+        }
+    }
+
+    /// Log of a flushed memtable has nothing to replay
+    fn remove_empty_wal(&mut self) -> Result<()> {
+        if self.current.get_mut().unwrap().size() == 0 {
+            fs::remove_file(self.shared.writer.lock().unwrap().wal.path())?;
+        }
+        Ok(())
+    }
+
+    /// Files of one kind in the directory with their timestamps, oldest first
+    fn find_files(
+        path: &Path,
+        file_timestamp: fn(&Path) -> Option<u128>
+    ) -> Result<Vec<(u128, PathBuf)>> {
+        let mut files = vec![];
+        for entry in fs::read_dir(path)? {
+            let file_name = entry?.path();
+            if let Some(timestamp) = file_timestamp(&file_name) {
+                files.push((timestamp, file_name));
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Micro seconds since the unix epoch
+    fn now() -> u128 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_micros()
+    }
+}
+
+impl Drop for Engine {
+    /// Best effort flush of the current memtable, errors can't be reported from here,
+    /// use `close()` to know if everything reached the disk
+    fn drop(&mut self) {
+        // already closed
+        if self.flush_thread.is_none() {
+            return;
+        }
+        let _ = self.flush();
+        self.stop_background_threads();
+        let _ = self.remove_empty_wal();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    /// Fresh directory for every test, tests run in parallel
+    fn test_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("simpledb-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.to_str().unwrap().to_owned()
+    }
+
+    fn ss_table_count(dir: &str) -> usize {
+        Engine::find_files(Path::new(dir), SSTable::file_timestamp).unwrap().len()
+    }
+
+    #[test]
+    fn test_flow() {
+        let engine = Engine::new(
+            test_dir("test-flow"),
+            1024,
+            2
+        ).unwrap();
+
+        for i in 1..60 {
+            println!("Iteration {}",i);
+            engine.set(i.to_string().as_bytes().to_vec(), (i+1).to_string().as_bytes().to_vec()).unwrap();
+        }
+    }
+
+    #[test]
+    fn get_while_flushing() {
+        let engine = Engine::new(test_dir("get-while-flushing"), 1024, 2).unwrap();
+
+        for i in 1..200 {
+            engine.set(i.to_string().as_bytes().to_vec(), (i+1).to_string().as_bytes().to_vec()).unwrap();
+            assert!(engine.immutable_mem_table_count() <= 2);
+
+            // everything written so far is readable, wherever it lives now
+            for j in (1..=i).step_by(17) {
+                let value = engine.get(j.to_string().as_bytes().to_vec()).unwrap();
+                assert_eq!(value, Some((j+1).to_string().as_bytes().to_vec()));
+            }
+        }
+        assert_eq!(engine.get("0".as_bytes().to_vec()).unwrap(), None);
+    }
+
+    #[test]
+    fn newest_value_wins() {
+        let engine = Engine::new(test_dir("newest-value-wins"), 512, 1).unwrap();
+
+        for round in 0..5 {
+            for i in 0..20 {
+                engine.set(vec![i], vec![i, round]).unwrap();
+            }
+        }
+        for i in 0..20 {
+            assert_eq!(engine.get(vec![i]).unwrap(), Some(vec![i, 4]));
+        }
+    }
+
+    #[test]
+    fn reopen_reads_flushed_tables() {
+        let dir = test_dir("reopen-reads-flushed-tables");
+        {
+            let engine = Engine::new(dir.clone(), 512, 1).unwrap();
+            for i in 0..50 {
+                engine.set(vec![i], vec![i + 1]).unwrap();
+            }
+            // dropping writes the queued memtables and the current one
+        }
+
+        let engine = Engine::new(dir, 512, 1).unwrap();
+        assert_eq!(engine.get(vec![0]).unwrap(), Some(vec![1]));
+        assert_eq!(engine.get(vec![20]).unwrap(), Some(vec![21]));
+        assert_eq!(engine.get(vec![49]).unwrap(), Some(vec![50]));
+    }
+
+    #[test]
+    fn flush_writes_current_mem_table() {
+        let dir = test_dir("flush-writes-current-mem-table");
+        let engine = Engine::new(dir.clone(), 1024, 2).unwrap();
+        engine.set(vec![1], vec![2]).unwrap();
+
+        assert_eq!(ss_table_count(&dir), 0);
+        engine.flush().unwrap();
+        assert_eq!(ss_table_count(&dir), 1);
+        assert_eq!(engine.immutable_mem_table_count(), 0);
+        assert_eq!(engine.get(vec![1]).unwrap(), Some(vec![2]));
+
+        // nothing new to write
+        engine.flush().unwrap();
+        assert_eq!(ss_table_count(&dir), 1);
+    }
+
+    #[test]
+    fn close_keeps_everything() {
+        let dir = test_dir("close-keeps-everything");
+        let engine = Engine::new(dir.clone(), 512, 1).unwrap();
+        for i in 0..50 {
+            engine.set(vec![i], vec![i + 1]).unwrap();
+        }
+        engine.close().unwrap();
+        assert!(Engine::find_files(Path::new(&dir), Wal::file_timestamp).unwrap().is_empty());
+
+        let engine = Engine::new(dir, 512, 1).unwrap();
+        for i in 0..50 {
+            assert_eq!(engine.get(vec![i]).unwrap(), Some(vec![i + 1]));
+        }
+    }
+
+    #[test]
+    fn drop_flushes_mem_table() {
+        let dir = test_dir("drop-flushes-mem-table");
+        {
+            let engine = Engine::new(dir.clone(), 1024, 2).unwrap();
+            engine.set(vec![1], vec![2]).unwrap();
+        }
+
+        let engine = Engine::new(dir, 1024, 2).unwrap();
+        assert_eq!(engine.get(vec![1]).unwrap(), Some(vec![2]));
+    }
+
+    #[test]
+    fn zero_immutable_mem_tables_rejected() {
+        assert!(Engine::new(test_dir("zero-immutable"), 512, 0).is_err());
+    }
+
+    #[test]
+    fn open_with_options() {
+        let dir = test_dir("open-with-options");
+        let missing = Options::builder().create_if_missing(false).build().unwrap();
+        assert!(matches!(Engine::open(dir.clone(), missing.clone()), Err(Error::NotFound(_))));
+
+        let options = Options::builder()
+            .mem_table_size(1024)
+            .wal_sync_mode(WalSyncMode::EveryWrite)
+            .block_size(256)
+            .build()
+            .unwrap();
+        let engine = Engine::open(dir.clone(), options).unwrap();
+        engine.set(vec![1], vec![1]).unwrap();
+        // crash, the synced log has the write
+        mem::forget(engine);
+
+        let exclusive = Options::builder().error_if_exists(true).build().unwrap();
+        assert!(matches!(Engine::open(dir.clone(), exclusive), Err(Error::InvalidArgument(_))));
+
+        let engine = Engine::open(dir.clone(), missing).unwrap();
+        assert_eq!(engine.get(vec![1]).unwrap(), Some(vec![1]));
+        // the options of the last open
+        let stored = Options::load(Path::new(&dir)).unwrap().unwrap();
+        assert_eq!((stored.create_if_missing, stored.wal_sync_mode), (false, WalSyncMode::Buffered));
+    }
+
+    #[test]
+    fn database_without_manifest_is_refused() {
+        let dir = test_dir("database-without-manifest-is-refused");
+        fs::create_dir_all(&dir).unwrap();
+        // a table of a database written before the manifest existed
+        fs::write(SSTable::file_name(Path::new(&dir), 1), b"old table").unwrap();
+        assert!(matches!(Engine::new(dir.clone(), 1024, 2), Err(Error::InvalidArgument(_))));
+
+        // anything else is a new database
+        fs::remove_file(SSTable::file_name(Path::new(&dir), 1)).unwrap();
+        fs::write(Path::new(&dir).join("notes"), b"").unwrap();
+        let engine = Engine::new(dir, 1024, 2).unwrap();
+        engine.set(vec![1], vec![1]).unwrap();
+        assert_eq!(engine.get(vec![1]).unwrap(), Some(vec![1]));
+    }
+
+    #[test]
+    fn write_batch() {
+        let engine = Engine::new(test_dir("write-batch"), 1024, 2).unwrap();
+        engine.set(vec![3], vec![3]).unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch
+            .set(vec![1], vec![1])
+            .set(vec![2], vec![2])
+            .delete(vec![3])
+            .set(vec![1], vec![10]);
+        engine.write(batch).unwrap();
+
+        assert_eq!(engine.get(vec![1]).unwrap(), Some(vec![10]));
+        assert_eq!(engine.get(vec![2]).unwrap(), Some(vec![2]));
+        assert_eq!(engine.get(vec![3]).unwrap(), None);
+    }
+
+    #[test]
+    fn batch_never_split_across_mem_tables() {
+        let dir = test_dir("batch-never-split");
+        let engine = Engine::new(dir.clone(), 512, 2).unwrap();
+        for i in 0..8 {
+            engine.set(vec![i], vec![i]).unwrap();
+        }
+
+        let mut batch = WriteBatch::new();
+        for i in 100..110 {
+            batch.set(vec![i], vec![i]);
+        }
+        engine.write(batch).unwrap();
+
+        // the whole batch went to the new memtable
+        for i in 100..110 {
+            assert!(engine.current.read().unwrap().mem_tables[&0].get_entry(&[i], u128::MAX).is_some());
+        }
+        for i in 0..8 {
+            assert!(engine.current.read().unwrap().mem_tables[&0].get_entry(&[i], u128::MAX).is_none());
+        }
+        let timestamps: Vec<u128> = (100..110)
+            .map(|i| engine.current.read().unwrap().mem_tables[&0].get_entry(&[i], u128::MAX).unwrap().timestamp)
+            .collect();
+        assert!(timestamps.windows(2).all(|pair| pair[1] == pair[0] + 1));
+    }
+
+    #[test]
+    fn delete_hides_flushed_value() {
+        let dir = test_dir("delete-hides-flushed-value");
+        let engine = Engine::new(dir.clone(), 1024, 2).unwrap();
+        engine.set(vec![1], vec![2]).unwrap();
+        engine.flush().unwrap();
+
+        engine.delete(vec![1]).unwrap();
+        assert_eq!(engine.get(vec![1]).unwrap(), None);
+        engine.flush().unwrap();
+        assert_eq!(engine.get(vec![1]).unwrap(), None);
+        engine.close().unwrap();
+
+        let engine = Engine::new(dir, 1024, 2).unwrap();
+        assert_eq!(engine.get(vec![1]).unwrap(), None);
+    }
+
+    #[test]
+    fn wal_replayed_after_crash() {
+        let dir = test_dir("wal-replayed-after-crash");
+        let engine = Engine::new(dir.clone(), 1024, 2).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.set(vec![1], vec![1]).set(vec![2], vec![2]);
+        engine.write(batch).unwrap();
+        engine.delete(vec![2]).unwrap();
+        // crash, nothing is flushed
+        mem::forget(engine);
+        assert_eq!(ss_table_count(&dir), 0);
+
+        let engine = Engine::new(dir.clone(), 1024, 2).unwrap();
+        assert_eq!(engine.get(vec![1]).unwrap(), Some(vec![1]));
+        assert_eq!(engine.get(vec![2]).unwrap(), None);
+        assert_eq!(ss_table_count(&dir), 1);
+    }
+
+    #[test]
+    fn snapshot_reads_old_versions() {
+        let dir = test_dir("snapshot-reads-old-versions");
+        let engine = Engine::new(dir, 1024, 2).unwrap();
+        engine.set(vec![1], vec![1]).unwrap();
+        engine.set(vec![2], vec![2]).unwrap();
+        let snapshot = engine.snapshot();
+
+        engine.set(vec![1], vec![10]).unwrap();
+        engine.delete(vec![2]).unwrap();
+        engine.set(vec![3], vec![3]).unwrap();
+
+        let check = |engine: &Engine| {
+            assert_eq!(engine.get_at(&snapshot, vec![1]).unwrap(), Some(vec![1]));
+            assert_eq!(engine.get_at(&snapshot, vec![2]).unwrap(), Some(vec![2]));
+            assert_eq!(engine.get_at(&snapshot, vec![3]).unwrap(), None);
+            assert_eq!(engine.get(vec![1]).unwrap(), Some(vec![10]));
+            assert_eq!(engine.get(vec![2]).unwrap(), None);
+        };
+        check(&engine);
+        engine.flush().unwrap();
+        check(&engine);
+        engine.compact().unwrap();
+        check(&engine);
+    }
+
+    #[test]
+    fn snapshot_sees_whole_batches() {
+        let engine = Engine::new(test_dir("snapshot-sees-whole-batches"), 1024, 2).unwrap();
+        let before = engine.snapshot();
+        let mut batch = WriteBatch::new();
+        batch.set(vec![1], vec![1]).set(vec![2], vec![2]);
+        engine.write(batch).unwrap();
+        let after = engine.snapshot();
+
+        assert_eq!(engine.scan_at(&before, ..).unwrap(), vec![]);
+        assert_eq!(engine.scan_at(&after, ..).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn scan_merges_every_table() {
+        let engine = Engine::new(test_dir("scan-merges-every-table"), 1024, 2).unwrap();
+        for i in 0..10u8 {
+            engine.set(vec![i], vec![i]).unwrap();
+        }
+        engine.flush().unwrap();
+        engine.set(vec![3], vec![30]).unwrap();
+        engine.delete(vec![4]).unwrap();
+
+        let scanned = engine.scan(vec![2]..vec![6]).unwrap();
+        assert_eq!(scanned, vec![(vec![2], vec![2]), (vec![3], vec![30]), (vec![5], vec![5])]);
+        assert_eq!(engine.scan(..).unwrap().len(), 9);
+    }
+
+    #[test]
+    fn compaction_drops_hidden_versions() {
+        let dir = test_dir("compaction-drops-hidden-versions");
+        let engine = Engine::new(dir.clone(), 1024, 2).unwrap();
+        for round in 0..3u8 {
+            for i in 0..5u8 {
+                engine.set(vec![i], vec![round]).unwrap();
+            }
+            engine.flush().unwrap();
+        }
+        engine.delete(vec![0]).unwrap();
+        engine.flush().unwrap();
+        assert_eq!(ss_table_count(&dir), 4);
+
+        engine.compact().unwrap();
+        assert_eq!(ss_table_count(&dir), 1);
+        let file_name = engine.shared.tables.lock().unwrap().column_families[&0].ss_tables[0].clone();
+        assert_eq!(SSTable::read(file_name.path(), true).unwrap().entries().len(), 4);
+        engine.close().unwrap();
+
+        let engine = Engine::new(dir, 1024, 2).unwrap();
+        assert_eq!(engine.get(vec![0]).unwrap(), None);
+        assert_eq!(engine.get(vec![4]).unwrap(), Some(vec![2]));
+    }
+
+    #[test]
+    fn get_as_of_reads_history() {
+        let dir = test_dir("get-as-of-reads-history");
+        let engine = Engine::new(dir.clone(), 1024, 2).unwrap();
+        assert!(engine.get_as_of(vec![1], 0).is_err());
+        engine.set_retention_window(Some(Duration::from_secs(3600)));
+
+        let mut timestamps = vec![];
+        for i in 0..3u8 {
+            engine.set(vec![1], vec![i]).unwrap();
+            timestamps.push(engine.snapshot().sequence());
+        }
+        engine.delete(vec![1]).unwrap();
+
+        let check = |engine: &Engine| {
+            assert_eq!(engine.get_as_of(vec![1], timestamps[0] - 1).unwrap(), None);
+            for (i, timestamp) in timestamps.iter().enumerate() {
+                assert_eq!(engine.get_as_of(vec![1], *timestamp).unwrap(), Some(vec![i as u8]));
+            }
+            assert_eq!(engine.get(vec![1]).unwrap(), None);
+        };
+        check(&engine);
+        engine.flush().unwrap();
+        engine.compact().unwrap();
+        check(&engine);
+
+        // everything is older than the window now
+        engine.set_retention_window(Some(Duration::ZERO));
+        engine.compact().unwrap();
+        let file_name = engine.shared.tables.lock().unwrap().column_families[&0].ss_tables[0].clone();
+        assert!(SSTable::read(file_name.path(), true).unwrap().entries().is_empty());
+    }
+
+    #[test]
+    fn conditional_writes() {
+        let engine = Engine::new(test_dir("conditional-writes"), 1024, 2).unwrap();
+        assert!(engine.set_if_absent(vec![1], vec![1]).unwrap());
+        assert!(!engine.set_if_absent(vec![1], vec![2]).unwrap());
+        engine.flush().unwrap();
+
+        // checked against the flushed value
+        assert!(!engine.compare_and_set(vec![1], Some(&[2]), vec![3]).unwrap());
+        assert!(engine.compare_and_set(vec![1], Some(&[1]), vec![3]).unwrap());
+        assert_eq!(engine.get(vec![1]).unwrap(), Some(vec![3]));
+
+        assert!(!engine.delete_if_equals(vec![1], &[1]).unwrap());
+        assert!(engine.delete_if_equals(vec![1], &[3]).unwrap());
+        assert_eq!(engine.get(vec![1]).unwrap(), None);
+        // a deleted key is absent
+        assert!(engine.compare_and_set(vec![1], None, vec![4]).unwrap());
+        assert_eq!(engine.get(vec![1]).unwrap(), Some(vec![4]));
+    }
+
+    #[test]
+    fn merge_operands_folded() {
+        use crate::engine::merge_operator::merge_operator::{AppendOperator, U64AddOperator};
+        let dir = test_dir("merge-operands-folded");
+        let engine = Engine::new(dir.clone(), 1024, 2).unwrap();
+        assert!(engine.merge(vec![1], 1u64.to_le_bytes().to_vec()).is_err());
+        engine.set_merge_operator(Arc::new(U64AddOperator));
+
+        let count = |engine: &Engine| engine.get(vec![1]).unwrap().map(|value| u64::from_le_bytes(value.try_into().unwrap()));
+        engine.set(vec![1], 10u64.to_le_bytes().to_vec()).unwrap();
+        engine.merge(vec![1], 1u64.to_le_bytes().to_vec()).unwrap();
+        engine.flush().unwrap();
+        let snapshot = engine.snapshot();
+        engine.merge(vec![1], 2u64.to_le_bytes().to_vec()).unwrap();
+        engine.merge(vec![2], 5u64.to_le_bytes().to_vec()).unwrap();
+        assert_eq!(count(&engine), Some(13));
+        assert_eq!(engine.scan(..).unwrap()[1], (vec![2], 5u64.to_le_bytes().to_vec()));
+
+        engine.flush().unwrap();
+        engine.compact().unwrap();
+        assert_eq!(count(&engine), Some(13));
+        assert_eq!(engine.get_at(&snapshot, vec![1]).unwrap(), Some(11u64.to_le_bytes().to_vec()));
+        drop(snapshot);
+
+        engine.delete(vec![1]).unwrap();
+        engine.merge(vec![1], 4u64.to_le_bytes().to_vec()).unwrap();
+        assert_eq!(count(&engine), Some(4));
+        engine.close().unwrap();
+
+        // the operator isn't stored, the operands can't be read without it
+        let engine = Engine::new(dir, 1024, 2).unwrap();
+        assert!(engine.get(vec![1]).is_err());
+        assert!(engine.scan(..).is_err());
+        engine.set_merge_operator(Arc::new(AppendOperator::new(vec![])));
+        assert_eq!(engine.get(vec![3]).unwrap(), None);
+    }
+
+    #[test]
+    fn expired_keys_read_as_missing() {
+        let dir = test_dir("expired-keys-read-as-missing");
+        let engine = Engine::new(dir.clone(), 1024, 2).unwrap();
+        engine.set(vec![1], vec![1]).unwrap();
+        engine.flush().unwrap();
+        engine.set_with_ttl(vec![1], vec![2], Duration::from_millis(50)).unwrap();
+        engine.set_with_ttl(vec![2], vec![2], Duration::from_secs(3600)).unwrap();
+        assert_eq!(engine.get(vec![1]).unwrap(), Some(vec![2]));
+
+        thread::sleep(Duration::from_millis(60));
+        // the expired value hides the older one as well
+        assert_eq!(engine.get(vec![1]).unwrap(), None);
+        assert_eq!(engine.scan(..).unwrap(), vec![(vec![2], vec![2])]);
+        assert!(engine.set_if_absent(vec![1], vec![3]).unwrap());
+        engine.delete(vec![1]).unwrap();
+
+        engine.flush().unwrap();
+        assert_eq!(engine.get(vec![1]).unwrap(), None);
+        engine.compact().unwrap();
+        let file_name = engine.shared.tables.lock().unwrap().column_families[&0].ss_tables[0].clone();
+        assert_eq!(SSTable::read(file_name.path(), true).unwrap().entries().len(), 1);
+        engine.close().unwrap();
+
+        let engine = Engine::new(dir, 1024, 2).unwrap();
+        assert_eq!(engine.get(vec![2]).unwrap(), Some(vec![2]));
+    }
+
+    #[test]
+    fn compaction_filters_applied() {
+        use crate::engine::compaction::compaction_filter::FilterDecision;
+        struct Expire;
+        impl CompactionFilter for Expire {
+            fn name(&self) -> &str {
+                "expire"
+            }
+            fn filter(&self, key: &[u8], value: &[u8]) -> FilterDecision {
+                match key.starts_with(b"tmp") {
+                    true => FilterDecision::Remove,
+                    false if value.is_empty() => FilterDecision::ChangeValue(b"default".to_vec()),
+                    false => FilterDecision::Keep,
+                }
+            }
+        }
+
+        let engine = Engine::new(test_dir("compaction-filters-applied"), 1024, 2).unwrap();
+        engine.add_compaction_filter(Arc::new(Expire));
+        engine.set(b"tmp1".to_vec(), vec![1]).unwrap();
+        engine.set(b"a".to_vec(), vec![]).unwrap();
+        engine.set(b"b".to_vec(), vec![2]).unwrap();
+        engine.flush().unwrap();
+        engine.compact().unwrap();
+
+        assert_eq!(engine.get(b"tmp1".to_vec()).unwrap(), None);
+        assert_eq!(engine.get(b"a".to_vec()).unwrap(), Some(b"default".to_vec()));
+        assert_eq!(engine.get(b"b".to_vec()).unwrap(), Some(vec![2]));
+
+        engine.set(b"tmp2".to_vec(), vec![1]).unwrap();
+        engine.flush().unwrap();
+        engine.compact().unwrap();
+        let stats = engine.compaction_filter_stats();
+        assert_eq!(stats.len(), 1);
+        // the removed key left nothing behind, a and b are seen by both compactions
+        assert_eq!((stats[0].examined, stats[0].removed, stats[0].changed), (6, 2, 1));
+    }
+
+    #[test]
+    fn delete_range_hides_covered_keys() {
+        let dir = test_dir("delete-range-hides-covered-keys");
+        let engine = Engine::new(dir.clone(), 1024, 2).unwrap();
+        for i in 0..6u8 {
+            engine.set(vec![i], vec![i]).unwrap();
+        }
+        engine.flush().unwrap();
+        engine.set(vec![2], vec![20]).unwrap();
+        let snapshot = engine.snapshot();
+        assert!(matches!(engine.delete_range(vec![3], vec![1]), Err(Error::InvalidArgument(_))));
+        // an empty range in a batch fails the whole batch before it reaches the log
+        let mut batch = WriteBatch::new();
+        batch.set(vec![9], vec![9]).delete_range(vec![3], vec![3]);
+        assert!(matches!(engine.write(batch), Err(Error::InvalidArgument(_))));
+        assert_eq!(engine.get(vec![9]).unwrap(), None);
+        engine.delete_range(vec![1], vec![4]).unwrap();
+        engine.set(vec![3], vec![30]).unwrap();
+
+        let keys = |entries: Vec<(Vec<u8>, Vec<u8>)>| -> Vec<(u8, u8)> {
+            entries.iter().map(|(key, value)| (key[0], value[0])).collect()
+        };
+        assert_eq!(engine.get(vec![1]).unwrap(), None);
+        assert_eq!(engine.get(vec![2]).unwrap(), None);
+        assert_eq!(engine.get(vec![3]).unwrap(), Some(vec![30]));
+        assert_eq!(keys(engine.scan(..).unwrap()), vec![(0, 0), (3, 30), (4, 4), (5, 5)]);
+        assert_eq!(engine.get_at(&snapshot, vec![2]).unwrap(), Some(vec![20]));
+        assert_eq!(keys(engine.scan_at(&snapshot, ..).unwrap()).len(), 6);
+
+        // crash, the tombstone is replayed from the log, then read back from the sstable
+        drop(snapshot);
+        mem::forget(engine);
+        let engine = Engine::new(dir.clone(), 1024, 2).unwrap();
+        assert_eq!(keys(engine.scan(..).unwrap()), vec![(0, 0), (3, 30), (4, 4), (5, 5)]);
+        engine.flush().unwrap();
+        assert_eq!(engine.get(vec![1]).unwrap(), None);
+
+        engine.compact().unwrap();
+        let file_name = engine.shared.tables.lock().unwrap().column_families[&0].ss_tables[0].clone();
+        let ss_table = SSTable::read(file_name.path(), true).unwrap();
+        assert_eq!(ss_table.entries().len(), 4);
+        assert!(ss_table.range_tombstones().is_empty());
+        assert_eq!(keys(engine.scan(..).unwrap()), vec![(0, 0), (3, 30), (4, 4), (5, 5)]);
+    }
+
+    #[test]
+    fn keys_sorted_by_comparator() {
+        use crate::engine::comparator::comparator::ReverseBytewiseComparator;
+        let dir = test_dir("keys-sorted-by-comparator");
+        let engine = Engine::with_comparator(dir.clone(), 1024, 2, Arc::new(ReverseBytewiseComparator)).unwrap();
+        for i in 0..6u8 {
+            engine.set(vec![i], vec![i]).unwrap();
+            if i % 2 == 1 {
+                engine.flush().unwrap();
+            }
+        }
+        engine.delete_range(vec![4], vec![2]).unwrap();
+
+        let keys = |engine: &Engine| -> Vec<u8> { engine.scan(..).unwrap().iter().map(|(key, _)| key[0]).collect() };
+        assert_eq!(keys(&engine), vec![5, 2, 1, 0]);
+        engine.flush().unwrap();
+        engine.compact().unwrap();
+        assert_eq!(keys(&engine), vec![5, 2, 1, 0]);
+        assert_eq!(engine.scan(vec![2]..=vec![1]).unwrap().len(), 2);
+        assert_eq!(engine.get(vec![3]).unwrap(), None);
+        engine.close().unwrap();
+
+        // the order of the files would not match
+        assert!(Engine::new(dir.clone(), 1024, 2).is_err());
+        let engine = Engine::with_comparator(dir, 1024, 2, Arc::new(ReverseBytewiseComparator)).unwrap();
+        assert_eq!(engine.get(vec![1]).unwrap(), Some(vec![1]));
+    }
+
+    #[test]
+    fn column_families_are_separate_keyspaces() {
+        let dir = test_dir("column-families-are-separate-keyspaces");
+        let engine = Engine::new(dir.clone(), 1024, 2).unwrap();
+        let users = engine.create_column_family("users", ColumnFamilyOptions::default()).unwrap();
+        let sessions = engine.create_column_family("sessions", ColumnFamilyOptions::default()).unwrap();
+        assert!(engine.create_column_family("users", ColumnFamilyOptions::default()).is_err());
+
+        engine.set(vec![1], vec![0]).unwrap();
+        engine.set_cf(&users, vec![1], vec![1]).unwrap();
+        // one record in the log, both or neither
+        let mut batch = WriteBatch::new();
+        batch.set_cf(&users, vec![2], vec![2]);
+        batch.set_cf(&sessions, vec![2], vec![3]);
+        batch.delete_cf(&users, vec![1]);
+        engine.write(batch).unwrap();
+
+        assert_eq!(engine.get(vec![1]).unwrap(), Some(vec![0]));
+        assert_eq!(engine.get_cf(&users, vec![1]).unwrap(), None);
+        assert_eq!(engine.scan_cf(&users, ..).unwrap(), vec![(vec![2], vec![2])]);
+        assert_eq!(engine.scan_cf(&sessions, ..).unwrap(), vec![(vec![2], vec![3])]);
+        engine.flush().unwrap();
+        engine.compact().unwrap();
+        assert_eq!(engine.get_cf(&sessions, vec![2]).unwrap(), Some(vec![3]));
+        assert!(Path::new(&dir).join(format!("cf-{}", sessions.id())).exists());
+        // replayed from the log on the next open
+        engine.set_cf(&sessions, vec![4], vec![4]).unwrap();
+        mem::forget(engine);
+
+        let engine = Engine::new(dir.clone(), 1024, 2).unwrap();
+        let names: Vec<String> = engine.column_families().iter().map(|cf| cf.name().to_owned()).collect();
+        assert_eq!(names, vec!["default", "users", "sessions"]);
+        let sessions = engine.column_family("sessions").unwrap();
+        assert_eq!(engine.scan_cf(&sessions, ..).unwrap(), vec![(vec![2], vec![3]), (vec![4], vec![4])]);
+
+        engine.drop_column_family(&sessions).unwrap();
+        assert!(matches!(engine.get_cf(&sessions, vec![2]), Err(Error::NotFound(_))));
+        assert!(engine.set_cf(&sessions, vec![2], vec![2]).is_err());
+        assert!(engine.column_family("sessions").is_none());
+        let default = engine.column_family("default").unwrap();
+        assert!(matches!(engine.drop_column_family(&default), Err(Error::InvalidArgument(_))));
+        assert!(!Path::new(&dir).join(format!("cf-{}", sessions.id())).exists());
+        // ids are never reused
+        let sessions_again = engine.create_column_family("sessions", ColumnFamilyOptions::default()).unwrap();
+        assert_ne!(sessions_again.id(), sessions.id());
+        assert_eq!(engine.get_cf(&sessions_again, vec![2]).unwrap(), None);
+        assert_eq!(engine.get_cf(&engine.column_family("users").unwrap(), vec![2]).unwrap(), Some(vec![2]));
+    }
+
+    #[test]
+    fn queued_writes_commit_in_one_group() {
+        let engine = Engine::new(test_dir("queued-writes-commit-in-one-group"), 1024 * 1024, 2).unwrap();
+        thread::scope(|scope| {
+            // the writes queue up behind the held writer
+            let writer = engine.shared.writer.lock().unwrap();
+            let engine = &engine;
+            let handles: Vec<_> =
+                (0..8u8).map(|i| scope.spawn(move || engine.set(vec![i], vec![i]))).collect();
+            while engine.write_queue.lock().unwrap().pending.len() < 8 {
+                thread::yield_now();
+            }
+            let groups = engine.write_queue.lock().unwrap().groups;
+            drop(writer);
+
+            for handle in handles {
+                handle.join().unwrap().unwrap();
+            }
+            // the first thread to get the writer committed every queued batch
+            assert_eq!(engine.write_queue.lock().unwrap().groups, groups + 1);
+        });
+        for i in 0..8u8 {
+            assert_eq!(engine.get(vec![i]).unwrap(), Some(vec![i]));
+        }
+    }
+
+    #[test]
+    fn automatic_compaction_merges_piled_up_tables() {
+        let dir = test_dir("automatic-compaction-merges-piled-up-tables");
+        let options = Options::builder()
+            .mem_table_size(512)
+            .compaction_style(CompactionStyle::Automatic { max_tables: 3 })
+            .build()
+            .unwrap();
+        let engine = Engine::open(dir.clone(), options).unwrap();
+        for i in 0..200u8 {
+            engine.set(vec![i], vec![i; 16]).unwrap();
+        }
+        engine.flush().unwrap();
+        // the compaction thread catches up without another write
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while ss_table_count(&dir) >= 3 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(ss_table_count(&dir) < 3);
+        engine.set(vec![0], vec![1]).unwrap();
+        assert_eq!(engine.get(vec![0]).unwrap(), Some(vec![1]));
+        assert_eq!(engine.get(vec![199]).unwrap(), Some(vec![199; 16]));
+    }
+
+    #[test]
+    fn column_family_options_survive_reopen() {
+        let dir = test_dir("column-family-options-survive-reopen");
+        let small = ColumnFamilyOptions {
+            mem_table_size: Some(256),
+            compression: Some(Compression::None),
+            block_size: Some(128),
+            bloom_bits_per_key: Some(0),
+            ..ColumnFamilyOptions::default()
+        };
+        let ss_table_count_cf = |id: u32| {
+            Engine::find_files(&ColumnFamily::dir(Path::new(&dir), id), SSTable::file_timestamp).unwrap().len()
+        };
+        {
+            let engine = Engine::new(dir.clone(), 1024 * 1024, 2).unwrap();
+            let invalid = ColumnFamilyOptions { block_size: Some(0), ..ColumnFamilyOptions::default() };
+            assert!(matches!(engine.create_column_family("logs", invalid), Err(Error::InvalidArgument(_))));
+            let logs = engine.create_column_family("logs", small).unwrap();
+            // the small memtable of the column family fills up long before the one of the engine
+            for i in 0..20u8 {
+                engine.set_cf(&logs, vec![i], vec![i; 64]).unwrap();
+            }
+            engine.flush().unwrap();
+            assert!(ss_table_count_cf(logs.id()) > 1);
+        }
+
+        let engine = Engine::new(dir.clone(), 1024 * 1024, 2).unwrap();
+        let logs = engine.column_family("logs").unwrap();
+        {
+            let options = engine.shared.column_family_options.read().unwrap();
+            let options = &options[&logs.id()];
+            assert_eq!(options.mem_table_size, Some(256));
+            assert_eq!(options.compression, Some(Compression::None));
+            assert_eq!(options.block_size, Some(128));
+            assert_eq!(options.bloom_bits_per_key, Some(0));
+        }
+        let table_options = engine.shared.table_options(logs.id(), 1);
+        assert_eq!((table_options.compression, table_options.block_size), (Compression::None, 128));
+        assert_eq!(engine.get_cf(&logs, vec![19]).unwrap(), Some(vec![19; 64]));
+
+        // replaced options are stored as well
+        let bigger = ColumnFamilyOptions { mem_table_size: Some(1024), ..ColumnFamilyOptions::default() };
+        engine.set_column_family_options(&logs, bigger).unwrap();
+        drop(engine);
+        let engine = Engine::new(dir, 1024 * 1024, 2).unwrap();
+        let options = engine.shared.column_family_options.read().unwrap();
+        assert_eq!(options[&logs.id()].mem_table_size, Some(1024));
+        assert_eq!(options[&logs.id()].block_size, None);
+    }
+
+    #[test]
+    fn block_cache_serves_hot_blocks() {
+        let dir = test_dir("block-cache-serves-hot-blocks");
+        let options = Options::builder().mem_table_size(64 * 1024).block_size(512).build().unwrap();
+        let engine = Engine::open(dir, options).unwrap();
+        for i in 0..1000u32 {
+            engine.set(i.to_be_bytes().to_vec(), i.to_le_bytes().to_vec()).unwrap();
+        }
+        engine.flush().unwrap();
+
+        let get = || assert_eq!(engine.get(7u32.to_be_bytes().to_vec()).unwrap(), Some(7u32.to_le_bytes().to_vec()));
+        get();
+        let stats = engine.block_cache_stats();
+        assert!(stats.misses > 0);
+        assert!(stats.usage > 0 && stats.usage <= stats.capacity);
+        // the same blocks again
+        get();
+        let hot = engine.block_cache_stats();
+        assert_eq!(hot.misses, stats.misses);
+        assert!(hot.hits > stats.hits);
+
+        // scans read the blocks of their range through the same cache
+        let scan = || engine.scan(5u32.to_be_bytes().to_vec()..9u32.to_be_bytes().to_vec()).unwrap();
+        assert_eq!(scan().len(), 4);
+        let scanned = engine.block_cache_stats();
+        assert_eq!(scan().len(), 4);
+        assert_eq!(engine.block_cache_stats().misses, scanned.misses);
+        assert!(engine.block_cache_stats().hits > scanned.hits);
+
+        // compaction drops the blocks of its inputs
+        engine.compact().unwrap();
+        assert_eq!(engine.block_cache_stats().usage, 0);
+        assert_eq!(engine.get(999u32.to_be_bytes().to_vec()).unwrap(), Some(999u32.to_le_bytes().to_vec()));
+    }
+
+    #[test]
+    fn open_files_bounded_by_table_cache() {
+        let dir = test_dir("open-files-bounded-by-table-cache");
+        let options = Options::builder().max_open_files(2).build().unwrap();
+        let engine = Engine::open(dir, options).unwrap();
+        for i in 0..5u8 {
+            engine.set(vec![i], vec![i]).unwrap();
+            engine.flush().unwrap();
+        }
+
+        for _ in 0..2 {
+            for i in 0..5u8 {
+                assert_eq!(engine.get(vec![i]).unwrap(), Some(vec![i]));
+            }
+        }
+        let stats = engine.table_cache_stats();
+        assert!(stats.usage <= 2);
+        assert!(stats.evictions > 0);
+        // only the newest table is read
+        engine.get(vec![4]).unwrap();
+        assert_eq!(engine.table_cache_stats().hits, stats.hits + 1);
+
+        engine.compact().unwrap();
+        assert_eq!(engine.table_cache_stats().usage, 0);
+        assert_eq!(engine.get(vec![3]).unwrap(), Some(vec![3]));
+        assert_eq!(engine.table_cache_stats().usage, 1);
+    }
+
+    #[test]
+    fn mmap_reads() {
+        let dir = test_dir("mmap-reads");
+        let options = Options::builder().allow_mmap_reads(true).block_cache_size(0).build().unwrap();
+        let engine = Engine::open(dir, options).unwrap();
+        for i in 0..100u8 {
+            engine.set(vec![i], vec![i; 100]).unwrap();
+        }
+        engine.flush().unwrap();
+        engine.delete(vec![5]).unwrap();
+        engine.flush().unwrap();
+
+        assert_eq!(engine.get(vec![42]).unwrap(), Some(vec![42; 100]));
+        assert_eq!(engine.get(vec![5]).unwrap(), None);
+        assert_eq!(engine.get(vec![100]).unwrap(), None);
+    }
+
+    #[test]
+    fn large_values_in_blob_files() {
+        use crate::engine::merge_operator::merge_operator::AppendOperator;
+
+        let dir = test_dir("large-values-in-blob-files");
+        let blob_files = |dir: &str| Engine::find_files(Path::new(dir), BlobFile::file_timestamp).unwrap().len();
+        let options = Options::builder().min_blob_size(Some(100)).blob_garbage_ratio(0.5).build().unwrap();
+        let engine = Engine::open(dir.clone(), options.clone()).unwrap();
+        for i in 0..20u8 {
+            engine.set(vec![i], vec![i; 1000]).unwrap();
+        }
+        engine.set(vec![100], vec![1]).unwrap();
+        engine.flush().unwrap();
+        let stats = engine.blob_stats();
+        assert_eq!(stats.file_count, 1);
+        assert!(stats.file_size > 20 * 1000);
+        // only the pointers are in the table
+        let (_, table) = &Engine::find_files(Path::new(&dir), SSTable::file_timestamp).unwrap()[0];
+        assert!(fs::metadata(table).unwrap().len() < 2000);
+        assert_eq!(engine.get(vec![7]).unwrap(), Some(vec![7; 1000]));
+        assert_eq!(engine.get(vec![100]).unwrap(), Some(vec![1]));
+        assert_eq!(engine.scan(vec![18]..).unwrap().len(), 3);
+
+        // the snapshot keeps the first blob file live
+        let snapshot = engine.snapshot();
+        for i in 0..15u8 {
+            engine.set(vec![i], vec![i + 1; 1000]).unwrap();
+        }
+        engine.flush().unwrap();
+        engine.compact().unwrap();
+        assert_eq!((engine.blob_stats().file_count, engine.blob_stats().relocated_values), (2, 0));
+        assert_eq!(engine.get_at(&snapshot, vec![3]).unwrap(), Some(vec![3; 1000]));
+        let old_values = vec![(vec![0], vec![0; 1000]), (vec![1], vec![1; 1000])];
+        assert_eq!(engine.scan_at(&snapshot, ..vec![2]).unwrap(), old_values);
+
+        // 15 of its 20 values are garbage, the 5 others move to the blob file of the output
+        drop(snapshot);
+        engine.compact().unwrap();
+        let stats = engine.blob_stats();
+        assert_eq!((stats.file_count, stats.relocated_values), (2, 5));
+        assert!(stats.reclaimed_bytes > 20 * 1000);
+        assert_eq!(blob_files(&dir), 2);
+        assert_eq!(engine.get(vec![3]).unwrap(), Some(vec![4; 1000]));
+        assert_eq!(engine.get(vec![17]).unwrap(), Some(vec![17; 1000]));
+
+        // merge operands are folded on a value read from its blob file
+        engine.set_merge_operator(Arc::new(AppendOperator::new(vec![])));
+        engine.set(vec![200], vec![9; 500]).unwrap();
+        engine.flush().unwrap();
+        engine.merge(vec![200], vec![1]).unwrap();
+        let merged = [vec![9; 500], vec![1]].concat();
+        assert_eq!(engine.get(vec![200]).unwrap(), Some(merged.clone()));
+        assert_eq!(engine.scan(vec![200]..).unwrap(), vec![(vec![200], merged.clone())]);
+        engine.close().unwrap();
+
+        let engine = Engine::open(dir.clone(), options).unwrap();
+        assert_eq!(engine.get(vec![19]).unwrap(), Some(vec![19; 1000]));
+        engine.set_merge_operator(Arc::new(AppendOperator::new(vec![])));
+        // the operand is folded in the output, which gets its own blob file
+        engine.compact().unwrap();
+        assert_eq!(engine.get(vec![200]).unwrap(), Some(merged));
+        assert_eq!(engine.scan(..).unwrap().len(), 22);
+
+        // nothing points in the blob files anymore
+        for i in (0..20u8).chain([200]) {
+            engine.delete(vec![i]).unwrap();
+        }
+        engine.compact().unwrap();
+        engine.flush().unwrap();
+        engine.compact().unwrap();
+        assert_eq!(engine.blob_stats().file_count, 0);
+        assert_eq!(blob_files(&dir), 0);
+        assert_eq!(engine.scan(..).unwrap(), vec![(vec![100], vec![1])]);
+    }
+
+    #[test]
+    fn scan_prefix_skips_tables() {
+        use crate::engine::prefix_extractor::prefix_extractor::DelimitedPrefix;
+
+        let dir = test_dir("scan-prefix-skips-tables");
+        let options = Options::builder().prefix_extractor(Arc::new(DelimitedPrefix::new(b'/', 2))).build().unwrap();
+        let engine = Engine::open(dir, options).unwrap();
+        let key = |tenant: u8, i: u8| format!("tenant/{}/{}", tenant, i).into_bytes();
+        for tenant in 1..=3 {
+            for i in 0..10 {
+                engine.set(key(tenant, i), vec![tenant, i]).unwrap();
+            }
+            engine.flush().unwrap();
+        }
+        // a newer table without tenant 2, its range tombstone still hides keys of tenant 2
+        engine.set(key(4, 0), vec![4, 0]).unwrap();
+        engine.delete_range(key(2, 0), key(2, 5)).unwrap();
+        engine.flush().unwrap();
+        engine.set(key(2, 9), vec![0]).unwrap();
+
+        let entries = engine.scan_prefix(b"tenant/2/").unwrap();
+        let expected: Vec<(Vec<u8>, Vec<u8>)> =
+            (5..9).map(|i| (key(2, i), vec![2, i])).chain([(key(2, 9), vec![0])]).collect();
+        assert_eq!(entries, expected);
+        // tenant 1, 3 and 4
+        let stats = engine.filter_stats();
+        assert_eq!((stats.checked, stats.useful), (4, 3));
+
+        assert!(engine.scan_prefix(b"tenant/7/").unwrap().is_empty());
+        assert_eq!(engine.scan_prefix(b"tenant/").unwrap().len(), 1 + 5 + 10 + 10);
+        assert_eq!(engine.get(key(3, 3)).unwrap(), Some(vec![3, 3]));
+        assert!(engine.filter_stats().useful > 7);
+    }
+
+    #[test]
+    fn compression_per_level() {
+        let engine = Engine::new(test_dir("compression-per-level"), 64 * 1024, 2).unwrap();
+        engine.set_compression_per_level(vec![Compression::None, Compression::Lz4]);
+        for i in 0..200u32 {
+            engine.set(i.to_be_bytes().to_vec(), b"text-heavy value ".repeat(8)).unwrap();
+        }
+        engine.flush().unwrap();
+        let flushed = engine.compression_stats();
+        assert_eq!(flushed.ratio(), 1.0);
+
+        engine.compact().unwrap();
+        let mut compacted = engine.compression_stats();
+        assert!(compacted.blocks > flushed.blocks);
+        compacted.uncompressed_bytes -= flushed.uncompressed_bytes;
+        compacted.compressed_bytes -= flushed.compressed_bytes;
+        assert!(compacted.ratio() > 4.0);
+        assert_eq!(engine.get(7u32.to_be_bytes().to_vec()).unwrap(), Some(b"text-heavy value ".repeat(8)));
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Exclusive per-key locks of the pessimistic transactions
+///
+/// A transaction waiting for a key waits for the one transaction holding it,
+/// so the wait-for graph is a set of chains and a deadlock is a chain leading back to the waiter
+#[derive(Debug, Default)]
+pub struct LockManager {
+    state: Mutex<LockState>,
+    // notified every time keys are released
+    released: Condvar,
+    next_transaction_id: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct LockState {
+    // key -> transaction holding it
+    owners: HashMap<Vec<u8>, u64>,
+    // waiting transaction -> transaction holding the key it waits for
+    waits_for: HashMap<u64, u64>,
+}
+
+impl LockManager {
+    pub fn new_transaction_id(&self) -> u64 {
+        self.next_transaction_id.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Locks the key for the transaction, a no-op if it already holds it
+    ///
+    /// Gives up with `LockError::Deadlock` if waiting would close a cycle,
+    /// or with `LockError::Timeout` once `timeout` passed
+    pub fn lock(&self, transaction_id: u64, key: &[u8], timeout: Duration) -> Result<(), LockError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let owner = match state.owners.get(key) {
+                None => {
+                    state.owners.insert(key.to_vec(), transaction_id);
+                    state.waits_for.remove(&transaction_id);
+                    return Ok(());
+                }
+                Some(owner) if *owner == transaction_id => {
+                    state.waits_for.remove(&transaction_id);
+                    return Ok(());
+                }
+                Some(owner) => *owner,
+            };
+
+            // the owner may have changed since the last wake up
+            state.waits_for.insert(transaction_id, owner);
+            if state.waits_on_itself(transaction_id) {
+                state.waits_for.remove(&transaction_id);
+                return Err(LockError::Deadlock { key: key.to_vec() });
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                state.waits_for.remove(&transaction_id);
+                return Err(LockError::Timeout { key: key.to_vec() });
+            }
+            state = self.released.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+
+    /// Releases every key the transaction holds and wakes up the waiters
+    pub fn unlock_all<'a, I: IntoIterator<Item = &'a Vec<u8>>>(&self, transaction_id: u64, keys: I) {
+        let mut state = self.state.lock().unwrap();
+        for key in keys {
+            if state.owners.get(key) == Some(&transaction_id) {
+                state.owners.remove(key);
+            }
+        }
+        state.waits_for.remove(&transaction_id);
+        self.released.notify_all();
+    }
+}
+
+impl LockState {
+    /// Follows the chain of waits from the transaction
+    fn waits_on_itself(&self, transaction_id: u64) -> bool {
+        let mut current = transaction_id;
+        // a chain is at most as long as the number of waiters
+        for _ in 0..=self.waits_for.len() {
+            match self.waits_for.get(&current) {
+                Some(owner) if *owner == transaction_id => return true,
+                Some(owner) => current = *owner,
+                None => return false,
+            }
+        }
+        false
+    }
+}
+
+/// Reason a pessimistic transaction couldn't lock a key, the transaction should be rolled back
+#[derive(Debug, Clone, PartialEq)]
+pub enum LockError {
+    Timeout { key: Vec<u8> },
+    Deadlock { key: Vec<u8> },
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockError::Timeout { key } => write!(f, "timed out waiting for the lock on key {:?}", key),
+            LockError::Deadlock { key } => write!(f, "deadlock waiting for the lock on key {:?}", key),
+        }
+    }
+}
+
+impl std::error::Error for LockError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn timeout_and_release() {
+        let locks = Arc::new(LockManager::default());
+        let (first, second) = (locks.new_transaction_id(), locks.new_transaction_id());
+        locks.lock(first, b"a", Duration::ZERO).unwrap();
+        locks.lock(first, b"a", Duration::ZERO).unwrap();
+        assert_eq!(
+            locks.lock(second, b"a", Duration::from_millis(20)),
+            Err(LockError::Timeout { key: b"a".to_vec() })
+        );
+
+        let waiter = {
+            let locks = locks.clone();
+            thread::spawn(move || locks.lock(second, b"a", Duration::from_secs(10)))
+        };
+        thread::sleep(Duration::from_millis(20));
+        locks.unlock_all(first, [&b"a".to_vec()]);
+        waiter.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn cycle_is_a_deadlock() {
+        let mut state = LockState::default();
+        state.waits_for.insert(1, 2);
+        state.waits_for.insert(2, 3);
+        assert!(!state.waits_on_itself(1));
+        state.waits_for.insert(3, 1);
+        assert!(state.waits_on_itself(1));
+        assert!(state.waits_on_itself(3));
+    }
+}
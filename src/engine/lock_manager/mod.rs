@@ -0,0 +1 @@
+pub mod lock_manager;
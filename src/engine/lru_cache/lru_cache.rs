@@ -0,0 +1,272 @@
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, HashMap},
+    fmt,
+    hash::{BuildHasher, Hash},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+/// Most shards a cache is split into, each one has its own lock
+const SHARDS: usize = 16;
+
+/// Counters of a cache since it was created, sizes are in the unit of the charges
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    // entries dropped to make room, erased ones are not counted
+    pub evictions: u64,
+    pub usage: usize,
+    // part of the usage which is never evicted
+    pub pinned_usage: usize,
+    pub capacity: usize,
+}
+
+impl CacheStats {
+    /// Share of the lookups served from the cache, 0 with no lookup yet
+    pub fn hit_ratio(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / lookups as f64
+    }
+}
+
+/// Least recently used cache split in shards by the hash of the key
+///
+/// Every entry has a charge, bytes for a block cache or 1 for a cache of open files,
+/// a shard evicts its least recently used entries once their charges go over its part of the capacity
+/// Pinned entries count in the usage but stay until they are erased
+/// A capacity of 0 caches nothing
+pub struct LruCache<K, V> {
+    shards: Vec<Mutex<Shard<K, V>>>,
+    hasher: RandomState,
+    capacity: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct Shard<K, V> {
+    capacity: usize,
+    usage: usize,
+    pinned_usage: usize,
+    evictions: u64,
+    entries: HashMap<K, Slot<V>>,
+    // keys of the entries which can be evicted by the tick of their last use, oldest first
+    lru: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+struct Slot<V> {
+    value: V,
+    charge: usize,
+    // None for a pinned entry
+    tick: Option<u64>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        // a tiny capacity split in 16 would leave shards which can't hold anything
+        let shard_count = SHARDS.min(capacity).max(1);
+        let shards = (0..shard_count)
+            .map(|i| {
+                // the remainder goes to the first shards so they add up to the capacity
+                let capacity = capacity / shard_count + usize::from(i < capacity % shard_count);
+                Mutex::new(Shard::new(capacity))
+            })
+            .collect();
+        Self {
+            shards,
+            hasher: RandomState::new(),
+            capacity,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Value of the key, which becomes the most recently used one
+    pub fn get(&self, key: &K) -> Option<V> {
+        let value = self.shard(key).lock().unwrap().get(key);
+        let counter = if value.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        value
+    }
+
+    /// Adds or replaces the value of the key and evicts what no longer fits,
+    /// the value itself if its charge is over the capacity of the shard
+    pub fn insert(&self, key: K, value: V, charge: usize) {
+        self.shard(&key).lock().unwrap().insert(key, value, charge, false);
+    }
+
+    /// Same as `insert()` but the entry is never evicted, see `erase()`
+    pub fn insert_pinned(&self, key: K, value: V, charge: usize) {
+        self.shard(&key).lock().unwrap().insert(key, value, charge, true);
+    }
+
+    pub fn erase(&self, key: &K) -> Option<V> {
+        self.shard(key).lock().unwrap().erase(key)
+    }
+
+    /// Erases every entry, pinned ones included, whose key doesn't satisfy `keep`
+    pub fn retain(&self, mut keep: impl FnMut(&K) -> bool) {
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap();
+            let erased: Vec<K> = shard.entries.keys().filter(|key| !keep(key)).cloned().collect();
+            for key in erased {
+                shard.erase(&key);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let mut stats = CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            capacity: self.capacity,
+            ..CacheStats::default()
+        };
+        for shard in &self.shards {
+            let shard = shard.lock().unwrap();
+            stats.evictions += shard.evictions;
+            stats.usage += shard.usage;
+            stats.pinned_usage += shard.pinned_usage;
+        }
+        stats
+    }
+
+    fn shard(&self, key: &K) -> &Mutex<Shard<K, V>> {
+        let hash = self.hasher.hash_one(key) as usize;
+        &self.shards[hash % self.shards.len()]
+    }
+}
+
+impl<K, V> fmt::Debug for LruCache<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LruCache").field("shards", &self.shards.len()).field("capacity", &self.capacity).finish()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Shard<K, V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            usage: 0,
+            pinned_usage: 0,
+            evictions: 0,
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn get(&mut self, key: &K) -> Option<V> {
+        let slot = self.entries.get_mut(key)?;
+        if let Some(tick) = slot.tick {
+            self.lru.remove(&tick);
+            self.tick += 1;
+            slot.tick = Some(self.tick);
+            self.lru.insert(self.tick, key.clone());
+        }
+        Some(slot.value.clone())
+    }
+
+    fn insert(&mut self, key: K, value: V, charge: usize, pinned: bool) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.erase(&key);
+
+        let tick = match pinned {
+            true => {
+                self.pinned_usage += charge;
+                None
+            }
+            false => {
+                self.tick += 1;
+                self.lru.insert(self.tick, key.clone());
+                Some(self.tick)
+            }
+        };
+        self.usage += charge;
+        self.entries.insert(key, Slot { value, charge, tick });
+
+        while self.usage > self.capacity {
+            let Some((_, key)) = self.lru.pop_first() else {
+                // only pinned entries are left
+                break;
+            };
+            let slot = self.entries.remove(&key).unwrap();
+            self.usage -= slot.charge;
+            self.evictions += 1;
+        }
+    }
+
+    fn erase(&mut self, key: &K) -> Option<V> {
+        let slot = self.entries.remove(key)?;
+        match slot.tick {
+            Some(tick) => {
+                self.lru.remove(&tick);
+            }
+            None => self.pinned_usage -= slot.charge,
+        }
+        self.usage -= slot.charge;
+        Some(slot.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recently_used_evicted_first() {
+        // a single shard so the order is known
+        let mut shard = Shard::new(3);
+        shard.insert(1, 10, 1, false);
+        shard.insert(2, 20, 1, false);
+        shard.insert(3, 30, 1, false);
+        assert_eq!(shard.get(&1), Some(10));
+        shard.insert(4, 40, 1, false);
+        // 2 was used the longest ago
+        assert_eq!(shard.get(&2), None);
+        assert_eq!(shard.get(&1), Some(10));
+
+        // 3 and 4 make room, 1 was used last
+        shard.insert(5, 50, 2, true);
+        assert_eq!((shard.get(&3), shard.get(&4)), (None, None));
+        assert_eq!((shard.get(&1), shard.get(&5)), (Some(10), Some(50)));
+        assert_eq!((shard.usage, shard.pinned_usage, shard.evictions), (3, 2, 3));
+
+        // doesn't fit next to the pinned entry
+        shard.insert(6, 60, 2, false);
+        assert_eq!((shard.get(&1), shard.get(&6)), (None, None));
+        assert_eq!(shard.erase(&5), Some(50));
+        assert_eq!((shard.usage, shard.pinned_usage), (0, 0));
+    }
+
+    #[test]
+    fn charges_split_over_the_shards() {
+        let cache: LruCache<u64, Vec<u8>> = LruCache::new(16 * 100);
+        for i in 0..1000 {
+            cache.insert(i, vec![0; 10], 10);
+        }
+        let stats = cache.stats();
+        assert!(stats.usage <= stats.capacity);
+        assert!(stats.usage > stats.capacity / 2);
+        assert_eq!(stats.evictions as usize, 1000 - stats.usage / 10);
+        assert_eq!(cache.get(&999), Some(vec![0; 10]));
+        assert_eq!(cache.get(&0), None);
+        assert_eq!((cache.stats().hits, cache.stats().misses), (1, 1));
+
+        cache.insert_pinned(1000, vec![], 10);
+        cache.retain(|key| *key < 500);
+        assert_eq!(cache.stats().pinned_usage, 0);
+
+        let disabled: LruCache<u64, u64> = LruCache::new(0);
+        disabled.insert_pinned(1, 1, 1);
+        assert_eq!(disabled.get(&1), None);
+    }
+}
@@ -0,0 +1,142 @@
+use crate::{
+    engine::column_family::column_family::ColumnFamilyOptions,
+    error::error::Corruption,
+    Result,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+/// Live sstables of one column family, oldest first, along with the blob files they point in
+#[derive(Serialize,Deserialize,Debug,Clone)]
+pub struct ColumnFamilyEntry {
+    pub id: u32,
+    pub name: String,
+    // only the settings which can be stored, the merge operator and the filters are skipped
+    pub options: ColumnFamilyOptions,
+    // file names in the directory of the column family, see `ColumnFamily::dir()`
+    pub ss_tables: Vec<String>,
+    pub blob_files: Vec<String>,
+}
+
+impl ColumnFamilyEntry {
+    pub fn ss_table_files(&self, dir: &Path) -> Vec<PathBuf> {
+        self.ss_tables.iter().map(|file_name| dir.join(file_name)).collect()
+    }
+
+    pub fn blob_file_names(&self, dir: &Path) -> Vec<PathBuf> {
+        self.blob_files.iter().map(|file_name| dir.join(file_name)).collect()
+    }
+}
+
+/// List of the column families with their live sstables and blob files
+///
+/// It is rewritten as a whole and renamed in place, so flushes and compactions
+/// change the set of tables atomically, files not in the list are leftovers of a crash
+#[derive(Serialize,Deserialize,Debug,Default)]
+pub struct Manifest {
+    pub column_families: Vec<ColumnFamilyEntry>,
+    // logs up to this timestamp are already in the sstables, replaying them would bring back old values
+    pub last_flushed_wal: u128,
+    // name of the comparator the keys are sorted by, see `Comparator::name()`
+    pub comparator: String,
+    // ids are never handed out twice, a log may still hold the entries of a dropped column family
+    pub next_column_family_id: u32,
+}
+
+impl Manifest {
+    pub fn new(last_flushed_wal: u128, comparator: &str, next_column_family_id: u32) -> Self {
+        Self {
+            column_families: vec![],
+            last_flushed_wal,
+            comparator: comparator.to_owned(),
+            next_column_family_id,
+        }
+    }
+
+    /// Only the file names of the tables and blob files are stored
+    pub fn add_column_family(
+        &mut self,
+        id: u32,
+        name: &str,
+        options: &ColumnFamilyOptions,
+        ss_tables: &[PathBuf],
+        blob_files: &[PathBuf]
+    ) {
+        let file_names = |files: &[PathBuf]| {
+            files.iter().filter_map(|file_name| file_name.file_name()?.to_str().map(str::to_owned)).collect()
+        };
+        self.column_families.push(ColumnFamilyEntry {
+            id,
+            name: name.to_owned(),
+            options: options.clone(),
+            ss_tables: file_names(ss_tables),
+            blob_files: file_names(blob_files),
+        });
+    }
+
+    pub fn file_name(dir: &Path) -> PathBuf {
+        dir.join("MANIFEST")
+    }
+
+    /// None for a new database, or one written before the manifest existed which `Engine::open()` refuses
+    pub fn load(dir: &Path) -> Result<Option<Self>> {
+        match fs::read(Self::file_name(dir)) {
+            Ok(content) => bincode::deserialize(&content)
+                .map(Some)
+                .map_err(|err| Corruption::new(&Self::file_name(dir), 0, err.to_string()).into()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn store(&self, dir: &Path) -> Result<()> {
+        let file_name = Self::file_name(dir);
+        let temp_file_name = file_name.with_extension("tmp");
+
+        let mut file = File::create(&temp_file_name)?;
+        file.write_all(&bincode::serialize(self)?)?;
+        file.sync_all()?;
+        fs::rename(&temp_file_name, &file_name)?;
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_and_load() {
+        let dir = std::env::temp_dir().join(format!("simpledb-manifest-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        assert!(Manifest::load(&dir).unwrap().is_none());
+
+        let ss_tables = vec![dir.join("1.sst"), dir.join("5.sst")];
+        let mut manifest = Manifest::new(4, "simpledb.bytewise", 1);
+        manifest.add_column_family(0, "default", &ColumnFamilyOptions::default(), &ss_tables, &[]);
+        manifest.store(&dir).unwrap();
+        let mut manifest = Manifest::new(6, "simpledb.bytewise", 3);
+        let options = ColumnFamilyOptions { block_size: Some(512), ..ColumnFamilyOptions::default() };
+        manifest.add_column_family(0, "default", &options, &ss_tables[1..], &[dir.join("5.blob")]);
+        manifest.add_column_family(2, "users", &options, &[dir.join("cf-2").join("7.sst")], &[]);
+        manifest.store(&dir).unwrap();
+
+        let manifest = Manifest::load(&dir).unwrap().unwrap();
+        assert_eq!(manifest.column_families[0].ss_table_files(&dir), vec![dir.join("5.sst")]);
+        assert_eq!(manifest.column_families[0].blob_file_names(&dir), vec![dir.join("5.blob")]);
+        assert_eq!(manifest.column_families[1].ss_tables, vec!["7.sst".to_owned()]);
+        assert_eq!((manifest.column_families[1].id, manifest.column_families[1].name.as_str()), (2, "users"));
+        assert_eq!(manifest.column_families[1].options.block_size, Some(512));
+        assert_eq!(manifest.last_flushed_wal, 6);
+        assert_eq!(manifest.comparator, "simpledb.bytewise");
+        assert_eq!(manifest.next_column_family_id, 3);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,140 +1,296 @@
-use super::red_black_tree::red_black_tree::{Color, NodePtr, RedBlackTree, Side, Status};
-use anyhow::Result;
-use std::{fs::{File, OpenOptions}, io::{BufWriter, Write}, mem::size_of, path::PathBuf};
-use serde::{Serialize,Deserialize};
+use super::red_black_tree::red_black_tree::{Color, KeyOrder, NodePtr, RedBlackTree, Side, Status};
+use crate::engine::{
+    comparator::comparator::{default_comparator, Comparator},
+    ss_table::{
+        compression::CompressionStats,
+        ss_table::{RangeTombstone, SSTable, SSTableEntry, TableOptions},
+    },
+};
+use crate::Result;
+use std::{
+    cmp::Ordering,
+    mem::size_of,
+    ops::{Bound, RangeBounds},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-#[derive(Serialize,Deserialize)]
-pub struct SSTableEntry {
+/// Key of the RB tree, versions of a key sit next to each other, newest first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionedKey {
     pub key: Vec<u8>,
-    pub value: Vec<u8>,
-    pub timestamp: u128
+    pub timestamp: u128,
 }
 
-impl SSTableEntry {
-    pub fn new(
-        key: Vec<u8>,
-        value: Vec<u8>,
-        timestamp: u128
-    ) -> Self {
-        Self {
-            key,
-            value,
-            timestamp
-        }
+impl VersionedKey {
+    pub fn new(key: Vec<u8>, timestamp: u128) -> Self {
+        Self { key, timestamp }
+    }
+}
+
+/// Bytewise order, see `MemTable::with_comparator()` for the others
+impl Ord for VersionedKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key
+            .cmp(&other.key)
+            .then(other.timestamp.cmp(&self.timestamp))
+    }
+}
+
+impl PartialOrd for VersionedKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
-#[derive(Serialize,Deserialize)]
-pub struct SSTable(Vec<SSTableEntry>);
 
+/// Value stored in the RB tree
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredValue {
+    pub value: Vec<u8>,
+    // see `SSTableEntry::expires_at`
+    pub expires_at: Option<u128>,
+}
+
+/// Every write adds a version of the key,
+/// the previous version is overwritten in place unless a snapshot can still see it
 #[derive(Debug)]
 pub struct MemTable {
     pub size: usize,
-    db_store: RedBlackTree<Vec<u8>, Vec<u8>>,
+    db_store: RedBlackTree<VersionedKey, StoredValue>,
+    // in the order they were written, they don't touch the keys in the tree
+    range_tombstones: Vec<RangeTombstone>,
+    comparator: Arc<dyn Comparator>,
+}
+
+// SAFETY: the tree is private and no `NodePtr` leaves the memtable, the `&self` methods only read the nodes
+// and every write goes through `&mut self`, so the memtable can be moved and shared like the keys and values
+unsafe impl Send for MemTable {}
+unsafe impl Sync for MemTable {}
+
+impl Default for MemTable {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MemTable {
     pub fn new() -> Self {
+        Self::with_comparator(default_comparator())
+    }
+
+    /// Keys sorted by `comparator`, versions of a key still newest first
+    pub fn with_comparator(comparator: Arc<dyn Comparator>) -> Self {
+        let order = {
+            let comparator = comparator.clone();
+            KeyOrder::new(move |a: &VersionedKey, b: &VersionedKey| {
+                comparator.compare(&a.key, &b.key).then(b.timestamp.cmp(&a.timestamp))
+            })
+        };
         Self {
             size: 0,
-            db_store: RedBlackTree::new(),
+            db_store: RedBlackTree::with_order(order),
+            range_tombstones: vec![],
+            comparator,
         }
     }
 
-    pub fn set(&mut self, key: Vec<u8>, value: Vec<u8>, timestamp: u128) -> Result<()> {
-        
-        match self.db_store.value(&key) {
-            Some(v) => {
-                if value.len() > v.len() {
-                    self.size += value.len() - v.len();
-                } else {
-                    self.size -= v.len() - value.len();
-                }
-            },
-            // this means node is not present in the tree(Refer implementation)
-            None => {
-                self.size += self.get_max_entry_size(&key, &value);
-            }
-        }
+    /// `newest_snapshot` is the largest timestamp a live snapshot is pinned to
+    pub fn set(
+        &mut self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        timestamp: u128,
+        newest_snapshot: Option<u128>
+    ) -> Result<()> {
+        self.insert(key, value, None, timestamp, Status::Available, newest_snapshot);
+        Ok(())
+    }
+
+    /// Same as `set()`, the value reads as missing from `expires_at` on
+    pub fn set_with_expiry(
+        &mut self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        expires_at: u128,
+        timestamp: u128,
+        newest_snapshot: Option<u128>
+    ) -> Result<()> {
+        self.insert(key, value, Some(expires_at), timestamp, Status::Available, newest_snapshot);
+        Ok(())
+    }
 
-        self.db_store
-            .insert_or_replace(key, value, timestamp, Status::Available);
+    /// Adds a TombStone with an empty value, see `set()`
+    pub fn delete(
+        &mut self,
+        key: Vec<u8>,
+        timestamp: u128,
+        newest_snapshot: Option<u128>
+    ) -> Result<()> {
+        self.insert(key, Vec::new(), None, timestamp, Status::Deleted, newest_snapshot);
+        Ok(())
+    }
+
+    /// Adds a merge operand, never overwrites the older versions since the operand is folded on top of them
+    pub fn merge(&mut self, key: Vec<u8>, operand: Vec<u8>, timestamp: u128) -> Result<()> {
+        self.insert(key, operand, None, timestamp, Status::Merge, Some(u128::MAX));
+        Ok(())
+    }
 
+    /// Hides every key in `[start, end)` written before `timestamp`, here and in the older tables
+    pub fn delete_range(&mut self, start: Vec<u8>, end: Vec<u8>, timestamp: u128) -> Result<()> {
+        self.size += Self::get_max_entry_size(&start, &end);
+        self.range_tombstones.push(RangeTombstone::new(start, end, timestamp));
         Ok(())
     }
 
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+
     pub fn get(&self, key: Vec<u8>) -> Option<Vec<u8>> {
-        let node = self.db_store.find_node(&key);
-        if node.is_null() || node.is_deleted() {
-            return None;
+        let entry = self.get_entry(&key, u128::MAX)?;
+        match RangeTombstone::newest_covering(self.comparator.as_ref(), &self.range_tombstones, &key, u128::MAX) {
+            Some(deleted_at) if entry.timestamp < deleted_at => None,
+            _ => entry.live_value(),
         }
-        return Some(node.value().unwrap());
     }
 
-    pub fn delete(&mut self, key: Vec<u8>, timestamp: u128) -> Result<()> {
-
-        if !self.db_store.has_node(&key) {
-            let empty_value = Vec::new();
-            self.size += self.get_max_entry_size(&key, &empty_value);
-            self.db_store
-                .insert_or_replace(key, empty_value, timestamp, Status::Deleted);
-        } else {
-            self.db_store.delete_key(&key, timestamp);
+    /// Newest version of the key written at or before `max_timestamp`,
+    /// including the TombStone of a deleted key which hides the older values in the immutable memtables and sstables
+    /// A merge operand is returned as is, the caller folds it with the older versions
+    pub fn get_entry(&self, key: &[u8], max_timestamp: u128) -> Option<SSTableEntry> {
+        let node = self
+            .db_store
+            .find_ceiling(&VersionedKey::new(key.to_vec(), max_timestamp));
+        if node.is_null() || node.key().unwrap().key != key {
+            return None;
         }
+        Some(Self::node_entry(node))
+    }
 
-        Ok(())
+    /// Every version of the keys in the range, sorted by key and newest version first
+    pub fn range_entries<R: RangeBounds<Vec<u8>>>(&self, range: R) -> Vec<SSTableEntry> {
+        let iter = match range.start_bound() {
+            Bound::Included(start) => self.db_store.iter_from(&VersionedKey::new(start.clone(), u128::MAX)),
+            // versions of start have timestamp >= 0, so this lands after all of them
+            Bound::Excluded(start) => {
+                let node = self.db_store.find_ceiling(&VersionedKey::new(start.clone(), 0));
+                let mut iter = self.db_store.iter_from(&VersionedKey::new(start.clone(), 0));
+                if !node.is_null() && node.key().unwrap().key == *start {
+                    iter.next();
+                }
+                iter
+            }
+            Bound::Unbounded => self.db_store.root.into_iter(),
+        };
+
+        iter.map(Self::node_entry)
+            .take_while(|entry| match range.end_bound() {
+                Bound::Included(end) => self.comparator.compare(&entry.key, end).is_le(),
+                Bound::Excluded(end) => self.comparator.compare(&entry.key, end).is_lt(),
+                Bound::Unbounded => true,
+            })
+            .collect()
     }
 
     /// Flush Memtable in the disk
-    /// 
-    /// Creates a file in the sstable directory
+    ///
+    /// Creates a file in the sstable directory and returns its name, along with how well it compressed
+    /// The large values go to a blob file next to it, returned as well if there was any, see `TableOptions`
     /// Iterate over RB tree and store the entries in the BufWriter to flush in the disk at once
     /// Create name using timestamp, will be helpful in compaction
-    pub fn flush(&self,path: &PathBuf, timestamp: u128) -> Result<()> {
-        
-        let file_name = path.join(timestamp.to_string() + ".sst");
-        // new file must be opened using append or write access
-        let file = OpenOptions::new().append(true).create(true).open(&file_name)?;
+    ///
+    /// Only reads the tree, so it is fine to call it while others `get` from the same memtable
+    pub fn flush(
+        &self,
+        path: &Path,
+        timestamp: u128,
+        options: &TableOptions
+    ) -> Result<(PathBuf, Option<PathBuf>, CompressionStats)> {
 
-        // default capacity for bufwriter is 8KB
-        let mut file = BufWriter::new(file);
+        let file_name = SSTable::file_name(path, timestamp);
 
-        let sstable = self.create_sorted_string_table();
-        let encoded_sstable = bincode::serialize(&sstable)?;
-        file.write_all(&encoded_sstable)?;
-        file.flush()?;
+        let mut sstable = SSTable::new(self.create_sorted_string_table(), self.range_tombstones.clone());
+        let blob_file = sstable.separate_values(path, timestamp, options.min_blob_size)?;
+        let stats = sstable.write(&file_name, self.comparator.as_ref(), options)?;
 
-        Ok(())
+        Ok((file_name, blob_file, stats))
     }
 
     // Think about writing format in sstable
     fn create_sorted_string_table(&self) -> Vec<SSTableEntry> {
-        let mut iter = self.db_store.root.into_iter();
         let mut sstable = vec![];
-        while let Some(node) = iter.next() {
-            if node.key().is_none() || node.value().is_none() || node.timestamp().is_none() {
-                panic!("Node key/value/timestamp is none");
-            }
-            let sstable_entry = SSTableEntry::new(
-                node.key().unwrap(),
-                node.value().unwrap(),
-                node.timestamp().unwrap()
-            );
-            sstable.push(sstable_entry);
+        for node in self.db_store.root {
+            sstable.push(Self::node_entry(node));
         }
         sstable
     }
 
-    // pub fn get(&self, key: &Vec<u8>) -> Option<Vec<u8>> {
-    //     self.db_store.get_value(key)
-    // }
+    /// Overwrites the newest version of the key if no snapshot can see it,
+    /// otherwise adds the new version next to it
+    fn insert(
+        &mut self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        expires_at: Option<u128>,
+        timestamp: u128,
+        status: Status,
+        newest_snapshot: Option<u128>
+    ) {
+        let latest = self
+            .db_store
+            .find_ceiling(&VersionedKey::new(key.clone(), u128::MAX));
+        let has_latest = !latest.is_null() && latest.key().unwrap().key == key;
+        // a snapshot taken at or after the latest version reads it
+        let latest_visible = match newest_snapshot {
+            Some(snapshot) => has_latest && latest.timestamp().unwrap() <= snapshot,
+            None => false,
+        };
+
+        let value = StoredValue { value, expires_at };
+        if has_latest && !latest_visible {
+            let v = latest.value().unwrap().value;
+            if value.value.len() > v.len() {
+                self.size += value.value.len() - v.len();
+            } else {
+                self.size -= v.len() - value.value.len();
+            }
+            // still the newest version of the key, so it stays at the same place in the tree
+            self.db_store
+                .replace_node(latest, VersionedKey::new(key, timestamp), value, timestamp, status);
+        } else {
+            self.size += Self::get_max_entry_size(&key, &value.value);
+            self.db_store
+                .insert_or_replace(VersionedKey::new(key, timestamp), value, timestamp, status);
+        }
+    }
+
+    fn node_entry(node: NodePtr<VersionedKey, StoredValue>) -> SSTableEntry {
+        if node.key().is_none() || node.value().is_none() || node.timestamp().is_none() {
+            panic!("Node key/value/timestamp is none");
+        }
+        let StoredValue { value, expires_at } = node.value().unwrap();
+        if node.is_merge() {
+            return SSTableEntry::merge_operand(node.key().unwrap().key, value, node.timestamp().unwrap());
+        }
+        SSTableEntry::new(
+            node.key().unwrap().key,
+            value,
+            node.timestamp().unwrap(),
+            node.is_deleted()
+        )
+        .with_expiry(expires_at)
+    }
 
     #[inline]
-    pub fn get_max_entry_size(&self, key: &Vec<u8>, value: &Vec<u8>) -> usize {
-        
+    #[allow(clippy::needless_return)]
+    pub fn get_max_entry_size(key: &[u8], value: &[u8]) -> usize {
+
         let new_insert_size = key.len()
             + value.len()
             + 16
-            + 3 * size_of::<NodePtr<Vec<u8>, Vec<u8>>>()
+            + size_of::<Option<u128>>()
+            + 3 * size_of::<NodePtr<VersionedKey, StoredValue>>()
             + size_of::<Side>()
             + size_of::<Color>()
             + size_of::<Status>();
@@ -142,3 +298,90 @@ impl MemTable {
         return new_insert_size;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overwrite_in_place_without_snapshots() {
+        let mut mem_table = MemTable::new();
+        mem_table.set(vec![1], vec![1], 1, None).unwrap();
+        let size = mem_table.size;
+        mem_table.set(vec![1], vec![2], 2, None).unwrap();
+        mem_table.delete(vec![1], 3, None).unwrap();
+
+        assert_eq!(mem_table.size, size - 1);
+        assert_eq!(mem_table.range_entries(..).len(), 1);
+        assert_eq!(mem_table.get(vec![1]), None);
+        assert!(mem_table.get_entry(&[1], 3).unwrap().deleted);
+    }
+
+    #[test]
+    fn versions_kept_for_snapshots() {
+        let mut mem_table = MemTable::new();
+        mem_table.set(vec![1], vec![1], 1, None).unwrap();
+        // snapshot at 1 reads the first version
+        mem_table.set(vec![1], vec![2], 2, Some(1)).unwrap();
+        // nobody reads the second version
+        mem_table.set(vec![1], vec![3], 3, Some(1)).unwrap();
+
+        assert_eq!(mem_table.range_entries(..).len(), 2);
+        assert_eq!(mem_table.get_entry(&[1], 1).unwrap().value, vec![1]);
+        assert_eq!(mem_table.get_entry(&[1], 2).unwrap().value, vec![1]);
+        assert_eq!(mem_table.get_entry(&[1], 3).unwrap().value, vec![3]);
+        assert_eq!(mem_table.get_entry(&[1], 0), None);
+    }
+
+    #[test]
+    fn range() {
+        let mut mem_table = MemTable::new();
+        for i in 0..10u8 {
+            mem_table.set(vec![i], vec![i], i as u128, None).unwrap();
+        }
+        mem_table.set(vec![4], vec![40], 20, Some(10)).unwrap();
+
+        let keys = |entries: Vec<SSTableEntry>| -> Vec<(u8, u8)> {
+            entries.iter().map(|entry| (entry.key[0], entry.value[0])).collect()
+        };
+        assert_eq!(keys(mem_table.range_entries(vec![3]..vec![6])), vec![(3, 3), (4, 40), (4, 4), (5, 5)]);
+        assert_eq!(
+            keys(mem_table.range_entries((Bound::Excluded(vec![4]), Bound::Included(vec![5])))),
+            vec![(5, 5)]
+        );
+        assert_eq!(keys(mem_table.range_entries(vec![8]..)), vec![(8, 8), (9, 9)]);
+        assert_eq!(mem_table.range_entries(..).len(), 11);
+    }
+
+    #[test]
+    fn range_tombstones_kept_apart() {
+        let mut mem_table = MemTable::new();
+        for i in 0..5u8 {
+            mem_table.set(vec![i], vec![i], i as u128, None).unwrap();
+        }
+        mem_table.delete_range(vec![1], vec![3], 10).unwrap();
+        mem_table.set(vec![2], vec![20], 11, None).unwrap();
+
+        assert_eq!(mem_table.get(vec![1]), None);
+        assert_eq!(mem_table.get(vec![2]), Some(vec![20]));
+        assert_eq!(mem_table.get(vec![3]), Some(vec![3]));
+        // the entries are still there, readers hide them
+        assert_eq!(mem_table.range_entries(..).len(), 5);
+        assert_eq!(mem_table.range_tombstones(), &[RangeTombstone::new(vec![1], vec![3], 10)]);
+    }
+
+    #[test]
+    fn sorted_by_comparator() {
+        use crate::engine::comparator::comparator::ReverseBytewiseComparator;
+        let mut mem_table = MemTable::with_comparator(Arc::new(ReverseBytewiseComparator));
+        for i in 0..5u8 {
+            mem_table.set(vec![i], vec![i], i as u128, None).unwrap();
+        }
+        mem_table.set(vec![2], vec![20], 10, Some(2)).unwrap();
+
+        let keys: Vec<(u8, u128)> =
+            mem_table.range_entries(vec![3]..vec![0]).iter().map(|entry| (entry.key[0], entry.timestamp)).collect();
+        assert_eq!(keys, vec![(3, 3), (2, 10), (2, 2), (1, 1)]);
+        assert_eq!(mem_table.get_entry(&[2], 5).unwrap().value, vec![2]);
+    }
+}
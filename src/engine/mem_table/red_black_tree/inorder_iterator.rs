@@ -1,60 +1,67 @@
-use super::red_black_tree::{NodePtr, RedBlackTree, Status};
+use super::red_black_tree::NodePtr;
 
 /*
-* InOrd + Cloneer Iterator can be implemented using stack but takes O(n) space,
-* The below implementation is Morris traversal(https://www.geeksforgeeks.org/morris-traversal-for-preOrd + Cloneer/) 
-* which takes O(1) space only
+* InOrder iterator can be implemented using stack but takes O(n) space,
+* Morris traversal(https://www.geeksforgeeks.org/morris-traversal-for-preorder/) takes O(1) space
+* but rewires the right pointers while walking, so the tree can't be read by anyone else meanwhile.
+* Every node already knows its parent and side, so the below implementation walks up and down
+* using those pointers which takes O(1) space and never writes to the tree
 */
 
-/// InOrd + Cloneer iterator, yield next item in an inOrd + Cloneer tree traversal fashion
-/// By using Morris Traversal
-pub struct InOrderIterator<K: Ord + Clone, V: Clone> {
-    current: NodePtr<K, V>,
+/// InOrder iterator, yield next item in an inOrder tree traversal fashion
+/// By following the parent pointers, the tree is only read
+pub struct InOrderIterator<K: Clone, V: Clone> {
+    next: NodePtr<K, V>,
 }
 
-impl<K:Ord + Clone,V:Clone> InOrderIterator<K,V> {
+impl<K: Clone,V:Clone> InOrderIterator<K,V> {
     pub fn new(root: NodePtr<K,V>) -> Self {
         Self {
-            current: root
+            next: Self::left_most(root)
         }
     }
+
+    /// iterator yielding the node first and then everything after it
+    pub fn starting_at(node: NodePtr<K,V>) -> Self {
+        Self {
+            next: node
+        }
+    }
+
+    /// smallest node of the subtree
+    fn left_most(mut node: NodePtr<K,V>) -> NodePtr<K,V> {
+        while !node.left().is_null() {
+            node = node.left();
+        }
+        node
+    }
 }
 
-impl<K: Ord + Clone, V: Clone> Iterator for InOrderIterator<K, V> {
+impl<K: Clone, V: Clone> Iterator for InOrderIterator<K, V> {
     type Item = NodePtr<K, V>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.current.is_null() {
+        if self.next.is_null() {
             return None;
         }
+        let current = self.next;
 
-        if self.current.left().is_null() {
-            let temp = Some(self.current);
-            self.current = self.current.right();
-            return temp;
+        if !current.right().is_null() {
+            // successor is the smallest node of the right subtree
+            self.next = Self::left_most(current.right());
         } else {
-            let mut right_most = self.current.left();
-
-            while !right_most.right().is_null() 
-                && right_most.right() != self.current {
-                right_most = right_most.right();
-            }
-
-            if right_most.right().is_null() {
-                right_most.set_right_child(self.current);
-                self.current = self.current.left();
-            } else {
-                right_most.set_right_child(NodePtr::null());
-                let temp = self.current;
-                self.current = self.current.right();
-                return Some(temp);
+            // climb till we come up from a left child, that parent is the successor
+            let mut node = current;
+            while !node.is_root() && node.is_right() {
+                node = node.get_parent();
             }
+            self.next = node.get_parent();
         }
-        return self.next();
+        Some(current)
     }
 }
 
-impl<K:Ord + Clone,V:Clone> IntoIterator for NodePtr<K,V> {
+impl<K: Clone,V:Clone> IntoIterator for NodePtr<K,V> {
     type Item = NodePtr<K, V>;
 
     type IntoIter = InOrderIterator<K,V>;
@@ -66,13 +73,13 @@ impl<K:Ord + Clone,V:Clone> IntoIterator for NodePtr<K,V> {
 
 # [cfg(test)]
 mod tests {
-    use super::*;
+    use super::super::red_black_tree::{RedBlackTree, Status};
     /*
     *           11 B
     *          /  \
     *       B 8   14 B
     *        /
-    *     R 7 
+    *     R 7
     *      /
     *   R 6 -> new insert
     *       POST
@@ -82,7 +89,7 @@ mod tests {
     *        / \
     *     R 6   8 R
     */
-        
+
     #[test]
     fn check_right_rotate() {
         let mut rb = RedBlackTree::<u8,u8>::new();
@@ -101,4 +108,27 @@ mod tests {
         assert_eq!(iter.next().unwrap().value(),Some(19));
         assert_eq!(iter.next(),None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn empty_tree() {
+        let rb = RedBlackTree::<u8,u8>::new();
+        assert_eq!(rb.root.into_iter().next(),None);
+    }
+
+    #[test]
+    fn iteration_leaves_tree_untouched() {
+        let mut rb = RedBlackTree::<u8,u8>::new();
+        for i in 1..30 {
+            rb.insert_or_replace(i, i, 0, Status::Available);
+        }
+
+        // stop half way, the tree must still be searchable
+        let mut iter = rb.root.into_iter();
+        for _ in 1..10 {
+            iter.next();
+        }
+        for i in 1..30 {
+            assert_eq!(rb.value(&i),Some(i));
+        }
+    }
+}
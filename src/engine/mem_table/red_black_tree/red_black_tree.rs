@@ -1,8 +1,12 @@
 // Memtable is Red-Black tree data-structure
 
 use std::cmp::Ordering;
+use std::fmt;
 use std::ops::Add;
 use std::ptr::null_mut;
+use std::sync::Arc;
+
+use super::inorder_iterator::InOrderIterator;
 
 #[derive(PartialEq)]
 pub enum Side {
@@ -22,15 +26,16 @@ pub enum Color {
 #[derive(PartialEq)]
 pub enum Status {
     Available,
-    Deleted
+    Deleted,
+    // operand of the merge operator, folded on top of the older versions when read
+    Merge
 }
 
 /// Node contains <key,value>,
-/// for using the node in Binary search tree,
-/// the key must implement Ord trait(provides min,max.. methods)
+/// the nodes are ordered by the `KeyOrder` of the tree
 /// A pointer to the node can be created using `NodePtr::new()` method
 #[allow(dead_code)]
-pub struct Node<K: Ord + Clone, V: Clone> {
+pub struct Node<K: Clone, V: Clone> {
     key: K,
     value: V,
     timestamp: u128,
@@ -49,9 +54,9 @@ pub struct Node<K: Ord + Clone, V: Clone> {
 // We will use unsafe rust and a node store the  pointer to the left ,right and  parent node
 /// NodePtr is the abstraction over the pointer to the node
 #[derive(Debug)]
-pub struct NodePtr<K: Ord + Clone, V:Clone>(*mut Node<K, V>);
+pub struct NodePtr<K: Clone, V:Clone>(*mut Node<K, V>);
 
-impl<K: Ord + Clone, V:Clone> NodePtr<K, V> {
+impl<K: Clone, V:Clone> NodePtr<K, V> {
     /// It allcoates a new node in the heap
     /// And saves the raw pointer to the node in the Node Pointer
     pub fn new(key: K, value: V, timestamp: u128,status: Status) -> Self {
@@ -59,7 +64,7 @@ impl<K: Ord + Clone, V:Clone> NodePtr<K, V> {
             key,
             value,
             timestamp,
-            status: status,
+            status,
             left: NodePtr::null(),
             right: NodePtr::null(),
             parent: NodePtr::null(),
@@ -146,6 +151,16 @@ impl<K: Ord + Clone, V:Clone> NodePtr<K, V> {
             (*self.0).parent = parent;
         }
     }
+    /// Safety: the new key must sort at the same place in the tree as the old one
+    fn set_key(&mut self, key: K) {
+        if self.is_null() {
+            return;
+        }
+        unsafe {
+            (*self.0).key = key;
+        }
+    }
+
     /// set the node status as deleted
     /// updates timestamp
     /// Cannot change the value to None or some min value
@@ -165,7 +180,7 @@ impl<K: Ord + Clone, V:Clone> NodePtr<K, V> {
         if self.is_null() {
             return NodePtr::null();
         }
-        unsafe { (*self.0).parent.clone() }
+        unsafe { (*self.0).parent }
     }
 
     /// returns the value stored inside the node
@@ -236,7 +251,7 @@ impl<K: Ord + Clone, V:Clone> NodePtr<K, V> {
         if self.is_null() {
             return NodePtr::null();
         }
-        unsafe { (*self.0).left.clone() }
+        unsafe { (*self.0).left }
     }
 
     /// returns a copy of right child of the node
@@ -244,7 +259,7 @@ impl<K: Ord + Clone, V:Clone> NodePtr<K, V> {
         if self.is_null() {
             return NodePtr::null();
         }
-        unsafe { (*self.0).right.clone() }
+        unsafe { (*self.0).right }
     }
 
     /// checks if this node locates in the left
@@ -262,6 +277,11 @@ impl<K: Ord + Clone, V:Clone> NodePtr<K, V> {
         unsafe { (*self.0).status == Status::Deleted }
     }
 
+    /// checks if this node holds a merge operand
+    pub fn is_merge(&self) -> bool {
+        unsafe { (*self.0).status == Status::Merge }
+    }
+
     /// checks if this node is the root node
     pub fn is_root(&self) -> bool {
         unsafe { (*self.0).side == Side::Root }
@@ -276,49 +296,89 @@ impl<K: Ord + Clone, V:Clone> NodePtr<K, V> {
     }
 }
 
-impl<K: Ord + Clone, V:Clone> Clone for NodePtr<K, V> {
+impl<K: Clone, V:Clone> Clone for NodePtr<K, V> {
     fn clone(&self) -> NodePtr<K, V> {
-        NodePtr(self.0)
+        *self
     }
 }
-impl<K: Ord + Clone, V:Clone> Copy for NodePtr<K, V> {}
+impl<K: Clone, V:Clone> Copy for NodePtr<K, V> {}
+
+/// To impelement Eq trait, typw must implement PartialEq
+impl<K: Clone, V:Clone> Eq for NodePtr<K, V> {}
 
-/// To implement Ord trait one must implement PartialOrd and Eq
-/// Implementations must be consistent with the PartialOrd
-impl<K: Ord + Clone, V:Clone> Ord for NodePtr<K, V> {
-    fn cmp(&self, other: &NodePtr<K, V>) -> Ordering {
-        unsafe { (*self.0).key.cmp(&(*other.0).key) }
+impl<K: Clone, V:Clone> PartialEq for NodePtr<K, V> {
+    fn eq(&self, other: &NodePtr<K, V>) -> bool {
+        self.0 == other.0
     }
 }
 
-impl<K: Ord + Clone, V:Clone> PartialOrd for NodePtr<K, V> {
-    fn partial_cmp(&self, other: &NodePtr<K, V>) -> Option<Ordering> {
-        unsafe { Some((*self.0).key.cmp(&(*other.0).key)) }
+/// Order of the keys in the tree, `Ord` of the key unless given to `RedBlackTree::with_order()`
+#[derive(Clone)]
+pub struct KeyOrder<K>(Arc<CompareFn<K>>);
+
+type CompareFn<K> = dyn Fn(&K, &K) -> Ordering + Send + Sync;
+
+impl<K> KeyOrder<K> {
+    pub fn new(compare: impl Fn(&K, &K) -> Ordering + Send + Sync + 'static) -> Self {
+        Self(Arc::new(compare))
     }
 }
-/// To impelement Eq trait, typw must implement PartialEq
-impl<K: Ord + Clone, V:Clone> Eq for NodePtr<K, V> {}
 
-impl<K: Ord + Clone, V:Clone> PartialEq for NodePtr<K, V> {
-    fn eq(&self, other: &NodePtr<K, V>) -> bool {
-        self.0 == other.0
+impl<K> fmt::Debug for KeyOrder<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("KeyOrder")
     }
 }
 
 #[derive(Debug)]
-pub struct RedBlackTree<K: Ord + Clone, V:Clone> {
+pub struct RedBlackTree<K: Clone, V:Clone> {
     pub root: NodePtr<K, V>,
     size: u64,
+    order: KeyOrder<K>,
+}
+
+impl<K: Ord + Clone + 'static, V:Clone> Default for RedBlackTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl<K: Ord + Clone, V:Clone> RedBlackTree<K, V> {
+/// Frees every node allocated by `NodePtr::new()` once the tree goes away
+impl<K: Clone, V:Clone> Drop for RedBlackTree<K, V> {
+    fn drop(&mut self) {
+        let mut stack = vec![self.root];
+        while let Some(node) = stack.pop() {
+            if node.is_null() {
+                continue;
+            }
+            stack.push(node.left());
+            stack.push(node.right());
+            unsafe { drop(Box::from_raw(node.0)) }
+        }
+        self.root = NodePtr::null();
+    }
+}
+
+impl<K: Ord + Clone + 'static, V:Clone> RedBlackTree<K, V> {
     /// It creates a new Red-Black tree
     pub fn new() -> Self {
+        Self::with_order(KeyOrder::new(K::cmp))
+    }
+}
+
+impl<K: Clone, V:Clone> RedBlackTree<K, V> {
+    /// Red-Black tree with the keys sorted by `order`
+    pub fn with_order(order: KeyOrder<K>) -> Self {
         Self {
             root: NodePtr::null(),
             size: 0,
+            order,
         }
     }
+
+    fn compare(&self, a: &K, b: &K) -> Ordering {
+        (self.order.0)(a, b)
+    }
     /// It will insert or replace the node in the binary search tree format
     /// In case of no root, it will be the root node
     pub fn insert_or_replace(
@@ -344,6 +404,7 @@ impl<K: Ord + Clone, V:Clone> RedBlackTree<K, V> {
     }
     /// It traverses the tree and return the pointer to the node
     /// if found else return the null Nodeptr
+    #[allow(clippy::needless_return)]
     pub fn find_node(&self, key: &K) -> NodePtr<K, V> {
         if self.root.0.is_null() {
             return NodePtr::null();
@@ -353,7 +414,7 @@ impl<K: Ord + Clone, V:Clone> RedBlackTree<K, V> {
         while !current.is_null() {
             unsafe {
                 let curr_key = &(*current.0).key;
-                let next = match key.cmp(curr_key) {
+                let next = match self.compare(key, curr_key) {
                     Ordering::Less => &mut (*current.0).left,
                     Ordering::Greater => &mut (*current.0).right,
                     Ordering::Equal => return *current,
@@ -363,6 +424,46 @@ impl<K: Ord + Clone, V:Clone> RedBlackTree<K, V> {
         }
         return NodePtr::null();
     }
+    /// It returns the node with the smallest key which is greater than or equal to the key,
+    /// null Nodeptr if every key is smaller
+    pub fn find_ceiling(&self, key: &K) -> NodePtr<K, V> {
+        let mut current = self.root;
+        let mut ceiling = NodePtr::null();
+
+        while !current.is_null() {
+            let curr_key = unsafe { &(*current.0).key };
+            match self.compare(key, curr_key) {
+                Ordering::Less => {
+                    // current is a candidate, a closer one can only be on the left
+                    ceiling = current;
+                    current = current.left();
+                }
+                Ordering::Greater => current = current.right(),
+                Ordering::Equal => return current,
+            }
+        }
+        ceiling
+    }
+    /// In order iterator starting from the ceiling of the key, see `find_ceiling`
+    pub fn iter_from(&self, key: &K) -> InOrderIterator<K, V> {
+        InOrderIterator::starting_at(self.find_ceiling(key))
+    }
+    /// Overwrites everything stored in the node, the tree is not re-balanced
+    ///
+    /// Safety: the new key must sort at the same place in the tree as the old one
+    pub fn replace_node(
+        &mut self,
+        mut node: NodePtr<K, V>,
+        key: K,
+        value: V,
+        timestamp: u128,
+        status: Status
+    ) {
+        node.set_key(key);
+        node.set_value(value);
+        node.set_timestamp(timestamp);
+        node.set_status(status);
+    }
     /// return true if node with key k is present
     pub fn has_node(&self, k: &K) -> bool {
         let node = self.find_node(k);
@@ -374,6 +475,7 @@ impl<K: Ord + Clone, V:Clone> RedBlackTree<K, V> {
     /// Safety: use only if you have checked the node is not present in the tree
     /// It insert the node in the right place
     /// Any inserted node is Red in Color
+    #[allow(clippy::needless_return)]
     fn insert(
         &mut self, 
         key: K, 
@@ -391,7 +493,7 @@ impl<K: Ord + Clone, V:Clone> RedBlackTree<K, V> {
     
             unsafe {
                 let curr_key = &(*current.0).key;
-                let next = match key.cmp(curr_key) {
+                let next = match self.compare(&key, curr_key) {
                     Ordering::Less => (*current.0).left,
                     _ => (*current.0).right, // can never be Equal, as node not present in the tree
                 };
@@ -413,7 +515,7 @@ impl<K: Ord + Clone, V:Clone> RedBlackTree<K, V> {
             node.set_color_red();
     
 
-            match node.cmp(&parent) {
+            match unsafe { self.compare(&(*node.0).key, &(*parent.0).key) } {
                 Ordering::Less => {
             
                     parent.set_left_child(node);
@@ -447,11 +549,13 @@ impl<K: Ord + Clone, V:Clone> RedBlackTree<K, V> {
         }
         // it is a violation
         if node.is_red() && node.get_parent().is_red() {
-    
+            // after a color flip the grand parent turns red and may clash with its own parent,
+            // after a rotation it is a red child of a black node, so checking it is enough
+            let grand_parent = node.get_parent().get_parent();
+
             self.correct_tree(node);
-    
-            self.check_color(node.get_parent());
-    
+
+            self.check_color(grand_parent);
         }
     }
 
@@ -494,7 +598,7 @@ impl<K: Ord + Clone, V:Clone> RedBlackTree<K, V> {
 
             // else aunt is red, do color flip
             if !node.get_parent().get_parent().is_root() {
-                node.get_parent().get_parent().set_color_black();
+                node.get_parent().get_parent().set_color_red();
             }
             node.get_parent().set_color_black();             // Only if not root node,
 
@@ -504,6 +608,7 @@ impl<K: Ord + Clone, V:Clone> RedBlackTree<K, V> {
         }
     }
 
+    #[allow(clippy::needless_return)]
     fn rotate(&mut self, mut node: NodePtr<K, V>) {
         if node.is_left() {
             if node.get_parent().is_left() {
@@ -568,7 +673,7 @@ impl<K: Ord + Clone, V:Clone> RedBlackTree<K, V> {
                 node.get_parent().set_left_child(temp);
                 temp.set_side_left();
             } else {
-                node.set_right_child(temp);
+                node.get_parent().set_right_child(temp);
                 temp.set_side_right();
             }
         }
@@ -597,7 +702,7 @@ impl<K: Ord + Clone, V:Clone> RedBlackTree<K, V> {
                 node.get_parent().set_left_child(temp);
                 temp.set_side_left();
             } else {
-                node.set_right_child(temp);
+                node.get_parent().set_right_child(temp);
                 temp.set_side_right();
             }
         }
@@ -619,6 +724,7 @@ impl<K: Ord + Clone, V:Clone> RedBlackTree<K, V> {
         self.left_rotate(node);
     }
 
+    #[allow(clippy::needless_return)]
     pub fn check_key_deleted(
         &self,
         key: &K
@@ -631,7 +737,7 @@ impl<K: Ord + Clone, V:Clone> RedBlackTree<K, V> {
     }
 
     pub fn delete_key(
-        &self,
+        &mut self,
         key: &K,
         timestamp: u128
     ) {
@@ -639,6 +745,7 @@ impl<K: Ord + Clone, V:Clone> RedBlackTree<K, V> {
         node.set_deleted(timestamp);
     }
 
+    #[allow(clippy::needless_return)]
     pub fn value(
         &self,
         key: &K
@@ -667,6 +774,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn insert_root() {
         let mut rb = RedBlackTree::<u8,u8>::new();
         rb.insert_or_replace(11, 16, 0, Status::Available);
@@ -692,6 +800,7 @@ mod tests {
     *   8   14
     */
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn insert_root_left_right() {
         let mut rb = RedBlackTree::<u8,u8>::new();
         rb.insert_or_replace(11, 16,0, Status::Available);
@@ -746,6 +855,7 @@ mod tests {
     *     R 7 -> new insert,Red aunt 
     */
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn check_color_flip() {
         let mut rb = RedBlackTree::<u8,u8>::new();
         rb.insert_or_replace(11, 16, 0, Status::Available);
@@ -811,6 +921,7 @@ mod tests {
     *     R 6   8 R
     */
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn check_right_rotate() {
         let mut rb = RedBlackTree::<u8,u8>::new();
         rb.insert_or_replace(11, 16, 0, Status::Available);
@@ -922,4 +1033,103 @@ mod tests {
         }
         assert_eq!(None,iter.next());
     }
+
+    /// returns the black height of the subtree,
+    /// panics if any red-black or parent/side property is broken
+    fn black_height(node: NodePtr<u32,u32>) -> usize {
+        if node.is_null() {
+            return 1;
+        }
+        if node.is_red() {
+            assert!(node.left().is_black() && node.right().is_black());
+        }
+        if !node.left().is_null() {
+            assert_eq!(node.left().get_parent(), node);
+            assert!(node.left().is_left());
+        }
+        if !node.right().is_null() {
+            assert_eq!(node.right().get_parent(), node);
+            assert!(node.right().is_right());
+        }
+        let left = black_height(node.left());
+        assert_eq!(left, black_height(node.right()));
+        if node.is_black() { left + 1 } else { left }
+    }
+
+    #[test]
+    fn random_insertions(){
+        // simple LCG, good enough to shuffle the keys
+        let mut seed: u64 = 42;
+        let mut rb = RedBlackTree::<u32,u32>::new();
+        let mut keys = std::collections::BTreeSet::new();
+        for _ in 0..2000 {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let key = ((seed >> 33) % 1000) as u32;
+            rb.insert_or_replace(key, key + 1, 0, Status::Available);
+            keys.insert(key);
+
+            assert!(rb.root.is_root());
+            assert!(rb.root.is_black());
+            black_height(rb.root);
+        }
+
+        assert_eq!(rb.size, keys.len() as u64);
+        let mut iter = rb.root.into_iter();
+        for key in keys {
+            assert_eq!(Some(key + 1),iter.next().unwrap().value());
+        }
+        assert_eq!(None,iter.next());
+    }
+
+    #[test]
+    fn deletes_keep_the_invariants(){
+        let mut seed: u64 = 7;
+        let mut rb = RedBlackTree::<u32,u32>::new();
+        // true while the key is available
+        let mut keys = std::collections::BTreeMap::new();
+        for _ in 0..2000 {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let key = ((seed >> 33) % 500) as u32;
+            if (seed >> 32) & 1 == 0 {
+                rb.insert_or_replace(key, key + 1, 0, Status::Available);
+                keys.insert(key, true);
+            } else if rb.has_node(&key) {
+                rb.delete_key(&key, 0);
+                keys.insert(key, false);
+            } else {
+                // deleting a missing key inserts a tombstone, like the memtable does
+                rb.insert_or_replace(key, 0, 0, Status::Deleted);
+                keys.insert(key, false);
+            }
+
+            assert!(rb.root.is_root());
+            assert!(rb.root.is_black());
+            black_height(rb.root);
+        }
+
+        assert_eq!(rb.size, keys.len() as u64);
+        for (key, available) in keys {
+            assert_eq!(rb.check_key_deleted(&key), !available);
+            if available {
+                assert_eq!(rb.value(&key), Some(key + 1));
+            }
+        }
+    }
+
+    #[test]
+    fn ceiling_and_iter_from(){
+        let mut rb = RedBlackTree::<u8,u8>::new();
+        for i in (10..60).step_by(10) {
+            rb.insert_or_replace(i, i, 0, Status::Available);
+        }
+
+        assert_eq!(rb.find_ceiling(&5).key(),Some(10));
+        assert_eq!(rb.find_ceiling(&20).key(),Some(20));
+        assert_eq!(rb.find_ceiling(&21).key(),Some(30));
+        assert!(rb.find_ceiling(&51).is_null());
+
+        let keys: Vec<u8> = rb.iter_from(&25).map(|node| node.key().unwrap()).collect();
+        assert_eq!(keys,vec![30,40,50]);
+        assert_eq!(rb.iter_from(&51).next(),None);
+    }
 }
@@ -0,0 +1,260 @@
+use crate::{
+    engine::{
+        comparator::comparator::{BytewiseComparator, Comparator},
+        merge_operator::merge_operator::{fold_versions, MergeOperator},
+        ss_table::ss_table::{RangeTombstone, SSTableEntry},
+    },
+    Error, Result,
+};
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    iter::Peekable,
+    sync::Arc,
+    vec::IntoIter,
+};
+
+/// Entry waiting in the heap along with the run it came from
+struct HeapEntry<'a> {
+    entry: SSTableEntry,
+    run: usize,
+    comparator: &'a dyn Comparator,
+}
+
+/// BinaryHeap is a max heap, reverse the order to pop the smallest key first
+impl Ord for HeapEntry<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.comparator
+            .compare(&other.entry.key, &self.entry.key)
+            .then(self.entry.timestamp.cmp(&other.entry.timestamp))
+            .then(other.run.cmp(&self.run))
+    }
+}
+
+impl PartialOrd for HeapEntry<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Eq for HeapEntry<'_> {}
+
+impl PartialEq for HeapEntry<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+/// Merges runs sorted by key and newest version first(memtables, sstables) into one such run,
+/// the keys are sorted by `comparator`
+///
+/// Timestamps are unique, so versions of a key coming from different runs
+/// end up in the right order without knowing which run is newer
+pub struct MergeIterator<'a> {
+    runs: Vec<IntoIter<SSTableEntry>>,
+    heap: BinaryHeap<HeapEntry<'a>>,
+    comparator: &'a dyn Comparator,
+}
+
+impl<'a> MergeIterator<'a> {
+    pub fn new(runs: Vec<Vec<SSTableEntry>>, comparator: &'a dyn Comparator) -> Self {
+        let mut runs: Vec<IntoIter<SSTableEntry>> = runs.into_iter().map(Vec::into_iter).collect();
+        let mut heap = BinaryHeap::new();
+        for (run, entries) in runs.iter_mut().enumerate() {
+            if let Some(entry) = entries.next() {
+                heap.push(HeapEntry { entry, run, comparator });
+            }
+        }
+        Self { runs, heap, comparator }
+    }
+}
+
+impl Iterator for MergeIterator<'_> {
+    type Item = SSTableEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let HeapEntry { entry, run, .. } = self.heap.pop()?;
+        if let Some(next) = self.runs[run].next() {
+            self.heap.push(HeapEntry { entry: next, run, comparator: self.comparator });
+        }
+        Some(entry)
+    }
+}
+
+/// Reads the value of an entry pointing in a blob file, see `SSTableEntry::blob`
+type ReadBlob<'a> = Box<dyn Fn(SSTableEntry) -> Result<SSTableEntry> + 'a>;
+
+/// Yields what a reader at `max_timestamp` sees from a merged run:
+/// the newest version of every key written at or before it, skipping the deleted and expired keys
+///
+/// Merge operands are folded with the older versions, which fails without a merge operator
+pub struct VisibleIterator<'a, I: Iterator<Item = SSTableEntry>> {
+    entries: Peekable<I>,
+    max_timestamp: u128,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    range_tombstones: Vec<RangeTombstone>,
+    comparator: &'a dyn Comparator,
+    read_blob: Option<ReadBlob<'a>>,
+}
+
+impl<'a, I: Iterator<Item = SSTableEntry>> VisibleIterator<'a, I> {
+    pub fn new(entries: I, max_timestamp: u128, merge_operator: Option<Arc<dyn MergeOperator>>) -> Self {
+        Self {
+            entries: entries.peekable(),
+            max_timestamp,
+            merge_operator,
+            range_tombstones: vec![],
+            comparator: &BytewiseComparator,
+            read_blob: None,
+        }
+    }
+
+    /// Range tombstones of every merged run, they hide the older versions of the keys they cover
+    pub fn with_range_tombstones(
+        mut self,
+        range_tombstones: Vec<RangeTombstone>,
+        comparator: &'a dyn Comparator
+    ) -> Self {
+        self.range_tombstones = range_tombstones;
+        self.comparator = comparator;
+        self
+    }
+
+    /// Values in blob files are only read for the versions which are yielded or folded
+    pub fn with_blob_reader(mut self, read_blob: impl Fn(SSTableEntry) -> Result<SSTableEntry> + 'a) -> Self {
+        self.read_blob = Some(Box::new(read_blob));
+        self
+    }
+
+    fn read_blob(&self, entry: SSTableEntry) -> Result<SSTableEntry> {
+        match &self.read_blob {
+            Some(read_blob) if entry.blob.is_some() => read_blob(entry),
+            _ => Ok(entry),
+        }
+    }
+}
+
+impl<I: Iterator<Item = SSTableEntry>> Iterator for VisibleIterator<'_, I> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let mut entry = self.entries.next()?;
+            if entry.timestamp > self.max_timestamp {
+                continue;
+            }
+            // older versions of the key are hidden by this one, or folded into it
+            let mut versions = vec![];
+            while let Some(older) = self.entries.next_if(|older| older.key == entry.key) {
+                versions.push(older);
+            }
+
+            let deleted_at = RangeTombstone::newest_covering(
+                self.comparator,
+                &self.range_tombstones,
+                &entry.key,
+                self.max_timestamp
+            );
+            if let Some(deleted_at) = deleted_at {
+                if entry.timestamp < deleted_at {
+                    continue;
+                }
+                // operands on top are folded as if the key didn't exist
+                versions.retain(|older| older.timestamp > deleted_at);
+            }
+
+            if entry.merge {
+                let Some(merge_operator) = &self.merge_operator else {
+                    let reason = "merge operand found but no merge operator is set".to_owned();
+                    return Some(Err(Error::InvalidArgument(reason)));
+                };
+                versions.insert(0, entry);
+                // the value the operands are folded on may be in a blob file
+                if let Some(base) = versions.iter_mut().find(|version| !version.merge) {
+                    match self.read_blob(base.clone()) {
+                        Ok(read) => *base = read,
+                        Err(err) => return Some(Err(err)),
+                    }
+                }
+                entry = fold_versions(merge_operator.as_ref(), &versions);
+            }
+
+            // deleted or expired
+            if entry.deleted || entry.is_expired() {
+                continue;
+            }
+            return Some(self.read_blob(entry).map(|entry| (entry.key, entry.value)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: u8, timestamp: u128, deleted: bool) -> SSTableEntry {
+        SSTableEntry::new(vec![key], vec![timestamp as u8], timestamp, deleted)
+    }
+
+    #[test]
+    fn merge_runs() {
+        let runs = vec![
+            vec![entry(1, 1, false), entry(3, 3, false)],
+            vec![entry(1, 5, false), entry(2, 4, false)],
+            vec![],
+            vec![entry(3, 6, true), entry(4, 2, false)],
+        ];
+        let merged: Vec<(u8, u128)> = MergeIterator::new(runs, &BytewiseComparator)
+            .map(|entry| (entry.key[0], entry.timestamp))
+            .collect();
+        assert_eq!(merged, vec![(1, 5), (1, 1), (2, 4), (3, 6), (3, 3), (4, 2)]);
+    }
+
+    #[test]
+    fn visible_versions() {
+        let run = vec![
+            entry(1, 5, false),
+            entry(1, 1, false),
+            entry(2, 4, false),
+            entry(3, 6, true),
+            entry(3, 3, false),
+        ];
+
+        let latest: Vec<(Vec<u8>, Vec<u8>)> =
+            VisibleIterator::new(run.clone().into_iter(), u128::MAX, None).collect::<Result<_>>().unwrap();
+        assert_eq!(latest, vec![(vec![1], vec![5]), (vec![2], vec![4])]);
+
+        let at_three: Vec<(Vec<u8>, Vec<u8>)> =
+            VisibleIterator::new(run.into_iter(), 3, None).collect::<Result<_>>().unwrap();
+        assert_eq!(at_three, vec![(vec![1], vec![1]), (vec![3], vec![3])]);
+    }
+
+    #[test]
+    fn range_tombstones_hide_older_versions() {
+        let run = vec![entry(1, 5, false), entry(1, 1, false), entry(2, 2, false), entry(3, 6, false)];
+        let range_tombstones = vec![RangeTombstone::new(vec![1], vec![3], 4)];
+
+        let latest: Vec<(Vec<u8>, Vec<u8>)> = VisibleIterator::new(run.clone().into_iter(), u128::MAX, None)
+            .with_range_tombstones(range_tombstones.clone(), &BytewiseComparator)
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(latest, vec![(vec![1], vec![5]), (vec![3], vec![6])]);
+
+        // written after the reader started
+        let at_three: Vec<(Vec<u8>, Vec<u8>)> = VisibleIterator::new(run.into_iter(), 3, None)
+            .with_range_tombstones(range_tombstones, &BytewiseComparator)
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(at_three, vec![(vec![1], vec![1]), (vec![2], vec![2])]);
+    }
+
+    #[test]
+    fn merge_runs_with_comparator() {
+        use crate::engine::comparator::comparator::ReverseBytewiseComparator;
+        let runs = vec![vec![entry(3, 3, false), entry(1, 1, false)], vec![entry(2, 4, false), entry(1, 5, false)]];
+        let merged: Vec<(u8, u128)> = MergeIterator::new(runs, &ReverseBytewiseComparator)
+            .map(|entry| (entry.key[0], entry.timestamp))
+            .collect();
+        assert_eq!(merged, vec![(3, 3), (2, 4), (1, 5), (1, 1)]);
+    }
+}
@@ -0,0 +1 @@
+pub mod merge_iterator;
@@ -0,0 +1,145 @@
+use std::fmt;
+
+use crate::engine::ss_table::ss_table::SSTableEntry;
+
+/// Folds the operands of `Engine::merge()` into a value, registered with `Engine::set_merge_operator()`
+///
+/// Operands are stored as they are and folded when the key is read or compacted,
+/// so a counter is bumped without reading it first
+pub trait MergeOperator: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// `existing` is the value below the operands, None if the key doesn't exist,
+    /// `operands` come oldest first
+    fn full_merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[Vec<u8>]) -> Vec<u8>;
+}
+
+impl fmt::Debug for dyn MergeOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MergeOperator({})", self.name())
+    }
+}
+
+/// Value seen at the newest of `versions`, versions of one key newest first
+///
+/// A merge operand is folded with the operands below it down to the first value or TombStone,
+/// the result is a plain value with the timestamp of the newest operand
+pub fn fold_versions(operator: &dyn MergeOperator, versions: &[SSTableEntry]) -> SSTableEntry {
+    let newest = &versions[0];
+    if !newest.merge {
+        return newest.clone();
+    }
+
+    let mut operands = vec![];
+    let mut existing = None;
+    for version in versions {
+        if !version.merge {
+            existing = version.live_value();
+            break;
+        }
+        operands.push(version.value.clone());
+    }
+    operands.reverse();
+
+    let value = operator.full_merge(&newest.key, existing.as_deref(), &operands);
+    SSTableEntry::new(newest.key.clone(), value, newest.timestamp, false)
+}
+
+/// Little endian u64, a malformed value or operand counts as 0
+fn decode_u64(value: &[u8]) -> u64 {
+    value.try_into().map(u64::from_le_bytes).unwrap_or(0)
+}
+
+/// Adds little endian u64 operands, wrapping on overflow
+#[derive(Debug, Default)]
+pub struct U64AddOperator;
+
+impl MergeOperator for U64AddOperator {
+    fn name(&self) -> &str {
+        "u64_add"
+    }
+
+    fn full_merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[Vec<u8>]) -> Vec<u8> {
+        let sum = operands
+            .iter()
+            .fold(existing.map_or(0, decode_u64), |sum, operand| sum.wrapping_add(decode_u64(operand)));
+        sum.to_le_bytes().to_vec()
+    }
+}
+
+/// Keeps the largest little endian u64
+#[derive(Debug, Default)]
+pub struct U64MaxOperator;
+
+impl MergeOperator for U64MaxOperator {
+    fn name(&self) -> &str {
+        "u64_max"
+    }
+
+    fn full_merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[Vec<u8>]) -> Vec<u8> {
+        let max = operands
+            .iter()
+            .map(|operand| decode_u64(operand))
+            .chain(existing.map(decode_u64))
+            .max()
+            .unwrap_or(0);
+        max.to_le_bytes().to_vec()
+    }
+}
+
+/// Appends the operands to the value, separated by `delimiter`
+#[derive(Debug, Default)]
+pub struct AppendOperator {
+    delimiter: Vec<u8>,
+}
+
+impl AppendOperator {
+    pub fn new(delimiter: Vec<u8>) -> Self {
+        Self { delimiter }
+    }
+}
+
+impl MergeOperator for AppendOperator {
+    fn name(&self) -> &str {
+        "append"
+    }
+
+    fn full_merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[Vec<u8>]) -> Vec<u8> {
+        let mut parts: Vec<&[u8]> = existing.into_iter().collect();
+        parts.extend(operands.iter().map(Vec::as_slice));
+        parts.join(self.delimiter.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in_operators() {
+        let operands = vec![3u64.to_le_bytes().to_vec(), 9u64.to_le_bytes().to_vec()];
+        assert_eq!(U64AddOperator.full_merge(b"k", Some(&5u64.to_le_bytes()), &operands), 17u64.to_le_bytes());
+        assert_eq!(U64AddOperator.full_merge(b"k", None, &operands), 12u64.to_le_bytes());
+        assert_eq!(U64MaxOperator.full_merge(b"k", Some(&5u64.to_le_bytes()), &operands), 9u64.to_le_bytes());
+
+        let append = AppendOperator::new(b",".to_vec());
+        assert_eq!(append.full_merge(b"k", Some(b"a"), &[b"b".to_vec(), b"c".to_vec()]), b"a,b,c");
+        assert_eq!(append.full_merge(b"k", None, &[b"b".to_vec()]), b"b");
+    }
+
+    #[test]
+    fn fold_down_to_value_or_tombstone() {
+        let add = |value: u64, timestamp| SSTableEntry::merge_operand(vec![1], value.to_le_bytes().to_vec(), timestamp);
+        let versions = vec![
+            add(1, 6),
+            add(2, 5),
+            SSTableEntry::new(vec![1], 10u64.to_le_bytes().to_vec(), 4, false),
+            add(100, 3),
+        ];
+        let folded = fold_versions(&U64AddOperator, &versions);
+        assert_eq!((folded.value, folded.timestamp, folded.merge), (13u64.to_le_bytes().to_vec(), 6, false));
+
+        let versions = vec![add(1, 6), SSTableEntry::new(vec![1], vec![], 5, true), add(100, 3)];
+        assert_eq!(fold_versions(&U64AddOperator, &versions).value, 1u64.to_le_bytes());
+    }
+}
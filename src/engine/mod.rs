@@ -1,2 +1,22 @@
+pub mod checksum;
+pub mod comparator;
 pub mod mem_table;
-pub mod engine;
\ No newline at end of file
+pub mod lru_cache;
+pub mod bloom_filter;
+pub mod prefix_extractor;
+pub mod blob_file;
+pub mod ss_table;
+pub mod wal;
+pub mod write_batch;
+pub mod merge_iterator;
+pub mod merge_operator;
+pub mod compaction;
+pub mod column_family;
+pub mod options;
+pub mod manifest;
+pub mod snapshot;
+pub mod lock_manager;
+pub mod transaction;
+pub mod engine;
+pub mod db;
+pub mod async_db;
\ No newline at end of file
@@ -0,0 +1,381 @@
+use std::{
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    engine::{
+        column_family::column_family::ColumnFamilyOptions,
+        comparator::comparator::{default_comparator, Comparator},
+        prefix_extractor::prefix_extractor::PrefixExtractor,
+        ss_table::{
+            compression::Compression,
+            ss_table::{TableOptions, TableReaderOptions},
+        },
+    },
+    error::error::Corruption,
+    Error, Result,
+};
+
+/// When a commit waits for the log to reach the disk
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WalSyncMode {
+    /// The log is handed over to the OS, a power loss may take the last writes with it
+    #[default]
+    Buffered,
+    /// Every group commit waits for the log to be synced
+    EveryWrite,
+}
+
+/// How the sstables are merged, every compaction merges all the sstables of a column family into one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompactionStyle {
+    /// Only `Engine::compact()` merges
+    #[default]
+    Manual,
+    /// Once a flush leaves a column family with `max_tables` sstables,
+    /// the background compaction thread merges them, a failure stops the writes like a failed flush
+    Automatic { max_tables: usize },
+}
+
+/// Settings of `Engine::open()`, see `Options::builder()`
+///
+/// The ones which can be stored are written to the `OPTIONS` file of the database on every open,
+/// the comparator and the default column family options are skipped
+/// The file is only a record of the last open for debugging, `Engine::open()` never reads it back,
+/// the database opens with whatever options it is given
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Options {
+    pub create_if_missing: bool,
+    pub error_if_exists: bool,
+    // bytes the memtable holds before it is frozen
+    pub mem_table_size: usize,
+    // writes stall once this many full memtables are waiting to be flushed
+    pub max_immutable_mem_tables: usize,
+    pub wal_sync_mode: WalSyncMode,
+    pub compaction_style: CompactionStyle,
+    // uncompressed size a sstable block is cut at
+    pub block_size: usize,
+    // bits per key of the bloom filters of the sstables, 0 turns them off
+    pub bloom_bits_per_key: usize,
+    // codec of every level, the last one is used for the levels below it, see `Engine::set_compression_per_level()`
+    pub compression_per_level: Vec<Compression>,
+    // bytes of decoded blocks kept in memory for the point lookups, 0 turns the cache off
+    pub block_cache_size: usize,
+    // the index, range tombstones and filters of the tables stay in the block cache as long as the tables live
+    pub pin_index_blocks: bool,
+    // the sstables are mapped in memory, the blocks are decoded from the page cache without a copy
+    pub allow_mmap_reads: bool,
+    // sstable readers kept open at once for the point lookups, each one holds a file handle and the index
+    pub max_open_files: usize,
+    pub verify_checksums: bool,
+    // multi-version mode, see `Engine::set_retention_window()`
+    pub retention_window: Option<Duration>,
+    // values at least this big are written to blob files, the sstables only point at them
+    // so compaction doesn't rewrite them, None keeps every value in the sstables
+    pub min_blob_size: Option<usize>,
+    // compaction moves the live values out of a blob file once this share of it is garbage
+    pub blob_garbage_ratio: f64,
+    #[serde(skip, default = "default_comparator")]
+    pub comparator: Arc<dyn Comparator>,
+    #[serde(skip)]
+    pub default_column_family: ColumnFamilyOptions,
+    // the bloom filters are built over the prefixes it cuts, see `Engine::scan_prefix()`
+    #[serde(skip)]
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            create_if_missing: true,
+            error_if_exists: false,
+            mem_table_size: 4 * 1024 * 1024,
+            max_immutable_mem_tables: 2,
+            wal_sync_mode: WalSyncMode::default(),
+            compaction_style: CompactionStyle::default(),
+            block_size: 4 * 1024,
+            bloom_bits_per_key: 10,
+            compression_per_level: vec![Compression::default()],
+            block_cache_size: 8 * 1024 * 1024,
+            pin_index_blocks: false,
+            allow_mmap_reads: false,
+            max_open_files: 1000,
+            verify_checksums: true,
+            retention_window: None,
+            min_blob_size: None,
+            blob_garbage_ratio: 0.5,
+            comparator: default_comparator(),
+            default_column_family: ColumnFamilyOptions::default(),
+            prefix_extractor: None,
+        }
+    }
+}
+
+impl Options {
+    pub fn builder() -> OptionsBuilder {
+        OptionsBuilder {
+            options: Options::default(),
+        }
+    }
+
+    /// Fails with `Error::InvalidArgument` naming the first setting out of range
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |reason: &str| Err(Error::InvalidArgument(reason.to_owned()));
+        if self.mem_table_size == 0 {
+            return invalid("mem_table_size must be at least 1");
+        }
+        if self.max_immutable_mem_tables == 0 {
+            return invalid("max_immutable_mem_tables must be at least 1");
+        }
+        if self.block_size == 0 {
+            return invalid("block_size must be at least 1");
+        }
+        if self.bloom_bits_per_key > 64 {
+            return invalid("bloom_bits_per_key must be at most 64");
+        }
+        if self.compression_per_level.is_empty() {
+            return invalid("compression_per_level needs a codec for level 0");
+        }
+        if self.max_open_files == 0 {
+            return invalid("max_open_files must be at least 1");
+        }
+        if matches!(self.compaction_style, CompactionStyle::Automatic { max_tables } if max_tables < 2) {
+            return invalid("max_tables of the automatic compaction must be at least 2");
+        }
+        if self.min_blob_size == Some(0) {
+            return invalid("min_blob_size must be at least 1");
+        }
+        if !(0.0..=1.0).contains(&self.blob_garbage_ratio) {
+            return invalid("blob_garbage_ratio must be between 0 and 1");
+        }
+        if self.error_if_exists && !self.create_if_missing {
+            return invalid("error_if_exists without create_if_missing can never open a database");
+        }
+        Ok(())
+    }
+
+    /// Codec of the tables written at `level`, memtables are flushed at level 0 and compacted to level 1
+    pub fn compression(&self, level: usize) -> Compression {
+        let compression = &self.compression_per_level;
+        compression.get(level).or(compression.last()).copied().unwrap_or_default()
+    }
+
+    /// How the tables written at `level` are laid out, see `compression()`
+    pub fn table_options(&self, level: usize) -> TableOptions {
+        TableOptions {
+            compression: self.compression(level),
+            block_size: self.block_size,
+            bloom_bits_per_key: self.bloom_bits_per_key,
+            prefix_extractor: self.prefix_extractor.clone(),
+            min_blob_size: self.min_blob_size,
+        }
+    }
+
+    /// How the point lookups read the sstables
+    pub fn table_reader_options(&self) -> TableReaderOptions {
+        TableReaderOptions {
+            verify_checksums: self.verify_checksums,
+            pin_index_blocks: self.pin_index_blocks,
+            allow_mmap_reads: self.allow_mmap_reads,
+            prefix_extractor: self.prefix_extractor.clone(),
+        }
+    }
+
+    pub fn file_name(dir: &Path) -> PathBuf {
+        dir.join("OPTIONS")
+    }
+
+    /// Options the database was last opened with, None if it never was, for tools and debugging
+    pub fn load(dir: &Path) -> Result<Option<Self>> {
+        let file_name = Self::file_name(dir);
+        match fs::read(&file_name) {
+            Ok(content) => bincode::deserialize(&content)
+                .map(Some)
+                .map_err(|err| Corruption::new(&file_name, 0, err.to_string()).into()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn store(&self, dir: &Path) -> Result<()> {
+        let file_name = Self::file_name(dir);
+        let temp_file_name = file_name.with_extension("tmp");
+
+        let mut file = File::create(&temp_file_name)?;
+        file.write_all(&bincode::serialize(self)?)?;
+        file.sync_all()?;
+        fs::rename(&temp_file_name, &file_name)?;
+        Ok(())
+    }
+}
+
+/// Builds `Options` starting from the defaults, `build()` validates them
+#[derive(Debug, Clone)]
+pub struct OptionsBuilder {
+    options: Options,
+}
+
+impl OptionsBuilder {
+    pub fn create_if_missing(mut self, create_if_missing: bool) -> Self {
+        self.options.create_if_missing = create_if_missing;
+        self
+    }
+
+    pub fn error_if_exists(mut self, error_if_exists: bool) -> Self {
+        self.options.error_if_exists = error_if_exists;
+        self
+    }
+
+    pub fn mem_table_size(mut self, mem_table_size: usize) -> Self {
+        self.options.mem_table_size = mem_table_size;
+        self
+    }
+
+    pub fn max_immutable_mem_tables(mut self, max_immutable_mem_tables: usize) -> Self {
+        self.options.max_immutable_mem_tables = max_immutable_mem_tables;
+        self
+    }
+
+    pub fn wal_sync_mode(mut self, wal_sync_mode: WalSyncMode) -> Self {
+        self.options.wal_sync_mode = wal_sync_mode;
+        self
+    }
+
+    pub fn compaction_style(mut self, compaction_style: CompactionStyle) -> Self {
+        self.options.compaction_style = compaction_style;
+        self
+    }
+
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.options.block_size = block_size;
+        self
+    }
+
+    pub fn bloom_bits_per_key(mut self, bloom_bits_per_key: usize) -> Self {
+        self.options.bloom_bits_per_key = bloom_bits_per_key;
+        self
+    }
+
+    /// Same codec for every level
+    pub fn compression(self, compression: Compression) -> Self {
+        self.compression_per_level(vec![compression])
+    }
+
+    pub fn compression_per_level(mut self, compression_per_level: Vec<Compression>) -> Self {
+        self.options.compression_per_level = compression_per_level;
+        self
+    }
+
+    pub fn block_cache_size(mut self, block_cache_size: usize) -> Self {
+        self.options.block_cache_size = block_cache_size;
+        self
+    }
+
+    pub fn pin_index_blocks(mut self, pin_index_blocks: bool) -> Self {
+        self.options.pin_index_blocks = pin_index_blocks;
+        self
+    }
+
+    pub fn allow_mmap_reads(mut self, allow_mmap_reads: bool) -> Self {
+        self.options.allow_mmap_reads = allow_mmap_reads;
+        self
+    }
+
+    pub fn max_open_files(mut self, max_open_files: usize) -> Self {
+        self.options.max_open_files = max_open_files;
+        self
+    }
+
+    pub fn verify_checksums(mut self, verify_checksums: bool) -> Self {
+        self.options.verify_checksums = verify_checksums;
+        self
+    }
+
+    pub fn retention_window(mut self, retention_window: Option<Duration>) -> Self {
+        self.options.retention_window = retention_window;
+        self
+    }
+
+    /// Key-value separation, the values already written stay where they are till compaction rewrites them
+    pub fn min_blob_size(mut self, min_blob_size: Option<usize>) -> Self {
+        self.options.min_blob_size = min_blob_size;
+        self
+    }
+
+    /// 1.0 only drops the blob files nobody reads anymore, 0.0 rewrites every blob file on every compaction
+    pub fn blob_garbage_ratio(mut self, blob_garbage_ratio: f64) -> Self {
+        self.options.blob_garbage_ratio = blob_garbage_ratio;
+        self
+    }
+
+    /// A database is always opened with the comparator it was created with, checked by name
+    pub fn comparator(mut self, comparator: Arc<dyn Comparator>) -> Self {
+        self.options.comparator = comparator;
+        self
+    }
+
+    /// Bloom filters over the prefixes of the keys, the ones written before keep the filter they have
+    pub fn prefix_extractor(mut self, prefix_extractor: Arc<dyn PrefixExtractor>) -> Self {
+        self.options.prefix_extractor = Some(prefix_extractor);
+        self
+    }
+
+    /// Settings of the default column family, unlike the other column families it takes them from every open,
+    /// the copy in the manifest is only a record
+    pub fn default_column_family(mut self, default_column_family: ColumnFamilyOptions) -> Self {
+        self.options.default_column_family = default_column_family;
+        self
+    }
+
+    pub fn build(self) -> Result<Options> {
+        self.options.validate()?;
+        Ok(self.options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::comparator::comparator::ReverseBytewiseComparator;
+
+    #[test]
+    fn build_validate_and_store() {
+        let dir = std::env::temp_dir().join(format!("simpledb-options-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        assert!(Options::load(&dir).unwrap().is_none());
+
+        let options = Options::builder()
+            .mem_table_size(1024)
+            .wal_sync_mode(WalSyncMode::EveryWrite)
+            .compression_per_level(vec![Compression::None, Compression::Lz4])
+            .retention_window(Some(Duration::from_secs(60)))
+            .comparator(Arc::new(ReverseBytewiseComparator))
+            .build()
+            .unwrap();
+        assert_eq!((options.compression(0), options.compression(5)), (Compression::None, Compression::Lz4));
+        options.store(&dir).unwrap();
+
+        let loaded = Options::load(&dir).unwrap().unwrap();
+        assert_eq!(loaded.mem_table_size, 1024);
+        assert_eq!(loaded.wal_sync_mode, WalSyncMode::EveryWrite);
+        assert_eq!(loaded.retention_window, Some(Duration::from_secs(60)));
+        // not stored
+        assert_eq!(loaded.comparator.name(), "simpledb.bytewise");
+
+        assert!(matches!(Options::builder().block_size(0).build(), Err(Error::InvalidArgument(_))));
+        assert!(Options::builder().compression_per_level(vec![]).build().is_err());
+        assert!(Options::builder().min_blob_size(Some(0)).build().is_err());
+        assert!(Options::builder().compaction_style(CompactionStyle::Automatic { max_tables: 1 }).build().is_err());
+        assert!(Options::builder().blob_garbage_ratio(1.5).build().is_err());
+        assert!(Options::builder().create_if_missing(false).error_if_exists(true).build().is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt;
+
+/// Cuts the prefix the bloom filters of the sstables are built over, see `OptionsBuilder::prefix_extractor()`
+///
+/// `Engine::scan_prefix()` skips the tables whose filter doesn't have the prefix
+/// Every key starting with a prefix in the domain must have that same prefix
+/// The name is stored along with the filters, a filter built by another extractor is not used
+pub trait PrefixExtractor: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// None for a key out of the domain, such keys are never filtered
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]>;
+}
+
+impl fmt::Debug for dyn PrefixExtractor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PrefixExtractor({})", self.name())
+    }
+}
+
+/// First `len` bytes of the key, shorter keys are out of the domain
+#[derive(Debug)]
+pub struct FixedPrefix {
+    len: usize,
+    name: String,
+}
+
+impl FixedPrefix {
+    pub fn new(len: usize) -> Self {
+        Self { len, name: format!("simpledb.fixed_prefix.{}", len) }
+    }
+}
+
+impl PrefixExtractor for FixedPrefix {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        key.get(..self.len)
+    }
+}
+
+/// Key up to and including the `count`th `delimiter`, `tenant/<id>/` with `/` and 2
+#[derive(Debug)]
+pub struct DelimitedPrefix {
+    delimiter: u8,
+    count: usize,
+    name: String,
+}
+
+impl DelimitedPrefix {
+    pub fn new(delimiter: u8, count: usize) -> Self {
+        Self { delimiter, count, name: format!("simpledb.delimited_prefix.{}.{}", delimiter, count) }
+    }
+}
+
+impl PrefixExtractor for DelimitedPrefix {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        let mut delimiters = key.iter().enumerate().filter(|(_, byte)| **byte == self.delimiter);
+        let (end, _) = delimiters.nth(self.count.checked_sub(1)?)?;
+        Some(&key[..=end])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixes() {
+        let fixed = FixedPrefix::new(3);
+        assert_eq!(fixed.prefix(b"abcdef"), Some(&b"abc"[..]));
+        assert_eq!(fixed.prefix(b"ab"), None);
+
+        let tenant = DelimitedPrefix::new(b'/', 2);
+        assert_eq!(tenant.prefix(b"tenant/42/users/7"), Some(&b"tenant/42/"[..]));
+        assert_eq!(tenant.prefix(b"tenant/42/"), Some(&b"tenant/42/"[..]));
+        assert_eq!(tenant.prefix(b"tenant/42"), None);
+        assert_eq!(DelimitedPrefix::new(b'/', 0).prefix(b"a/b"), None);
+        assert_ne!(tenant.name(), DelimitedPrefix::new(b'/', 1).name());
+    }
+}
@@ -0,0 +1,85 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+/// Timestamps of the live snapshots with the number of handles pinned to each
+#[derive(Debug, Default)]
+pub struct SnapshotList {
+    snapshots: Mutex<BTreeMap<u128, usize>>,
+}
+
+impl SnapshotList {
+    /// Pins a new handle to the timestamp, released when the handle is dropped
+    pub fn acquire(self: &Arc<Self>, timestamp: u128) -> Snapshot {
+        *self.snapshots.lock().unwrap().entry(timestamp).or_insert(0) += 1;
+        Snapshot {
+            timestamp,
+            list: self.clone(),
+        }
+    }
+
+    /// Timestamps of the live snapshots, sorted
+    pub fn timestamps(&self) -> Vec<u128> {
+        self.snapshots.lock().unwrap().keys().copied().collect()
+    }
+
+    pub fn newest(&self) -> Option<u128> {
+        self.snapshots.lock().unwrap().keys().next_back().copied()
+    }
+
+    fn release(&self, timestamp: u128) {
+        let mut snapshots = self.snapshots.lock().unwrap();
+        if let Some(count) = snapshots.get_mut(&timestamp) {
+            *count -= 1;
+            if *count == 0 {
+                snapshots.remove(&timestamp);
+            }
+        }
+    }
+}
+
+/// Consistent view of the database, see `Engine::snapshot()`
+///
+/// Reads through the snapshot only see the entries written at or before its timestamp,
+/// compaction keeps the versions it reads till the handle is dropped
+#[derive(Debug)]
+pub struct Snapshot {
+    timestamp: u128,
+    list: Arc<SnapshotList>,
+}
+
+impl Snapshot {
+    /// Timestamp of the last entry the snapshot sees, timestamps double as sequence numbers
+    pub fn sequence(&self) -> u128 {
+        self.timestamp
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.list.release(self.timestamp);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acquire_and_release() {
+        let list = Arc::new(SnapshotList::default());
+        let first = list.acquire(5);
+        let second = list.acquire(5);
+        let third = list.acquire(9);
+        assert_eq!(list.timestamps(), vec![5, 9]);
+        assert_eq!(list.newest(), Some(9));
+
+        drop(third);
+        drop(first);
+        assert_eq!(list.timestamps(), vec![5]);
+        assert_eq!(second.sequence(), 5);
+        drop(second);
+        assert_eq!(list.newest(), None);
+    }
+}
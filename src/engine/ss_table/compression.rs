@@ -0,0 +1,85 @@
+use lz4_flex::block::DecompressError;
+use serde::{Deserialize, Serialize};
+
+/// Codec of one sstable block, its id is stored in the block trailer
+/// so tables written with different settings are read the same way
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Compression {
+    None,
+    /// Fast pure Rust codec, good enough for text-heavy values
+    #[default]
+    Lz4,
+}
+
+impl Compression {
+    pub fn id(&self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => 1,
+        }
+    }
+
+    /// None for an id no codec has
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Lz4),
+            _ => None,
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::None => data.to_vec(),
+            Compression::Lz4 => lz4_flex::compress_prepend_size(data),
+        }
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, DecompressError> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Lz4 => lz4_flex::decompress_size_prepended(data),
+        }
+    }
+}
+
+/// Bytes of the blocks before and after compression, trailers left out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    pub blocks: u64,
+    pub uncompressed_bytes: u64,
+    pub compressed_bytes: u64,
+}
+
+impl CompressionStats {
+    /// Uncompressed size over the size in the disk, 1 with nothing written
+    pub fn ratio(&self) -> f64 {
+        if self.compressed_bytes == 0 {
+            return 1.0;
+        }
+        self.uncompressed_bytes as f64 / self.compressed_bytes as f64
+    }
+
+    pub fn add(&mut self, other: &CompressionStats) {
+        self.blocks += other.blocks;
+        self.uncompressed_bytes += other.uncompressed_bytes;
+        self.compressed_bytes += other.compressed_bytes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let data = b"hello hello hello hello hello hello hello".repeat(10);
+        for compression in [Compression::None, Compression::Lz4] {
+            let compressed = compression.compress(&data);
+            assert_eq!(compression.decompress(&compressed).unwrap(), data);
+            assert_eq!(Compression::from_id(compression.id()).unwrap(), compression);
+        }
+        assert!(Compression::Lz4.compress(&data).len() < data.len() / 4);
+        assert!(Compression::from_id(9).is_none());
+    }
+}
@@ -0,0 +1,3 @@
+pub mod ss_table;
+pub mod compression;
+pub mod table_cache;
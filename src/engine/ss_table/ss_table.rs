@@ -0,0 +1,1047 @@
+use super::{
+    compression::{Compression, CompressionStats},
+    table_cache::TableCache,
+};
+use crate::{
+    engine::{
+        blob_file::blob_file::{BlobIndex, BlobWriter},
+        bloom_filter::bloom_filter::BloomFilter,
+        checksum::checksum::checksum,
+        comparator::comparator::Comparator,
+        lru_cache::lru_cache::LruCache,
+        prefix_extractor::prefix_extractor::PrefixExtractor,
+    },
+    error::error::Corruption,
+    Result,
+};
+use memmap2::Mmap;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    any::Any,
+    borrow::Cow,
+    cmp::Ordering,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    ops::{Bound, RangeBounds},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{self, AtomicBool, AtomicU64},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Serialize,Deserialize,Debug,Clone,PartialEq)]
+pub struct SSTableEntry {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    pub timestamp: u128,
+    // TombStone, the key was deleted and the value is empty
+    pub deleted: bool,
+    // operand of the merge operator, see `Engine::merge()`
+    pub merge: bool,
+    // micro seconds since the unix epoch, the value reads as deleted from then on
+    pub expires_at: Option<u128>,
+    // the value lives in a blob file, it is empty here till it is read, see `SSTable::separate_values()`
+    pub blob: Option<BlobIndex>
+}
+
+impl SSTableEntry {
+    pub fn new(
+        key: Vec<u8>,
+        value: Vec<u8>,
+        timestamp: u128,
+        deleted: bool
+    ) -> Self {
+        Self {
+            key,
+            value,
+            timestamp,
+            deleted,
+            merge: false,
+            expires_at: None,
+            blob: None
+        }
+    }
+
+    pub fn with_expiry(mut self, expires_at: Option<u128>) -> Self {
+        self.expires_at = expires_at;
+        self
+    }
+
+    pub fn merge_operand(key: Vec<u8>, operand: Vec<u8>, timestamp: u128) -> Self {
+        Self {
+            key,
+            value: operand,
+            timestamp,
+            deleted: false,
+            merge: true,
+            expires_at: None,
+            blob: None
+        }
+    }
+
+    /// Value seen by the readers, None for a deleted or expired key
+    /// A merge operand must be folded first, see `fold_versions()`
+    pub fn live_value(&self) -> Option<Vec<u8>> {
+        if self.deleted || self.is_expired() {
+            return None;
+        }
+        Some(self.value.clone())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros();
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// Deletes every key in `[start, end)` written before it, see `Engine::delete_range()`
+///
+/// Kept apart from the entries, the keys it covers may live in older memtables and sstables
+#[derive(Serialize,Deserialize,Debug,Clone,PartialEq)]
+pub struct RangeTombstone {
+    pub start: Vec<u8>,
+    pub end: Vec<u8>,
+    pub timestamp: u128,
+}
+
+impl RangeTombstone {
+    pub fn new(start: Vec<u8>, end: Vec<u8>, timestamp: u128) -> Self {
+        Self { start, end, timestamp }
+    }
+
+    pub fn contains(&self, comparator: &dyn Comparator, key: &[u8]) -> bool {
+        comparator.compare(&self.start, key).is_le() && comparator.compare(key, &self.end).is_lt()
+    }
+
+    /// Does it hide the version of the key written at `timestamp`
+    pub fn covers(&self, comparator: &dyn Comparator, key: &[u8], timestamp: u128) -> bool {
+        self.contains(comparator, key) && timestamp < self.timestamp
+    }
+
+    /// Timestamp of the newest tombstone written at or before `max_timestamp` covering the key,
+    /// every version of the key older than it reads as deleted
+    pub fn newest_covering(
+        comparator: &dyn Comparator,
+        tombstones: &[RangeTombstone],
+        key: &[u8],
+        max_timestamp: u128
+    ) -> Option<u128> {
+        tombstones
+            .iter()
+            .filter(|tombstone| tombstone.timestamp <= max_timestamp && tombstone.contains(comparator, key))
+            .map(|tombstone| tombstone.timestamp)
+            .max()
+    }
+}
+
+/// Last bytes of every table, a file without it is not a table
+const TABLE_MAGIC: u64 = 0x5349_4d50_4c45_4443;
+
+/// Offsets and sizes of the range tombstone and index blocks as little endian u64,
+/// the CRC32C of them as u32, then `TABLE_MAGIC`
+const FOOTER_SIZE: usize = 4 * 8 + 4 + 8;
+
+/// Last bytes of the tables with a filter block, its handle comes after the index in the footer
+const FILTER_TABLE_MAGIC: u64 = 0x5349_4d50_4c45_4446;
+
+const FILTER_FOOTER_SIZE: usize = 6 * 8 + 4 + 8;
+
+/// Codec id, then the CRC32C of the block and the codec id as a little endian u32
+const BLOCK_TRAILER_SIZE: usize = 1 + 4;
+
+/// Number of the next table file opened by this process, the cached blocks are keyed by it
+static NEXT_FILE_NUMBER: AtomicU64 = AtomicU64::new(1);
+
+/// Decoded blocks by file number and block offset, charged their uncompressed size
+pub type BlockCache = LruCache<(u64, u64), Arc<dyn Any + Send + Sync>>;
+
+/// How `SSTable::write()` lays out the table
+#[derive(Debug, Clone)]
+pub struct TableOptions {
+    pub compression: Compression,
+    // uncompressed size a data block is cut at, the entry crossing it stays in the block
+    pub block_size: usize,
+    // of the bloom filter, 0 writes the table without one
+    pub bloom_bits_per_key: usize,
+    // the filter is built over the prefixes it cuts instead of the whole keys
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    // values at least this big go to a blob file, see `separate_values()`, None keeps them in the table
+    pub min_blob_size: Option<usize>,
+}
+
+impl Default for TableOptions {
+    fn default() -> Self {
+        Self {
+            compression: Compression::default(),
+            block_size: 4 * 1024,
+            bloom_bits_per_key: 10,
+            prefix_extractor: None,
+            min_blob_size: None,
+        }
+    }
+}
+
+/// How `TableReader` reads a table file
+#[derive(Debug, Clone, Default)]
+pub struct TableReaderOptions {
+    pub verify_checksums: bool,
+    // the index, range tombstones and filter stay in the block cache as long as the file lives
+    pub pin_index_blocks: bool,
+    // blocks are decoded straight from a mapping of the file instead of being read in a buffer
+    pub allow_mmap_reads: bool,
+    // filters built by another extractor are not used
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+}
+
+/// Where a block lives in the file, trailer left out
+#[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq)]
+struct BlockHandle {
+    offset: u64,
+    size: u64,
+}
+
+/// One data block in the index, `key` sorts at or after every key in the block and before the next block
+#[derive(Serialize,Deserialize,Debug,Clone,PartialEq)]
+struct IndexEntry {
+    key: Vec<u8>,
+    handle: BlockHandle,
+}
+
+/// Handles of the blocks found from the footer
+struct Footer {
+    range_tombstones: BlockHandle,
+    index: BlockHandle,
+    filter: Option<BlockHandle>,
+}
+
+/// Bloom filter of a table, over the prefixes cut by the extractor it names or over the whole keys
+#[derive(Serialize,Deserialize,Debug,Clone,PartialEq)]
+struct FilterBlock {
+    prefix_extractor: Option<String>,
+    filter: BloomFilter,
+}
+
+/// Sorted String Table, the entries are sorted by key with the comparator of the database
+///
+/// In the file the entries are cut in data blocks, every block compressed on its own
+/// and followed by a trailer with the codec id and a checksum
+/// Range tombstones get their own block, in the order they were written,
+/// then come the index of the data blocks, the bloom filter if there is one and the footer pointing at them
+pub struct SSTable {
+    entries: Vec<SSTableEntry>,
+    range_tombstones: Vec<RangeTombstone>,
+}
+
+impl SSTable {
+    /// entries must already be sorted by key
+    pub fn new(entries: Vec<SSTableEntry>, range_tombstones: Vec<RangeTombstone>) -> Self {
+        Self { entries, range_tombstones }
+    }
+
+    /// Name of the table file, timestamp keeps the files in creation order
+    pub fn file_name(dir: &Path, timestamp: u128) -> PathBuf {
+        dir.join(timestamp.to_string() + ".sst")
+    }
+
+    /// Timestamp of the table file, None if it is not a table file
+    pub fn file_timestamp(file_name: &Path) -> Option<u128> {
+        if file_name.extension()? != "sst" {
+            return None;
+        }
+        file_name.file_stem()?.to_str()?.parse().ok()
+    }
+
+    /// Writes the table in the disk, every block compressed with the codec of the options
+    /// unless it doesn't get any smaller
+    ///
+    /// The table is written in a temporary file first and renamed once it is synced,
+    /// so a crash in between never leaves a half written `.sst` file behind
+    pub fn write(
+        &self,
+        file_name: &Path,
+        comparator: &dyn Comparator,
+        options: &TableOptions
+    ) -> Result<CompressionStats> {
+        let temp_file_name = file_name.with_extension("sst.tmp");
+        let file = OpenOptions::new().write(true).create(true).truncate(true).open(&temp_file_name)?;
+
+        // default capacity for bufwriter is 8KB
+        let mut writer = BlockWriter {
+            file: BufWriter::new(file),
+            offset: 0,
+            compression: options.compression,
+            stats: CompressionStats::default(),
+        };
+
+        let mut index: Vec<IndexEntry> = vec![];
+        let mut block_start = 0;
+        let mut block_size = 0;
+        for (i, entry) in self.entries.iter().enumerate() {
+            block_size += bincode::serialized_size(entry)?;
+            let last = i + 1 == self.entries.len();
+            if block_size < options.block_size as u64 && !last {
+                continue;
+            }
+            let handle = writer.write_block(&bincode::serialize(&self.entries[block_start..=i])?)?;
+            let key = match self.entries.get(i + 1) {
+                Some(next) => comparator.find_shortest_separator(&entry.key, &next.key),
+                None => entry.key.clone(),
+            };
+            index.push(IndexEntry { key, handle });
+            block_start = i + 1;
+            block_size = 0;
+        }
+        let range_tombstones = writer.write_block(&bincode::serialize(&self.range_tombstones)?)?;
+        let index = writer.write_block(&bincode::serialize(&index)?)?;
+        let mut fields = vec![range_tombstones.offset, range_tombstones.size, index.offset, index.size];
+        let mut magic = TABLE_MAGIC;
+        if options.bloom_bits_per_key > 0 {
+            let filter = writer.write_block(&bincode::serialize(&self.filter_block(options))?)?;
+            fields.extend([filter.offset, filter.size]);
+            magic = FILTER_TABLE_MAGIC;
+        }
+
+        let mut footer: Vec<u8> = fields.iter().flat_map(|field| field.to_le_bytes()).collect();
+        footer.extend_from_slice(&checksum(&footer).to_le_bytes());
+        footer.extend_from_slice(&magic.to_le_bytes());
+        writer.file.write_all(&footer)?;
+        writer.file.flush()?;
+        writer.file.get_ref().sync_all()?;
+
+        fs::rename(&temp_file_name, file_name)?;
+        Ok(writer.stats)
+    }
+
+    /// Moves the values of at least `min_blob_size` bytes to a new blob file in `dir` named after `timestamp`,
+    /// the entries keep a `BlobIndex` to them, returns the blob file if one was written
+    ///
+    /// Values read from a blob file keep pointing at it and are dropped from the entries,
+    /// TombStones and merge operands always stay in the table
+    pub fn separate_values(
+        &mut self,
+        dir: &Path,
+        timestamp: u128,
+        min_blob_size: Option<usize>
+    ) -> Result<Option<PathBuf>> {
+        let mut writer = None;
+        for entry in &mut self.entries {
+            if entry.blob.is_some() {
+                entry.value = vec![];
+                continue;
+            }
+            let large = min_blob_size.is_some_and(|min_blob_size| entry.value.len() >= min_blob_size);
+            if !large || entry.deleted || entry.merge {
+                continue;
+            }
+            let writer = match &mut writer {
+                Some(writer) => writer,
+                None => writer.insert(BlobWriter::create(dir, timestamp)?),
+            };
+            entry.blob = Some(writer.append(&entry.key, &entry.value)?);
+            entry.value = vec![];
+        }
+        writer.map(BlobWriter::finish).transpose()
+    }
+
+    /// Bloom filter over the prefixes or the keys of the entries, each one added once
+    fn filter_block(&self, options: &TableOptions) -> FilterBlock {
+        let mut keys: Vec<&[u8]> = match &options.prefix_extractor {
+            Some(extractor) => self.entries.iter().filter_map(|entry| extractor.prefix(&entry.key)).collect(),
+            None => self.entries.iter().map(|entry| entry.key.as_slice()).collect(),
+        };
+        keys.dedup();
+        FilterBlock {
+            prefix_extractor: options.prefix_extractor.as_ref().map(|extractor| extractor.name().to_owned()),
+            filter: BloomFilter::new(&keys, options.bloom_bits_per_key),
+        }
+    }
+
+    /// Reads the whole table from the disk
+    ///
+    /// Fails with `Corruption` if a block doesn't decode, or with `verify_checksums`
+    /// if its checksum doesn't match, the footer is always checked
+    pub fn read(file_name: &Path, verify_checksums: bool) -> Result<Self> {
+        let data = fs::read(file_name)?;
+        let reader = BlockReader { file_name, data: &data, verify_checksums };
+        let Some(footer) = reader.footer()? else {
+            return Err(Corruption::new(file_name, data.len() as u64, "table without a footer").into());
+        };
+
+        let index: Vec<IndexEntry> = reader.block(footer.index)?;
+        let mut entries = vec![];
+        for index_entry in index {
+            entries.extend(reader.block::<Vec<SSTableEntry>>(index_entry.handle)?);
+        }
+        let range_tombstones = reader.block(footer.range_tombstones)?;
+        Ok(Self { entries, range_tombstones })
+    }
+
+    /// Binary search for the newest version of the key written at or before `max_timestamp`,
+    /// entries are sorted by key and newest version first
+    pub fn get(&self, comparator: &dyn Comparator, key: &[u8], max_timestamp: u128) -> Option<&SSTableEntry> {
+        let index = self.entries.partition_point(|entry| {
+            comparator
+                .compare(&entry.key, key)
+                .then(max_timestamp.cmp(&entry.timestamp))
+                .is_lt()
+        });
+        self.entries.get(index).filter(|entry| entry.key == key)
+    }
+
+    /// Every version of the keys in the range
+    pub fn range<R: RangeBounds<Vec<u8>>>(&self, comparator: &dyn Comparator, range: R) -> &[SSTableEntry] {
+        // number of entries sorted before the key, along with the ones equal to it when `including_key`
+        let after = |key: &Vec<u8>, including_key: bool| {
+            self.entries.partition_point(|entry| match comparator.compare(&entry.key, key) {
+                Ordering::Less => true,
+                Ordering::Equal => including_key,
+                Ordering::Greater => false,
+            })
+        };
+        let start = match range.start_bound() {
+            Bound::Included(start) => after(start, false),
+            Bound::Excluded(start) => after(start, true),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => after(end, true),
+            Bound::Excluded(end) => after(end, false),
+            Bound::Unbounded => self.entries.len(),
+        };
+        &self.entries[start..end.max(start)]
+    }
+
+    pub fn entries(&self) -> &[SSTableEntry] {
+        &self.entries
+    }
+
+    pub fn into_entries(self) -> Vec<SSTableEntry> {
+        self.entries
+    }
+
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+}
+
+/// Appends blocks with their trailer to a table file
+struct BlockWriter {
+    file: BufWriter<std::fs::File>,
+    offset: u64,
+    compression: Compression,
+    stats: CompressionStats,
+}
+
+impl BlockWriter {
+    fn write_block(&mut self, block: &[u8]) -> Result<BlockHandle> {
+        let mut compression = self.compression;
+        let mut compressed = compression.compress(block);
+        if compressed.len() >= block.len() {
+            compression = Compression::None;
+            compressed = block.to_vec();
+        }
+        let mut trailer = vec![compression.id()];
+        let crc = crc32c::crc32c_append(checksum(&compressed), &trailer);
+        trailer.extend_from_slice(&crc.to_le_bytes());
+        self.file.write_all(&compressed)?;
+        self.file.write_all(&trailer)?;
+
+        let handle = BlockHandle { offset: self.offset, size: compressed.len() as u64 };
+        self.offset += (compressed.len() + BLOCK_TRAILER_SIZE) as u64;
+        self.stats.blocks += 1;
+        self.stats.uncompressed_bytes += block.len() as u64;
+        self.stats.compressed_bytes += compressed.len() as u64;
+        Ok(handle)
+    }
+}
+
+/// Decodes the blocks of a table file read in memory
+struct BlockReader<'a> {
+    file_name: &'a Path,
+    data: &'a [u8],
+    verify_checksums: bool,
+}
+
+impl BlockReader<'_> {
+    /// None if the file has no footer
+    fn footer(&self) -> Result<Option<Footer>> {
+        let tail = &self.data[self.data.len().saturating_sub(FILTER_FOOTER_SIZE)..];
+        decode_footer(self.file_name, self.data.len() as u64, tail)
+    }
+
+    fn block<T: DeserializeOwned>(&self, handle: BlockHandle) -> Result<T> {
+        let start = handle.offset as usize;
+        let end = start.checked_add(handle.size as usize + BLOCK_TRAILER_SIZE);
+        let Some(end) = end.filter(|end| *end <= self.data.len()) else {
+            return Err(Corruption::new(self.file_name, handle.offset, "block past the end of the table").into());
+        };
+        let (block, _) = decode_block(self.file_name, handle.offset, &self.data[start..end], self.verify_checksums)?;
+        Ok(block)
+    }
+}
+
+/// Footer in `tail`, the last `FILTER_FOOTER_SIZE` bytes of the file or the whole file if it is shorter,
+/// None if there is no footer
+fn decode_footer(file_name: &Path, file_size: u64, tail: &[u8]) -> Result<Option<Footer>> {
+    let Some(magic) = tail.len().checked_sub(8).map(|start| &tail[start..]) else {
+        return Ok(None);
+    };
+    let footer_size = match u64::from_le_bytes(magic.try_into().unwrap()) {
+        TABLE_MAGIC => FOOTER_SIZE,
+        FILTER_TABLE_MAGIC => FILTER_FOOTER_SIZE,
+        _ => return Ok(None),
+    };
+    let Some(start) = tail.len().checked_sub(footer_size) else {
+        return Ok(None);
+    };
+    let footer = &tail[start..];
+    let fields_size = footer_size - 4 - 8;
+    let crc = u32::from_le_bytes(footer[fields_size..fields_size + 4].try_into().unwrap());
+    if checksum(&footer[..fields_size]) != crc {
+        let offset = file_size - footer_size as u64;
+        return Err(Corruption::new(file_name, offset, "footer checksum mismatch").into());
+    }
+
+    let fields: Vec<u64> =
+        footer[..fields_size].chunks(8).map(|field| u64::from_le_bytes(field.try_into().unwrap())).collect();
+    let handle = |i: usize| BlockHandle { offset: fields[i], size: fields[i + 1] };
+    Ok(Some(Footer {
+        range_tombstones: handle(0),
+        index: handle(2),
+        filter: (fields.len() > 4).then(|| handle(4)),
+    }))
+}
+
+/// Decoded content of a block read along with its trailer and its uncompressed size,
+/// the codec comes from the trailer
+fn decode_block<T: DeserializeOwned>(
+    file_name: &Path,
+    offset: u64,
+    data: &[u8],
+    verify_checksums: bool
+) -> Result<(T, usize)> {
+    let corruption = |reason: String| Corruption::new(file_name, offset, reason);
+    let end = data.len() - BLOCK_TRAILER_SIZE;
+    let codec = data[end];
+    let crc = u32::from_le_bytes(data[end + 1..].try_into().unwrap());
+    if verify_checksums && checksum(&data[..=end]) != crc {
+        return Err(corruption("block checksum mismatch".to_owned()).into());
+    }
+    let compression =
+        Compression::from_id(codec).ok_or_else(|| corruption(format!("unknown compression codec {}", codec)))?;
+    // an uncompressed block is decoded where it is, from the mapping of the file when there is one
+    let block = match compression {
+        Compression::None => Cow::Borrowed(&data[..end]),
+        _ => Cow::Owned(compression.decompress(&data[..end]).map_err(|err| corruption(err.to_string()))?),
+    };
+    let decoded = bincode::deserialize(&block).map_err(|err| corruption(err.to_string()))?;
+    Ok((decoded, block.len()))
+}
+
+/// Reads the blocks of one table file as they are needed, through the block cache, see `TableCache`
+///
+/// The index and range tombstone blocks are read when it is opened, the data blocks by `get()` and `range()`
+/// With `pin_index_blocks` the index and range tombstones stay in the cache until the file is dropped
+pub struct TableReader {
+    source: TableSource,
+    file_name: PathBuf,
+    number: u64,
+    block_cache: Arc<BlockCache>,
+    verify_checksums: bool,
+    prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    content: TableContent,
+}
+
+/// Where the bytes of the blocks come from
+enum TableSource {
+    // read with pread in a new buffer for every block
+    File(File),
+    Mmap(Mmap),
+}
+
+/// Blocks read when the table is opened
+#[derive(Default)]
+struct TableContent {
+    index: Arc<Vec<IndexEntry>>,
+    range_tombstones: Arc<Vec<RangeTombstone>>,
+    filter: Option<Arc<FilterBlock>>,
+}
+
+impl TableReader {
+    pub fn open(table_file: &SSTableFile, block_cache: Arc<BlockCache>, options: &TableReaderOptions) -> Result<Self> {
+        let file_name = table_file.path().to_path_buf();
+        let file = File::open(&file_name)?;
+        let size = file.metadata()?.len();
+        let source = match options.allow_mmap_reads {
+            // SAFETY: table files are never written again once renamed in place,
+            // they are only removed, which leaves the mapping alone
+            true => TableSource::Mmap(unsafe { Mmap::map(&file)? }),
+            false => TableSource::File(file),
+        };
+        let mut reader = Self {
+            source,
+            file_name,
+            number: table_file.number,
+            block_cache,
+            verify_checksums: options.verify_checksums,
+            prefix_extractor: options.prefix_extractor.clone(),
+            content: TableContent::default(),
+        };
+
+        let tail_size = size.min(FILTER_FOOTER_SIZE as u64);
+        let tail = reader.read(size - tail_size, tail_size as usize)?;
+        let Some(footer) = decode_footer(&reader.file_name, size, &tail)? else {
+            return Err(Corruption::new(&reader.file_name, size, "table without a footer").into());
+        };
+        let pinned = options.pin_index_blocks;
+        reader.content = TableContent {
+            index: reader.block(footer.index, pinned)?,
+            range_tombstones: reader.block(footer.range_tombstones, pinned)?,
+            filter: footer.filter.map(|filter| reader.block(filter, pinned)).transpose()?,
+        };
+        Ok(reader)
+    }
+
+    /// Same as `SSTable::get()`, reading only the blocks which may hold the key
+    pub fn get(&self, comparator: &dyn Comparator, key: &[u8], max_timestamp: u128) -> Result<Option<SSTableEntry>> {
+        let index = &self.content.index;
+        let first = index.partition_point(|index_entry| comparator.compare(&index_entry.key, key).is_lt());
+        for index_entry in &index[first..] {
+            let entries: Arc<Vec<SSTableEntry>> = self.block(index_entry.handle, false)?;
+            let position = entries.partition_point(|entry| {
+                comparator
+                    .compare(&entry.key, key)
+                    .then(max_timestamp.cmp(&entry.timestamp))
+                    .is_lt()
+            });
+            match entries.get(position) {
+                Some(entry) if entry.key == key => return Ok(Some(entry.clone())),
+                Some(_) => return Ok(None),
+                // older versions of the key may go on in the next block
+                None => continue,
+            }
+        }
+        Ok(None)
+    }
+
+    /// Same as `SSTable::range()`, reading through the block cache from the first block which may hold the start
+    pub fn range<R: RangeBounds<Vec<u8>>>(&self, comparator: &dyn Comparator, range: R) -> Result<Vec<SSTableEntry>> {
+        let index = &self.content.index;
+        let after_start = |key: &[u8]| match range.start_bound() {
+            Bound::Included(start) => comparator.compare(key, start).is_ge(),
+            Bound::Excluded(start) => comparator.compare(key, start).is_gt(),
+            Bound::Unbounded => true,
+        };
+        let before_end = |key: &[u8]| match range.end_bound() {
+            Bound::Included(end) => comparator.compare(key, end).is_le(),
+            Bound::Excluded(end) => comparator.compare(key, end).is_lt(),
+            Bound::Unbounded => true,
+        };
+
+        let first = index.partition_point(|index_entry| !after_start(&index_entry.key));
+        let mut entries = vec![];
+        for index_entry in &index[first..] {
+            let block: Arc<Vec<SSTableEntry>> = self.block(index_entry.handle, false)?;
+            let in_range = block.iter().filter(|entry| after_start(&entry.key) && before_end(&entry.key));
+            entries.extend(in_range.cloned());
+            // the next blocks only hold keys after this one
+            if !before_end(&index_entry.key) {
+                break;
+            }
+        }
+        Ok(entries)
+    }
+
+    /// What the filter of the table says about the key, None if it has no filter which can tell
+    ///
+    /// With a prefix filter the prefix of the key is looked up, keys out of the domain can't be told
+    pub fn may_contain(&self, key: &[u8]) -> Option<bool> {
+        let filter = self.filter()?;
+        match &filter.prefix_extractor {
+            None => Some(filter.filter.may_contain(key)),
+            Some(_) => self.may_contain_prefix(key),
+        }
+    }
+
+    /// False if no key of the table starts with `prefix`, None if the table has no usable prefix filter
+    ///
+    /// `prefix` may be longer than what the extractor cuts, it is looked up by its own prefix
+    pub fn may_contain_prefix(&self, prefix: &[u8]) -> Option<bool> {
+        let filter = self.filter()?;
+        let extractor = self.prefix_extractor.as_ref()?;
+        if filter.prefix_extractor.as_deref() != Some(extractor.name()) {
+            return None;
+        }
+        Some(filter.filter.may_contain(extractor.prefix(prefix)?))
+    }
+
+    fn filter(&self) -> Option<&FilterBlock> {
+        self.content.filter.as_deref()
+    }
+
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.content.range_tombstones
+    }
+
+    /// Decoded block from the cache, read from the file and added to the cache on a miss
+    fn block<T: DeserializeOwned + Send + Sync + 'static>(&self, handle: BlockHandle, pinned: bool) -> Result<Arc<T>> {
+        let cache_key = (self.number, handle.offset);
+        if let Some(block) = self.block_cache.get(&cache_key).and_then(|block| block.downcast().ok()) {
+            return Ok(block);
+        }
+
+        let data = self.read(handle.offset, handle.size as usize + BLOCK_TRAILER_SIZE)?;
+        let (block, size) = decode_block::<T>(&self.file_name, handle.offset, &data, self.verify_checksums)?;
+        let block = Arc::new(block);
+        match pinned {
+            true => self.block_cache.insert_pinned(cache_key, block.clone(), size),
+            false => self.block_cache.insert(cache_key, block.clone(), size),
+        }
+        Ok(block)
+    }
+
+    /// `size` bytes of the file from `offset`, borrowed from the mapping if the file is mapped
+    fn read(&self, offset: u64, size: usize) -> Result<Cow<'_, [u8]>> {
+        let past_end = || Corruption::new(&self.file_name, offset, "block past the end of the table").into();
+        match &self.source {
+            TableSource::File(file) => {
+                let mut data = vec![0; size];
+                file.read_exact_at(&mut data, offset).map_err(|err| match err.kind() {
+                    io::ErrorKind::UnexpectedEof => past_end(),
+                    _ => crate::Error::from(err),
+                })?;
+                Ok(Cow::Owned(data))
+            }
+            TableSource::Mmap(mmap) => {
+                let start = offset as usize;
+                let data = start.checked_add(size).and_then(|end| mmap.get(start..end)).ok_or_else(past_end)?;
+                Ok(Cow::Borrowed(data))
+            }
+        }
+    }
+}
+
+/// Table file in the list of live tables, readers hold it while they read the file
+///
+/// Compaction only marks its inputs obsolete,
+/// the file is removed once the last reader is done with it
+#[derive(Debug)]
+pub struct SSTableFile {
+    file_name: PathBuf,
+    // reader and blocks of the file in the cache, evicted once the file is dropped
+    number: u64,
+    table_cache: Arc<TableCache>,
+    obsolete: AtomicBool,
+}
+
+impl SSTableFile {
+    pub fn new(file_name: PathBuf, table_cache: Arc<TableCache>) -> Self {
+        Self {
+            file_name,
+            number: NEXT_FILE_NUMBER.fetch_add(1, atomic::Ordering::Relaxed),
+            table_cache,
+            obsolete: AtomicBool::new(false),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.file_name
+    }
+
+    /// Number of the file in this process, never reused
+    pub fn number(&self) -> u64 {
+        self.number
+    }
+
+    pub fn mark_obsolete(&self) {
+        self.obsolete.store(true, atomic::Ordering::Release);
+    }
+}
+
+impl Drop for SSTableFile {
+    fn drop(&mut self) {
+        self.table_cache.evict(self.number);
+        if self.obsolete.load(atomic::Ordering::Acquire) {
+            let _ = fs::remove_file(&self.file_name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::comparator::comparator::{BytewiseComparator, ReverseBytewiseComparator},
+        Error,
+    };
+
+    #[test]
+    fn write_and_read() {
+        let dir = std::env::temp_dir().join(format!("simpledb-ss-table-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file_name = SSTable::file_name(&dir, 7);
+
+        let mut entries: Vec<SSTableEntry> = (10..20u8)
+            .map(|i| SSTableEntry::new(vec![i], vec![i + 1], i as u128, i == 12))
+            .collect();
+        // newer version of 15
+        entries.insert(5, SSTableEntry::new(vec![15], vec![50], 50, false));
+        let range_tombstones = vec![RangeTombstone::new(vec![16], vec![18], 17)];
+        let table = SSTable::new(entries, range_tombstones.clone());
+        table.write(&file_name, &BytewiseComparator, &TableOptions::default()).unwrap();
+
+        let table = SSTable::read(&file_name, true).unwrap();
+        assert_eq!(table.entries().len(), 11);
+        assert_eq!(table.range_tombstones(), range_tombstones.as_slice());
+        assert_eq!(table.get(&BytewiseComparator, &[15], u128::MAX).unwrap().value, vec![50]);
+        assert_eq!(table.get(&BytewiseComparator, &[15], 49).unwrap().value, vec![16]);
+        assert_eq!(table.get(&BytewiseComparator, &[15], 15).unwrap().timestamp, 15);
+        assert!(table.get(&BytewiseComparator, &[15], 14).is_none());
+        assert_eq!(table.get(&BytewiseComparator, &[15], u128::MAX).unwrap().live_value(), Some(vec![50]));
+        assert_eq!(table.get(&BytewiseComparator, &[12], u128::MAX).unwrap().live_value(), None);
+        assert!(table.get(&BytewiseComparator, &[9], u128::MAX).is_none());
+        assert!(table.get(&BytewiseComparator, &[20], u128::MAX).is_none());
+        assert_eq!(SSTable::file_timestamp(&file_name), Some(7));
+
+        assert_eq!(table.range(&BytewiseComparator, vec![14]..vec![16]).len(), 3);
+        assert_eq!(table.range(&BytewiseComparator, vec![18]..).len(), 2);
+        assert_eq!(table.range(&BytewiseComparator, vec![16]..vec![14]).len(), 0);
+
+        // 16@16 is covered, 17@17 was written along with the tombstone
+        let newest_covering = |key: u8, max_timestamp| {
+            RangeTombstone::newest_covering(&BytewiseComparator, table.range_tombstones(), &[key], max_timestamp)
+        };
+        assert_eq!(newest_covering(16, u128::MAX), Some(17));
+        assert_eq!(newest_covering(16, 16), None);
+        assert_eq!(newest_covering(18, u128::MAX), None);
+        assert!(table.range_tombstones()[0].covers(&BytewiseComparator, &[16], 16));
+        assert!(!table.range_tombstones()[0].covers(&BytewiseComparator, &[17], 17));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn blocks_with_their_own_codec() {
+        let dir = std::env::temp_dir().join(format!("simpledb-ss-table-blocks-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let entries: Vec<SSTableEntry> = (0..2000u32)
+            .map(|i| SSTableEntry::new(i.to_be_bytes().to_vec(), b"some text value ".repeat(4), i as u128, false))
+            .collect();
+        let table = SSTable::new(entries.clone(), vec![]);
+        let options = |compression| TableOptions { compression, block_size: 4 * 1024, ..TableOptions::default() };
+
+        let lz4 = SSTable::file_name(&dir, 1);
+        let stats = table.write(&lz4, &BytewiseComparator, &options(Compression::Lz4)).unwrap();
+        // data blocks, range tombstones and index
+        assert!(stats.blocks > 3);
+        assert!(stats.ratio() > 2.0);
+        let none = SSTable::file_name(&dir, 2);
+        let stats = table.write(&none, &BytewiseComparator, &options(Compression::None)).unwrap();
+        assert_eq!(stats.ratio(), 1.0);
+        assert!(fs::metadata(&lz4).unwrap().len() * 2 < fs::metadata(&none).unwrap().len());
+        for file_name in [&lz4, &none] {
+            assert_eq!(SSTable::read(file_name, true).unwrap().entries(), entries.as_slice());
+        }
+
+        // no footer, not a table this version wrote
+        let truncated = SSTable::file_name(&dir, 3);
+        fs::write(&truncated, &fs::read(&lz4).unwrap()[..100]).unwrap();
+        assert!(matches!(SSTable::read(&truncated, true), Err(Error::Corruption(_))));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn checksums_catch_flipped_bits() {
+        let dir = std::env::temp_dir().join(format!("simpledb-ss-table-checksums-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file_name = SSTable::file_name(&dir, 1);
+        let entries: Vec<SSTableEntry> =
+            (0..50u8).map(|i| SSTableEntry::new(vec![i], vec![i; 16], i as u128, false)).collect();
+        // no filter, the footer without its handle
+        let options =
+            TableOptions { compression: Compression::None, bloom_bits_per_key: 0, ..TableOptions::default() };
+        SSTable::new(entries, vec![]).write(&file_name, &BytewiseComparator, &options).unwrap();
+        let content = fs::read(&file_name).unwrap();
+        let corruption = |err: Error| match err {
+            Error::Corruption(corruption) => corruption,
+            err => panic!("{}", err),
+        };
+
+        // last byte of a value in the first block
+        let mut flipped = content.clone();
+        flipped[40] ^= 1;
+        fs::write(&file_name, &flipped).unwrap();
+        let err = corruption(SSTable::read(&file_name, true).err().unwrap());
+        assert_eq!((err.file_name, err.offset), (file_name.clone(), 0));
+        // still decodes, with the wrong value
+        assert_eq!(SSTable::read(&file_name, false).unwrap().entries().len(), 50);
+
+        let mut flipped = content.clone();
+        flipped[content.len() - FOOTER_SIZE] ^= 1;
+        fs::write(&file_name, &flipped).unwrap();
+        let err = corruption(SSTable::read(&file_name, false).err().unwrap());
+        assert_eq!(err.offset, (content.len() - FOOTER_SIZE) as u64);
+
+        fs::write(&file_name, &content[..content.len() / 2]).unwrap();
+        corruption(SSTable::read(&file_name, true).err().unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn table_reader_reads_single_blocks() {
+        let dir = std::env::temp_dir().join(format!("simpledb-ss-table-reader-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file_name = SSTable::file_name(&dir, 1);
+        // 30 versions of 5, more than one block
+        let mut entries: Vec<SSTableEntry> =
+            (0..10u8).map(|i| SSTableEntry::new(vec![i], vec![i; 32], 1, false)).collect();
+        let versions = (2..32u128).rev().map(|timestamp| SSTableEntry::new(vec![5], vec![0; 32], timestamp, false));
+        entries.splice(5..5, versions);
+        let range_tombstones = vec![RangeTombstone::new(vec![8], vec![9], 40)];
+        let options = TableOptions { block_size: 256, ..TableOptions::default() };
+        SSTable::new(entries, range_tombstones.clone()).write(&file_name, &BytewiseComparator, &options).unwrap();
+
+        let table_cache = Arc::new(TableCache::new(10, 1024 * 1024));
+        let table_file = SSTableFile::new(file_name.clone(), table_cache.clone());
+        let block_cache = Arc::new(BlockCache::new(1024 * 1024));
+        let options = TableReaderOptions { verify_checksums: true, pin_index_blocks: true, ..Default::default() };
+        let reader = TableReader::open(&table_file, block_cache.clone(), &options).unwrap();
+        assert_eq!(reader.range_tombstones(), range_tombstones.as_slice());
+        let pinned = block_cache.stats().pinned_usage;
+        assert!(pinned > 0);
+
+        let get = |key: u8, max_timestamp| reader.get(&BytewiseComparator, &[key], max_timestamp).unwrap();
+        assert_eq!(get(5, u128::MAX).unwrap().timestamp, 31);
+        // in the last block of the key
+        assert_eq!(get(5, 3).unwrap().timestamp, 3);
+        assert_eq!(get(5, 1).unwrap().timestamp, 1);
+        assert_eq!(get(5, 0), None);
+        assert_eq!(get(9, u128::MAX).unwrap().value, vec![9; 32]);
+        assert_eq!(get(10, u128::MAX), None);
+
+        assert_eq!(get(0, u128::MAX).unwrap().value, vec![0; 32]);
+        let stats = block_cache.stats();
+        assert_eq!(get(0, u128::MAX).unwrap().value, vec![0; 32]);
+        assert_eq!((block_cache.stats().hits, block_cache.stats().misses), (stats.hits + 1, stats.misses));
+
+        // the same entries as the whole table, the versions of 5 span several blocks
+        let table = SSTable::read(&file_name, true).unwrap();
+        let bounds = [Bound::Unbounded, Bound::Included(vec![5]), Bound::Excluded(vec![5]), Bound::Excluded(vec![10])];
+        for start in &bounds {
+            for end in &bounds {
+                let range = (start.clone(), end.clone());
+                let expected = table.range(&BytewiseComparator, range.clone()).to_vec();
+                assert_eq!(reader.range(&BytewiseComparator, range).unwrap(), expected);
+            }
+        }
+        let stats = block_cache.stats();
+        assert_eq!(reader.range(&BytewiseComparator, vec![9]..).unwrap().len(), 1);
+        // only the last block
+        assert_eq!(block_cache.stats().hits, stats.hits + 1);
+
+        assert!(pinned < 1024);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn mapped_reads_match_buffered_reads() {
+        let dir = std::env::temp_dir().join(format!("simpledb-ss-table-mmap-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file_name = SSTable::file_name(&dir, 1);
+        let entries: Vec<SSTableEntry> =
+            (0..200u8).map(|i| SSTableEntry::new(vec![i], vec![i; 64], i as u128, false)).collect();
+        let table_options = TableOptions { compression: Compression::None, block_size: 512, ..TableOptions::default() };
+        SSTable::new(entries, vec![]).write(&file_name, &BytewiseComparator, &table_options).unwrap();
+
+        let table_cache = Arc::new(TableCache::new(10, 0));
+        let table_file = SSTableFile::new(file_name.clone(), table_cache);
+        let open = |allow_mmap_reads| {
+            let options = TableReaderOptions { verify_checksums: true, allow_mmap_reads, ..Default::default() };
+            TableReader::open(&table_file, Arc::new(BlockCache::new(0)), &options)
+        };
+        let (buffered, mapped) = (open(false).unwrap(), open(true).unwrap());
+        for key in [0, 99, 199, 200] {
+            let get = |reader: &TableReader| reader.get(&BytewiseComparator, &[key], u128::MAX).unwrap();
+            assert_eq!(get(&buffered), get(&mapped));
+        }
+        assert_eq!(mapped.get(&BytewiseComparator, &[150], u128::MAX).unwrap().unwrap().value, vec![150; 64]);
+
+        // a block past the end of the mapping is reported, not read
+        let content = fs::read(&file_name).unwrap();
+        let mut truncated = content[..content.len() - FOOTER_SIZE - 600].to_vec();
+        truncated.extend_from_slice(&content[content.len() - FOOTER_SIZE..]);
+        fs::write(&file_name, &truncated).unwrap();
+        for allow_mmap_reads in [false, true] {
+            assert!(matches!(open(allow_mmap_reads), Err(Error::Corruption(_))));
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn filters_rule_out_missing_keys() {
+        use crate::engine::prefix_extractor::prefix_extractor::{DelimitedPrefix, FixedPrefix};
+
+        let dir = std::env::temp_dir().join(format!("simpledb-ss-table-filters-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let entries: Vec<SSTableEntry> = (0..300u32)
+            .map(|i| SSTableEntry::new(format!("tenant/{}/{:03}", i / 100, i).into_bytes(), vec![], 1, false))
+            .collect();
+        let table = SSTable::new(entries, vec![]);
+        let table_cache = Arc::new(TableCache::new(10, 0));
+        let tenant: Arc<dyn PrefixExtractor> = Arc::new(DelimitedPrefix::new(b'/', 2));
+        let open = |timestamp, table_options: TableOptions, prefix_extractor| {
+            let file_name = SSTable::file_name(&dir, timestamp);
+            table.write(&file_name, &BytewiseComparator, &table_options).unwrap();
+            let table_file = SSTableFile::new(file_name, table_cache.clone());
+            let options = TableReaderOptions { prefix_extractor, ..Default::default() };
+            TableReader::open(&table_file, Arc::new(BlockCache::new(0)), &options).unwrap()
+        };
+
+        let whole_keys = open(1, TableOptions::default(), None);
+        assert_eq!(whole_keys.may_contain(b"tenant/1/150"), Some(true));
+        let missing = |i: u32| format!("missing/{}", i).into_bytes();
+        let false_positives = (0..300u32).filter(|i| whole_keys.may_contain(&missing(*i)) == Some(true)).count();
+        assert!(false_positives < 15);
+        assert_eq!(whole_keys.may_contain_prefix(b"tenant/1/"), None);
+
+        let prefixes = TableOptions { prefix_extractor: Some(tenant.clone()), ..TableOptions::default() };
+        let reader = open(2, prefixes.clone(), Some(tenant.clone()));
+        assert_eq!(reader.may_contain_prefix(b"tenant/2/"), Some(true));
+        // looked up by `tenant/2/`
+        assert_eq!(reader.may_contain_prefix(b"tenant/2/05"), Some(true));
+        assert_eq!(reader.may_contain_prefix(b"tenant/7/"), Some(false));
+        assert_eq!(reader.may_contain(b"tenant/7/001"), Some(false));
+        // out of the domain
+        assert_eq!(reader.may_contain_prefix(b"tenant/"), None);
+        assert_eq!(reader.get(&BytewiseComparator, b"tenant/2/250", u128::MAX).unwrap().unwrap().timestamp, 1);
+
+        // built by another extractor
+        let other = open(3, prefixes, Some(Arc::new(FixedPrefix::new(9))));
+        assert_eq!(other.may_contain_prefix(b"tenant/7/"), None);
+        let no_filter = open(4, TableOptions { bloom_bits_per_key: 0, ..TableOptions::default() }, Some(tenant));
+        assert_eq!(no_filter.may_contain_prefix(b"tenant/7/"), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sorted_by_comparator() {
+        let comparator = ReverseBytewiseComparator;
+        let entries: Vec<SSTableEntry> =
+            (1..6u8).rev().map(|i| SSTableEntry::new(vec![i], vec![i], i as u128, false)).collect();
+        let table = SSTable::new(entries, vec![]);
+
+        assert_eq!(table.get(&comparator, &[2], u128::MAX).unwrap().value, vec![2]);
+        assert!(table.get(&comparator, &[2], 1).is_none());
+        // from 4 down to 2
+        assert_eq!(table.range(&comparator, vec![4]..vec![1]).len(), 3);
+        assert!(RangeTombstone::new(vec![4], vec![1], 9).covers(&comparator, &[3], 3));
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::Arc;
+
+use super::ss_table::{BlockCache, SSTableFile, TableReader, TableReaderOptions};
+use crate::{
+    engine::lru_cache::lru_cache::{CacheStats, LruCache},
+    Result,
+};
+
+/// Table readers kept open by file number, each one holds a file handle
+/// along with the index and range tombstones of its table
+///
+/// At most `max_open_files` are open at once, the least recently used one is closed to open another
+/// Owns the block cache as well, a file dropped from the live tables takes its reader and blocks along
+#[derive(Debug)]
+pub struct TableCache {
+    readers: LruCache<u64, Arc<TableReader>>,
+    block_cache: Arc<BlockCache>,
+}
+
+impl TableCache {
+    pub fn new(max_open_files: usize, block_cache_size: usize) -> Self {
+        Self {
+            readers: LruCache::new(max_open_files),
+            block_cache: Arc::new(BlockCache::new(block_cache_size)),
+        }
+    }
+
+    /// Reader of the table, opened if it isn't in the cache
+    ///
+    /// The settings only apply to a reader being opened, see `clear()`
+    pub fn reader(&self, file: &SSTableFile, options: &TableReaderOptions) -> Result<Arc<TableReader>> {
+        if let Some(reader) = self.readers.get(&file.number()) {
+            return Ok(reader);
+        }
+        let reader = Arc::new(TableReader::open(file, self.block_cache.clone(), options)?);
+        self.readers.insert(file.number(), reader.clone(), 1);
+        Ok(reader)
+    }
+
+    /// Closes the reader of the file and drops its blocks, pinned ones included
+    pub fn evict(&self, number: u64) {
+        self.readers.erase(&number);
+        self.block_cache.retain(|(block_number, _)| *block_number != number);
+    }
+
+    /// Closes every reader, the next reads open them with the settings of the time
+    pub fn clear(&self) {
+        self.readers.retain(|_| false);
+    }
+
+    /// Hits and misses of the open readers, the usage is the number of open files
+    pub fn stats(&self) -> CacheStats {
+        self.readers.stats()
+    }
+
+    pub fn block_cache_stats(&self) -> CacheStats {
+        self.block_cache.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{
+        comparator::comparator::BytewiseComparator,
+        ss_table::ss_table::{SSTable, SSTableEntry, TableOptions},
+    };
+    use std::fs;
+
+    #[test]
+    fn least_recently_used_reader_closed() {
+        let dir = std::env::temp_dir().join(format!("simpledb-table-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let table_cache = Arc::new(TableCache::new(2, 1024 * 1024));
+        let files: Vec<SSTableFile> = (0..3u8)
+            .map(|i| {
+                let file_name = SSTable::file_name(&dir, i as u128);
+                let entries = vec![SSTableEntry::new(vec![i], vec![i], 1, false)];
+                SSTable::new(entries, vec![]).write(&file_name, &BytewiseComparator, &TableOptions::default()).unwrap();
+                SSTableFile::new(file_name, table_cache.clone())
+            })
+            .collect();
+
+        let options = TableReaderOptions { verify_checksums: true, ..TableReaderOptions::default() };
+        let first = table_cache.reader(&files[0], &options).unwrap();
+        assert!(Arc::ptr_eq(&first, &table_cache.reader(&files[0], &options).unwrap()));
+        assert_eq!(first.get(&BytewiseComparator, &[0], u128::MAX).unwrap().unwrap().value, vec![0]);
+        table_cache.reader(&files[1], &options).unwrap();
+        table_cache.reader(&files[2], &options).unwrap();
+        let stats = table_cache.stats();
+        // which ones depends on the shards they hash to
+        assert!(stats.usage <= 2 && stats.evictions > 0);
+        assert_eq!(stats.usage as u64 + stats.evictions, 3);
+        assert_eq!((stats.hits, stats.misses), (1, 3));
+
+        // dropping a file closes its reader and drops its blocks
+        let block_usage = table_cache.block_cache_stats().usage;
+        drop(files);
+        assert_eq!(table_cache.stats().usage, 0);
+        assert!(block_usage > 0);
+        assert_eq!(table_cache.block_cache_stats().usage, 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
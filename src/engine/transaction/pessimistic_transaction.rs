@@ -0,0 +1,266 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    mem,
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::engine::{
+    engine::Engine,
+    lock_manager::lock_manager::LockManager,
+    snapshot::snapshot::Snapshot,
+    write_batch::write_batch::WriteBatch,
+};
+use crate::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IsolationLevel {
+    /// Every read sees the latest committed value
+    ReadCommitted,
+    /// Reads see the database as it was at the start,
+    /// the commit fails if a written key was changed by someone else after the start
+    Snapshot,
+}
+
+/// Lock-based transaction, see `Engine::begin_pessimistic_transaction()`
+///
+/// Keys are locked before they are written, or read with `get_for_update()`,
+/// and stay locked till the transaction is committed or dropped
+/// Locking doesn't need the engine, so threads sharing one engine
+/// can wait for a lock without holding the engine
+#[derive(Debug)]
+pub struct PessimisticTransaction {
+    id: u64,
+    locks: Arc<LockManager>,
+    lock_timeout: Duration,
+    isolation: IsolationLevel,
+    // only taken for the snapshot isolation
+    snapshot: Option<Snapshot>,
+    // None is a delete, a later write of the same key replaces the earlier one
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    locked_keys: BTreeSet<Vec<u8>>,
+}
+
+impl PessimisticTransaction {
+    pub fn new(
+        locks: Arc<LockManager>,
+        lock_timeout: Duration,
+        isolation: IsolationLevel,
+        snapshot: Option<Snapshot>
+    ) -> Self {
+        Self {
+            id: locks.new_transaction_id(),
+            locks,
+            lock_timeout,
+            isolation,
+            snapshot,
+            writes: BTreeMap::new(),
+            locked_keys: BTreeSet::new(),
+        }
+    }
+
+    /// Locks the key till the end of the transaction, fails with `Busy::Lock`
+    pub fn lock(&mut self, key: &[u8]) -> Result<()> {
+        if !self.locked_keys.contains(key) {
+            self.locks.lock(self.id, key, self.lock_timeout)?;
+            self.locked_keys.insert(key.to_vec());
+        }
+        Ok(())
+    }
+
+    /// Sees the transaction's own writes, else what the isolation level allows
+    pub fn get(&self, engine: &Engine, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.writes.get(&key) {
+            return Ok(value.clone());
+        }
+        match &self.snapshot {
+            Some(snapshot) => engine.get_at(snapshot, key),
+            None => engine.get(key),
+        }
+    }
+
+    /// Locks the key and reads it, nobody else can change it till the end of the transaction
+    pub fn get_for_update(&mut self, engine: &Engine, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.lock(&key)?;
+        self.get(engine, key)
+    }
+
+    pub fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.lock(&key)?;
+        self.writes.insert(key, Some(value));
+        Ok(())
+    }
+
+    pub fn delete(&mut self, key: Vec<u8>) -> Result<()> {
+        self.lock(&key)?;
+        self.writes.insert(key, None);
+        Ok(())
+    }
+
+    pub fn isolation(&self) -> IsolationLevel {
+        self.isolation
+    }
+
+    /// Timestamp of the last entry a snapshot isolated transaction sees
+    pub fn start_sequence(&self) -> Option<u128> {
+        self.snapshot.as_ref().map(Snapshot::sequence)
+    }
+
+    pub fn written_keys(&self) -> impl Iterator<Item = &Vec<u8>> {
+        self.writes.keys()
+    }
+
+    /// Buffered writes as a batch, the locks are released when the transaction is dropped
+    pub fn take_write_batch(&mut self) -> WriteBatch {
+        let mut batch = WriteBatch::new();
+        for (key, value) in mem::take(&mut self.writes) {
+            match value {
+                Some(value) => batch.set(key, value),
+                None => batch.delete(key),
+            };
+        }
+        batch
+    }
+
+    /// Drops the buffered writes and releases the locks
+    pub fn rollback(self) {}
+}
+
+impl Drop for PessimisticTransaction {
+    fn drop(&mut self) {
+        self.locks.unlock_all(self.id, &self.locked_keys);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::{db::db::Db, lock_manager::lock_manager::LockError},
+        error::error::Busy,
+        Error,
+    };
+    use std::{sync::Barrier, thread};
+
+    fn test_db(name: &str) -> Db {
+        let dir = std::env::temp_dir().join(format!("simpledb-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        Db::open(dir.to_str().unwrap().to_owned(), 1024, 2).unwrap()
+    }
+
+    #[test]
+    fn concurrent_increments() {
+        let db = test_db("pessimistic-concurrent-increments");
+        db.set(b"counter".to_vec(), 0u32.to_le_bytes().to_vec()).unwrap();
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    for _ in 0..50 {
+                        let mut txn = db
+                            .begin_pessimistic_transaction(IsolationLevel::ReadCommitted, Duration::from_secs(10));
+                        // wait for the key before reading it
+                        txn.lock(b"counter").unwrap();
+
+                        let value = txn.get(&db, b"counter".to_vec()).unwrap().unwrap();
+                        let counter = u32::from_le_bytes(value.try_into().unwrap());
+                        txn.set(b"counter".to_vec(), (counter + 1).to_le_bytes().to_vec()).unwrap();
+                        db.commit_pessimistic(txn).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let value = db.get(b"counter".to_vec()).unwrap().unwrap();
+        assert_eq!(u32::from_le_bytes(value.try_into().unwrap()), 400);
+    }
+
+    #[test]
+    fn deadlock_is_detected() {
+        let db = test_db("pessimistic-deadlock-is-detected");
+        let barrier = Arc::new(Barrier::new(2));
+
+        let threads: Vec<_> = [(b"a", b"b"), (b"b", b"a")]
+            .into_iter()
+            .map(|(first, second)| {
+                let db = db.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    let mut txn = db
+                        .begin_pessimistic_transaction(IsolationLevel::ReadCommitted, Duration::from_secs(10));
+                    txn.set(first.to_vec(), vec![1]).unwrap();
+                    barrier.wait();
+                    match txn.set(second.to_vec(), vec![1]) {
+                        Ok(()) => {
+                            db.commit_pessimistic(txn).unwrap();
+                            true
+                        }
+                        Err(err) => {
+                            assert!(matches!(err, Error::Busy(Busy::Lock(LockError::Deadlock { .. }))));
+                            // releases the locks, the other transaction goes on
+                            txn.rollback();
+                            false
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        let committed: Vec<bool> = threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+        assert_eq!(committed.iter().filter(|committed| **committed).count(), 1);
+    }
+
+    #[test]
+    fn lock_timeout() {
+        let db = test_db("pessimistic-lock-timeout");
+        let mut first = db.begin_pessimistic_transaction(IsolationLevel::ReadCommitted, Duration::from_secs(10));
+        let mut second = db.begin_pessimistic_transaction(IsolationLevel::ReadCommitted, Duration::from_millis(20));
+        first.get_for_update(&db, vec![1]).unwrap();
+
+        let err = second.delete(vec![1]).unwrap_err();
+        assert!(matches!(err, Error::Busy(Busy::Lock(LockError::Timeout { key })) if key == vec![1]));
+        drop(first);
+        second.delete(vec![1]).unwrap();
+    }
+
+    #[test]
+    fn isolation_levels() {
+        let db = test_db("pessimistic-isolation-levels");
+        db.set(vec![1], vec![1]).unwrap();
+
+        let mut read_committed = db.begin_pessimistic_transaction(IsolationLevel::ReadCommitted, Duration::ZERO);
+        let mut snapshot = db.begin_pessimistic_transaction(IsolationLevel::Snapshot, Duration::ZERO);
+        db.set(vec![1], vec![2]).unwrap();
+
+        assert_eq!(read_committed.get(&db, vec![1]).unwrap(), Some(vec![2]));
+        assert_eq!(snapshot.get(&db, vec![1]).unwrap(), Some(vec![1]));
+
+        // the key changed after the snapshot was taken
+        snapshot.set(vec![1], vec![3]).unwrap();
+        let err = db.commit_pessimistic(snapshot).unwrap_err();
+        assert!(matches!(err, Error::Busy(Busy::TransactionConflict(conflict)) if conflict.key == vec![1]));
+
+        read_committed.set(vec![1], vec![4]).unwrap();
+        db.commit_pessimistic(read_committed).unwrap();
+        assert_eq!(db.get(vec![1]).unwrap(), Some(vec![4]));
+    }
+
+    #[test]
+    fn plain_write_after_lock_conflicts() {
+        let db = test_db("pessimistic-plain-write-after-lock");
+        db.set(vec![1], vec![1]).unwrap();
+
+        // plain writes don't wait for the key locks, the commit has to catch them
+        let mut snapshot = db.begin_pessimistic_transaction(IsolationLevel::Snapshot, Duration::ZERO);
+        snapshot.set(vec![1], vec![2]).unwrap();
+        db.set(vec![1], vec![3]).unwrap();
+
+        let err = db.commit_pessimistic(snapshot).unwrap_err();
+        assert!(matches!(err, Error::Busy(Busy::TransactionConflict(conflict)) if conflict.key == vec![1]));
+        assert_eq!(db.get(vec![1]).unwrap(), Some(vec![3]));
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use crate::engine::{
+    engine::Engine,
+    snapshot::snapshot::Snapshot,
+    write_batch::write_batch::WriteBatch,
+};
+use crate::Result;
+
+/// Read-modify-write transaction, see `Engine::begin_transaction()`
+///
+/// Reads go through the snapshot taken at the start, writes are buffered
+/// and applied as one batch by `Engine::commit()`, which fails with `Busy::TransactionConflict`
+/// if a key the transaction read was written by someone else in the meantime
+#[derive(Debug)]
+pub struct Transaction {
+    snapshot: Snapshot,
+    // None is a delete, a later write of the same key replaces the earlier one
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    reads: BTreeSet<Vec<u8>>,
+}
+
+impl Transaction {
+    pub fn new(snapshot: Snapshot) -> Self {
+        Self {
+            snapshot,
+            writes: BTreeMap::new(),
+            reads: BTreeSet::new(),
+        }
+    }
+
+    /// Sees the transaction's own writes, else the database as it was at the start
+    pub fn get(&mut self, engine: &Engine, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.writes.get(&key) {
+            return Ok(value.clone());
+        }
+        let value = engine.get_at(&self.snapshot, key.clone())?;
+        self.reads.insert(key);
+        Ok(value)
+    }
+
+    pub fn set(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.writes.insert(key, Some(value));
+    }
+
+    pub fn delete(&mut self, key: Vec<u8>) {
+        self.writes.insert(key, None);
+    }
+
+    /// Timestamp of the last entry the transaction sees
+    pub fn start_sequence(&self) -> u128 {
+        self.snapshot.sequence()
+    }
+
+    pub fn read_keys(&self) -> &BTreeSet<Vec<u8>> {
+        &self.reads
+    }
+
+    /// Buffered writes as a batch, the snapshot is released
+    pub fn into_write_batch(self) -> WriteBatch {
+        let mut batch = WriteBatch::new();
+        for (key, value) in self.writes {
+            match value {
+                Some(value) => batch.set(key, value),
+                None => batch.delete(key),
+            };
+        }
+        batch
+    }
+}
+
+/// Commit failed because a key read by the transaction changed after it started
+///
+/// Nothing was written, the transaction can be retried from the start
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionConflict {
+    pub key: Vec<u8>,
+}
+
+impl fmt::Display for TransactionConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "transaction conflict on key {:?}", self.key)
+    }
+}
+
+impl std::error::Error for TransactionConflict {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::error::Busy, Error};
+
+    fn test_engine(name: &str) -> Engine {
+        let dir = std::env::temp_dir().join(format!("simpledb-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        Engine::new(dir.to_str().unwrap().to_owned(), 1024, 2).unwrap()
+    }
+
+    #[test]
+    fn reads_own_writes() {
+        let engine = test_engine("txn-reads-own-writes");
+        engine.set(vec![1], vec![1]).unwrap();
+
+        let mut txn = engine.begin_transaction();
+        assert_eq!(txn.get(&engine, vec![1]).unwrap(), Some(vec![1]));
+        txn.set(vec![1], vec![2]);
+        txn.delete(vec![3]);
+        assert_eq!(txn.get(&engine, vec![1]).unwrap(), Some(vec![2]));
+        assert_eq!(txn.get(&engine, vec![3]).unwrap(), None);
+        // nothing is visible before the commit
+        assert_eq!(engine.get(vec![1]).unwrap(), Some(vec![1]));
+
+        engine.commit(txn).unwrap();
+        assert_eq!(engine.get(vec![1]).unwrap(), Some(vec![2]));
+    }
+
+    #[test]
+    fn conflicting_commit_fails() {
+        let engine = test_engine("txn-conflicting-commit-fails");
+        engine.set(vec![1], vec![0]).unwrap();
+
+        let mut first = engine.begin_transaction();
+        let mut second = engine.begin_transaction();
+        let value = first.get(&engine, vec![1]).unwrap().unwrap();
+        first.set(vec![1], vec![value[0] + 1]);
+        let value = second.get(&engine, vec![1]).unwrap().unwrap();
+        second.set(vec![1], vec![value[0] + 1]);
+
+        engine.commit(first).unwrap();
+        // flushed writes are seen as well
+        engine.flush().unwrap();
+        let err = engine.commit(second).unwrap_err();
+        assert!(matches!(err, Error::Busy(Busy::TransactionConflict(conflict)) if conflict.key == vec![1]));
+        assert_eq!(engine.get(vec![1]).unwrap(), Some(vec![1]));
+    }
+
+    #[test]
+    fn blind_writes_never_conflict() {
+        let engine = test_engine("txn-blind-writes-never-conflict");
+        let mut txn = engine.begin_transaction();
+        txn.get(&engine, vec![2]).unwrap();
+        txn.set(vec![1], vec![1]);
+        engine.set(vec![1], vec![0]).unwrap();
+
+        engine.commit(txn).unwrap();
+        assert_eq!(engine.get(vec![1]).unwrap(), Some(vec![1]));
+    }
+}
@@ -0,0 +1,194 @@
+use crate::{
+    engine::{checksum::checksum::checksum, write_batch::write_batch::WriteBatch},
+    error::error::Corruption,
+    Result,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// One record per `Engine::write`,
+/// the entries of the batch get the timestamps `timestamp..timestamp + batch.len()`
+#[derive(Serialize,Deserialize,Debug,PartialEq)]
+pub struct WalRecord {
+    pub timestamp: u128,
+    pub batch: WriteBatch,
+}
+
+/// Write ahead log of the current memtable
+///
+/// Every record is written as its length(u64, little endian), the CRC32C of the length(u32, little endian)
+/// and the CRC32C of the record(u32, little endian) followed by the bincode encoded record,
+/// a crash in the middle of a write leaves a torn record at the end which is ignored on replay
+#[derive(Debug)]
+pub struct Wal {
+    file_name: PathBuf,
+    file: BufWriter<File>,
+}
+
+impl Wal {
+    /// Name of the log file, timestamp keeps the files in creation order
+    pub fn file_name(dir: &Path, timestamp: u128) -> PathBuf {
+        dir.join(timestamp.to_string() + ".wal")
+    }
+
+    /// Timestamp of the log file, None if it is not a log file
+    pub fn file_timestamp(file_name: &Path) -> Option<u128> {
+        if file_name.extension()? != "wal" {
+            return None;
+        }
+        file_name.file_stem()?.to_str()?.parse().ok()
+    }
+
+    pub fn create(dir: &Path, timestamp: u128) -> Result<Self> {
+        let file_name = Self::file_name(dir, timestamp);
+        let file = OpenOptions::new().append(true).create(true).open(&file_name)?;
+        Ok(Self {
+            file_name,
+            file: BufWriter::new(file),
+        })
+    }
+
+    /// Appends the record and hands it over to the OS,
+    /// call `sync()` to make sure it reached the disk
+    pub fn append(&mut self, record: &WalRecord) -> Result<()> {
+        self.append_all(std::slice::from_ref(record))
+    }
+
+    /// Appends the records of a group commit with a single write to the OS
+    pub fn append_all(&mut self, records: &[WalRecord]) -> Result<()> {
+        for record in records {
+            let encoded_record = bincode::serialize(record)?;
+            let length = (encoded_record.len() as u64).to_le_bytes();
+            self.file.write_all(&length)?;
+            self.file.write_all(&checksum(&length).to_le_bytes())?;
+            self.file.write_all(&checksum(&encoded_record).to_le_bytes())?;
+            self.file.write_all(&encoded_record)?;
+        }
+        self.file.flush()?;
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_all()?;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.file_name
+    }
+
+    /// Reads back every complete record of the log, in the order they were appended
+    ///
+    /// A record failing its checksum is a torn write if it is the last one, otherwise it fails with `Corruption`
+    /// A record cut short is a torn write only if its length passes the checksum,
+    /// a damaged length would make every record after it look like the torn end of the log
+    pub fn replay(file_name: &Path) -> Result<Vec<WalRecord>> {
+        let content = fs::read(file_name)?;
+        let mut records = vec![];
+        let mut offset = 0;
+
+        while offset + 16 <= content.len() {
+            let length_bytes = &content[offset..offset + 8];
+            let length_crc = u32::from_le_bytes(content[offset + 8..offset + 12].try_into().unwrap());
+            if checksum(length_bytes) != length_crc {
+                return Err(Corruption::new(file_name, offset as u64, "log record length checksum mismatch").into());
+            }
+            let length = u64::from_le_bytes(length_bytes.try_into().unwrap()) as usize;
+            let crc = u32::from_le_bytes(content[offset + 12..offset + 16].try_into().unwrap());
+            let start = offset + 16;
+            // torn write at the end of the log
+            if content.len() - start < length {
+                break;
+            }
+            let end = start + length;
+            let record = &content[start..end];
+            if checksum(record) != crc {
+                if end == content.len() {
+                    break;
+                }
+                return Err(Corruption::new(file_name, offset as u64, "log record checksum mismatch").into());
+            }
+            let record = bincode::deserialize(record)
+                .map_err(|err| Corruption::new(file_name, offset as u64, err.to_string()))?;
+            records.push(record);
+            offset = end;
+        }
+
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    #[test]
+    fn append_and_replay() {
+        let dir = std::env::temp_dir().join(format!("simpledb-wal-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut wal = Wal::create(&dir, 3).unwrap();
+        let mut records = vec![];
+        for i in 0..5u8 {
+            let mut batch = WriteBatch::new();
+            batch.set(vec![i], vec![i + 1]).delete(vec![i + 2]);
+            let record = WalRecord { timestamp: i as u128 * 2, batch };
+            wal.append(&record).unwrap();
+            records.push(record);
+        }
+        wal.sync().unwrap();
+        assert_eq!(Wal::file_timestamp(wal.path()), Some(3));
+        assert_eq!(Wal::replay(wal.path()).unwrap(), records);
+
+        // crash in the middle of the last record
+        let file_name = wal.path().to_path_buf();
+        drop(wal);
+        let content = fs::read(&file_name).unwrap();
+        fs::write(&file_name, &content[..content.len() - 3]).unwrap();
+        records.pop();
+        assert_eq!(Wal::replay(&file_name).unwrap(), records);
+
+        // a flipped bit in the second record, the ones after it can't be trusted
+        let mut content = fs::read(&file_name).unwrap();
+        let second = 16 + u64::from_le_bytes(content[..8].try_into().unwrap()) as usize;
+        content[second + 18] ^= 1;
+        fs::write(&file_name, &content).unwrap();
+        let err = Wal::replay(&file_name).unwrap_err();
+        assert!(matches!(err, Error::Corruption(corruption) if corruption.offset == second as u64));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn damaged_length_is_not_a_torn_write() {
+        let dir = std::env::temp_dir().join(format!("simpledb-wal-length-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut wal = Wal::create(&dir, 1).unwrap();
+        for i in 0..3u8 {
+            let mut batch = WriteBatch::new();
+            batch.set(vec![i], vec![i; 32]);
+            wal.append(&WalRecord { timestamp: i as u128, batch }).unwrap();
+        }
+        let file_name = wal.path().to_path_buf();
+        drop(wal);
+
+        // the second record now claims to run past the end of the log
+        let mut content = fs::read(&file_name).unwrap();
+        let second = 16 + u64::from_le_bytes(content[..8].try_into().unwrap()) as usize;
+        content[second + 2] ^= 1;
+        fs::write(&file_name, &content).unwrap();
+        let err = Wal::replay(&file_name).unwrap_err();
+        assert!(matches!(err, Error::Corruption(corruption) if corruption.offset == second as u64));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+use crate::engine::column_family::column_family::ColumnFamily;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize,Deserialize,Debug,Clone,PartialEq)]
+pub enum BatchEntry {
+    Set { key: Vec<u8>, value: Vec<u8> },
+    Delete { key: Vec<u8> },
+    Merge { key: Vec<u8>, operand: Vec<u8> },
+    // expires_at is in micro seconds since the unix epoch
+    SetWithTtl { key: Vec<u8>, value: Vec<u8>, expires_at: u128 },
+    // every key in [start, end)
+    DeleteRange { start: Vec<u8>, end: Vec<u8> },
+}
+
+impl BatchEntry {
+    /// Start of the range for `DeleteRange`
+    pub fn key(&self) -> &[u8] {
+        match self {
+            BatchEntry::Set { key, .. } => key,
+            BatchEntry::Delete { key } => key,
+            BatchEntry::Merge { key, .. } => key,
+            BatchEntry::SetWithTtl { key, .. } => key,
+            BatchEntry::DeleteRange { start, .. } => start,
+        }
+    }
+}
+
+/// Group of sets and deletes applied atomically by `Engine::write`
+///
+/// Entries are applied in the order they were added,
+/// so a later entry for the same key wins
+/// Every entry goes to a column family, the default one unless added with a `_cf` method
+#[derive(Serialize,Deserialize,Debug,Clone,Default,PartialEq)]
+pub struct WriteBatch {
+    // id of the column family along with the entry
+    entries: Vec<(u32, BatchEntry)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self {
+            entries: vec![]
+        }
+    }
+
+    pub fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> &mut Self {
+        self.entries.push((0, BatchEntry::Set { key, value }));
+        self
+    }
+
+    pub fn set_cf(&mut self, column_family: &ColumnFamily, key: Vec<u8>, value: Vec<u8>) -> &mut Self {
+        self.entries.push((column_family.id(), BatchEntry::Set { key, value }));
+        self
+    }
+
+    /// The key reads as missing once `ttl` passed, counted from now
+    pub fn set_with_ttl(&mut self, key: Vec<u8>, value: Vec<u8>, ttl: Duration) -> &mut Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let expires_at = now.saturating_add(ttl).as_micros();
+        self.entries.push((0, BatchEntry::SetWithTtl { key, value, expires_at }));
+        self
+    }
+
+    pub fn delete(&mut self, key: Vec<u8>) -> &mut Self {
+        self.entries.push((0, BatchEntry::Delete { key }));
+        self
+    }
+
+    pub fn delete_cf(&mut self, column_family: &ColumnFamily, key: Vec<u8>) -> &mut Self {
+        self.entries.push((column_family.id(), BatchEntry::Delete { key }));
+        self
+    }
+
+    /// Deletes every key in `[start, end)` written before, the write fails if `start` doesn't sort before `end`
+    pub fn delete_range(&mut self, start: Vec<u8>, end: Vec<u8>) -> &mut Self {
+        self.entries.push((0, BatchEntry::DeleteRange { start, end }));
+        self
+    }
+
+    pub fn delete_range_cf(&mut self, column_family: &ColumnFamily, start: Vec<u8>, end: Vec<u8>) -> &mut Self {
+        self.entries.push((column_family.id(), BatchEntry::DeleteRange { start, end }));
+        self
+    }
+
+    /// Operand for the merge operator of the engine, see `Engine::merge()`
+    pub fn merge(&mut self, key: Vec<u8>, operand: Vec<u8>) -> &mut Self {
+        self.entries.push((0, BatchEntry::Merge { key, operand }));
+        self
+    }
+
+    pub fn merge_cf(&mut self, column_family: &ColumnFamily, key: Vec<u8>, operand: Vec<u8>) -> &mut Self {
+        self.entries.push((column_family.id(), BatchEntry::Merge { key, operand }));
+        self
+    }
+
+    /// Entries along with the id of their column family
+    pub fn entries(&self) -> &[(u32, BatchEntry)] {
+        &self.entries
+    }
+
+    pub fn into_entries(self) -> Vec<(u32, BatchEntry)> {
+        self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::engine::{lock_manager::lock_manager::LockError, transaction::transaction::TransactionConflict};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Every failure of the database, one variant per class a caller may react to
+///
+/// Cloneable so the batches of a group commit all get the error of the group
+#[derive(Debug, Clone)]
+pub enum Error {
+    /// A column family or another named thing doesn't exist
+    NotFound(String),
+    /// A file holds something other than what was written
+    Corruption(Corruption),
+    Io(Arc<io::Error>),
+    /// The call doesn't make sense with these arguments or settings, retrying won't help
+    InvalidArgument(String),
+    /// Lost a race with another user of the database, retrying may help
+    Busy(Busy),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound(what) => write!(f, "not found: {}", what),
+            Error::Corruption(corruption) => write!(f, "{}", corruption),
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::InvalidArgument(reason) => write!(f, "invalid argument: {}", reason),
+            Error::Busy(busy) => write!(f, "busy: {}", busy),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Corruption(corruption) => Some(corruption),
+            Error::Io(err) => Some(err.as_ref()),
+            Error::Busy(busy) => Some(busy),
+            Error::NotFound(_) | Error::InvalidArgument(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(Arc::new(err))
+    }
+}
+
+/// Only for encoding, a table or log which doesn't decode is reported as `Corruption` along with the file
+impl From<bincode::Error> for Error {
+    fn from(err: bincode::Error) -> Self {
+        match *err {
+            bincode::ErrorKind::Io(err) => err.into(),
+            err => Error::InvalidArgument(err.to_string()),
+        }
+    }
+}
+
+impl From<Corruption> for Error {
+    fn from(corruption: Corruption) -> Self {
+        Error::Corruption(corruption)
+    }
+}
+
+impl From<Busy> for Error {
+    fn from(busy: Busy) -> Self {
+        Error::Busy(busy)
+    }
+}
+
+impl From<TransactionConflict> for Error {
+    fn from(conflict: TransactionConflict) -> Self {
+        Error::Busy(Busy::TransactionConflict(conflict))
+    }
+}
+
+impl From<LockError> for Error {
+    fn from(err: LockError) -> Self {
+        Error::Busy(Busy::Lock(err))
+    }
+}
+
+/// A file holds something other than what was written, a flipped bit or a truncated table
+///
+/// Reads of the data in it fail, nothing is guessed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Corruption {
+    pub file_name: PathBuf,
+    // of the block or record that failed
+    pub offset: u64,
+    pub reason: String,
+}
+
+impl Corruption {
+    pub fn new(file_name: &Path, offset: u64, reason: impl Into<String>) -> Self {
+        Self {
+            file_name: file_name.to_path_buf(),
+            offset,
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "corruption in {} at offset {}: {}", self.file_name.display(), self.offset, self.reason)
+    }
+}
+
+impl std::error::Error for Corruption {}
+
+/// Why the database couldn't serve the call right now
+#[derive(Debug, Clone, PartialEq)]
+pub enum Busy {
+    /// Nothing was written, the transaction can be retried from the start
+    TransactionConflict(TransactionConflict),
+    /// The pessimistic transaction should be rolled back
+    Lock(LockError),
+    /// Other handles still use the database
+    InUse(String),
+    /// A background thread has stopped, nothing more is accepted
+    Stopped(String),
+}
+
+impl fmt::Display for Busy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Busy::TransactionConflict(conflict) => write!(f, "{}", conflict),
+            Busy::Lock(err) => write!(f, "{}", err),
+            Busy::InUse(what) | Busy::Stopped(what) => write!(f, "{}", what),
+        }
+    }
+}
+
+impl std::error::Error for Busy {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Busy::TransactionConflict(conflict) => Some(conflict),
+            Busy::Lock(err) => Some(err),
+            Busy::InUse(_) | Busy::Stopped(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn sources_are_chained() {
+        let err: Error = io::Error::new(io::ErrorKind::NotFound, "gone").into();
+        assert_eq!(err.to_string(), "I/O error: gone");
+        assert_eq!(err.source().unwrap().to_string(), "gone");
+
+        let err: Error = TransactionConflict { key: vec![1] }.into();
+        assert!(matches!(err, Error::Busy(Busy::TransactionConflict(_))));
+        let conflict = err.source().unwrap().source().unwrap();
+        assert_eq!(conflict.downcast_ref::<TransactionConflict>(), Some(&TransactionConflict { key: vec![1] }));
+    }
+}
@@ -1 +1,8 @@
-pub mod engine;
\ No newline at end of file
+#![allow(non_snake_case)]
+// every module is laid out as `foo/mod.rs` declaring `foo/foo.rs`
+#![allow(clippy::module_inception)]
+
+pub mod engine;
+pub mod error;
+
+pub use error::error::{Error, Result};